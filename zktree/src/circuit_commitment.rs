@@ -0,0 +1,260 @@
+//! Canonical definition of the circuit commitment of a zkTree, i.e. of the circuit hash exposed by
+//! its root, shared by the leaf and node proofs and by the native verifier. A leaf commits to the
+//! user circuit it verifies with `H::hash_or_noop(leaf_verifier_circuit_digest ||
+//! user_circuit_hash)`, padding leaves committing to a zero user circuit hash, and a node commits
+//! to its children with the node verifier circuit digest inserted in the middle of their circuit
+//! hashes, i.e. `H::hash_or_noop(left_circuit_hash || node_verifier_circuit_digest ||
+//! right_circuit_hash)` for binary trees.
+use std::iter;
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::{HashOut, HashOutTarget, RichField},
+    plonk::{circuit_builder::CircuitBuilder, config::AlgebraicHasher},
+};
+
+use crate::{components::node_circuit::check_arity, error::ZkTreeError};
+
+/// Computes the circuit hash of a leaf from the verifier circuit digest of the leaf circuit and
+/// the circuit hash of the user proof it verifies.
+pub fn leaf_circuit_hash<F, H>(
+    leaf_verifier_circuit_digest: HashOut<F>,
+    user_circuit_hash: HashOut<F>,
+) -> HashOut<F>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    H::hash_or_noop(
+        &[
+            leaf_verifier_circuit_digest.elements,
+            user_circuit_hash.elements,
+        ]
+        .concat(),
+    )
+}
+
+/// The circuit hash of a padding leaf of the leaf circuit whose verifier circuit digest is
+/// `leaf_verifier_circuit_digest`, i.e. the hash of a zero user circuit hash.
+pub fn padding_circuit_hash<F, H>(leaf_verifier_circuit_digest: HashOut<F>) -> HashOut<F>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    leaf_circuit_hash::<F, H>(leaf_verifier_circuit_digest, HashOut::ZERO)
+}
+
+/// Computes the circuit hash of a node from the circuit hashes of its children and the node
+/// verifier circuit digest, which is inserted in the middle of the children circuit hashes. For a
+/// binary node, this is `H::hash_or_noop(left_circuit_hash || verifier_circuit_digest ||
+/// right_circuit_hash)`.
+pub fn node_circuit_hash<F, H>(
+    children_circuit_hashes: &[HashOut<F>],
+    verifier_circuit_digest: HashOut<F>,
+) -> HashOut<F>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    let (left_circuit_hashes, right_circuit_hashes) =
+        children_circuit_hashes.split_at(children_circuit_hashes.len() / 2);
+    H::hash_or_noop(
+        &left_circuit_hashes
+            .iter()
+            .chain(iter::once(&verifier_circuit_digest))
+            .chain(right_circuit_hashes)
+            .flat_map(|circuit_hash| circuit_hash.elements)
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Computes the circuit commitment of a binary zkTree, i.e. its root circuit hash, see
+/// [`circuit_commitment_with_arity`].
+pub fn circuit_commitment<F, H>(
+    user_circuit_hashes: &[HashOut<F>],
+    leaf_verifier_circuit_digest: HashOut<F>,
+    node_verifier_circuit_digests: &[HashOut<F>],
+) -> Result<HashOut<F>, ZkTreeError>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    circuit_commitment_with_arity::<F, H>(
+        user_circuit_hashes,
+        leaf_verifier_circuit_digest,
+        node_verifier_circuit_digests,
+        2,
+    )
+}
+
+/// Computes the circuit commitment of a zkTree whose nodes aggregate `arity` children, i.e. its
+/// root circuit hash, from the circuit hashes of its user proofs and the verifier circuit digests
+/// of its leaf and node circuits. The leaves after the user proofs are padding leaves.
+///
+/// # Arguments
+///
+/// * `user_circuit_hashes`: The circuit hashes of the user proofs, in leaf order.
+/// * `leaf_verifier_circuit_digest`: The verifier circuit digest of the leaf circuit, shared by
+///   all the leaves.
+/// * `node_verifier_circuit_digests`: The verifier circuit digest of the node circuit of each
+///   level, from the level above the leaves to the root. The zkTree has `arity` to the power of
+///   their number of leaves.
+/// * `arity`: The number of children of every node, at least two.
+///
+/// # Returns
+///
+/// A `Result` containing the root circuit hash, or a `ZkTreeError` if the arity is invalid, or if
+/// the leaves cannot hold the user proofs.
+pub fn circuit_commitment_with_arity<F, H>(
+    user_circuit_hashes: &[HashOut<F>],
+    leaf_verifier_circuit_digest: HashOut<F>,
+    node_verifier_circuit_digests: &[HashOut<F>],
+    arity: usize,
+) -> Result<HashOut<F>, ZkTreeError>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    check_arity(arity)?;
+    if node_verifier_circuit_digests.is_empty() {
+        return Err(ZkTreeError::InvalidNumberOfLeaves {
+            num_leaves: 1,
+            arity,
+        });
+    }
+    let num_leaves = u32::try_from(node_verifier_circuit_digests.len())
+        .ok()
+        .and_then(|height| arity.checked_pow(height))
+        .unwrap_or(usize::MAX);
+    if user_circuit_hashes.is_empty() || user_circuit_hashes.len() > num_leaves {
+        return Err(ZkTreeError::InvalidNumberOfUserCircuits {
+            num_user_circuits: user_circuit_hashes.len(),
+            num_leaves,
+        });
+    }
+
+    let mut circuit_hashes = user_circuit_hashes
+        .iter()
+        .map(|user_circuit_hash| {
+            leaf_circuit_hash::<F, H>(leaf_verifier_circuit_digest, *user_circuit_hash)
+        })
+        .chain(iter::repeat(padding_circuit_hash::<F, H>(
+            leaf_verifier_circuit_digest,
+        )))
+        .take(num_leaves)
+        .collect::<Vec<_>>();
+    for node_verifier_circuit_digest in node_verifier_circuit_digests {
+        circuit_hashes = circuit_hashes
+            .chunks(arity)
+            .map(|children_circuit_hashes| {
+                node_circuit_hash::<F, H>(children_circuit_hashes, *node_verifier_circuit_digest)
+            })
+            .collect();
+    }
+    Ok(circuit_hashes[0])
+}
+
+/// In-circuit counterpart of [`node_circuit_hash`].
+pub(crate) fn node_circuit_hash_circuit<F, H, const D: usize>(
+    circuit_builder: &mut CircuitBuilder<F, D>,
+    children_circuit_hashes_targets: &[HashOutTarget],
+    verifier_circuit_digest_targets: HashOutTarget,
+) -> HashOutTarget
+where
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    let (left_circuit_hashes_targets, right_circuit_hashes_targets) =
+        children_circuit_hashes_targets.split_at(children_circuit_hashes_targets.len() / 2);
+    circuit_builder.hash_or_noop::<H>(
+        left_circuit_hashes_targets
+            .iter()
+            .chain(iter::once(&verifier_circuit_digest_targets))
+            .chain(right_circuit_hashes_targets)
+            .flat_map(|circuit_hash_targets| circuit_hash_targets.elements)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::{
+        field::{goldilocks_field::GoldilocksField, types::Sample},
+        hash::poseidon::PoseidonHash,
+    };
+
+    use super::*;
+
+    type F = GoldilocksField;
+    type H = PoseidonHash;
+
+    #[test]
+    fn test_circuit_commitment_pads_and_folds_levels() {
+        let user_circuit_hashes = (0..3).map(|_| HashOut::<F>::rand()).collect::<Vec<_>>();
+        let leaf_verifier_circuit_digest = HashOut::rand();
+        let node_verifier_circuit_digests = [HashOut::rand(), HashOut::rand()];
+
+        let leaf_circuit_hashes = [
+            leaf_circuit_hash::<F, H>(leaf_verifier_circuit_digest, user_circuit_hashes[0]),
+            leaf_circuit_hash::<F, H>(leaf_verifier_circuit_digest, user_circuit_hashes[1]),
+            leaf_circuit_hash::<F, H>(leaf_verifier_circuit_digest, user_circuit_hashes[2]),
+            padding_circuit_hash::<F, H>(leaf_verifier_circuit_digest),
+        ];
+        let expected = node_circuit_hash::<F, H>(
+            &[
+                node_circuit_hash::<F, H>(
+                    &leaf_circuit_hashes[..2],
+                    node_verifier_circuit_digests[0],
+                ),
+                node_circuit_hash::<F, H>(
+                    &leaf_circuit_hashes[2..],
+                    node_verifier_circuit_digests[0],
+                ),
+            ],
+            node_verifier_circuit_digests[1],
+        );
+
+        assert_eq!(
+            circuit_commitment::<F, H>(
+                &user_circuit_hashes,
+                leaf_verifier_circuit_digest,
+                &node_verifier_circuit_digests
+            )
+            .expect("Failed to compute circuit commitment"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_circuit_commitment_fails_with_invalid_number_of_user_circuits() {
+        let user_circuit_hashes = (0..3).map(|_| HashOut::<F>::rand()).collect::<Vec<_>>();
+        let leaf_verifier_circuit_digest = HashOut::rand();
+        let node_verifier_circuit_digests = [HashOut::rand(), HashOut::rand()];
+
+        assert!(circuit_commitment::<F, H>(
+            &[],
+            leaf_verifier_circuit_digest,
+            &node_verifier_circuit_digests
+        )
+        .is_err());
+        assert!(circuit_commitment::<F, H>(
+            &user_circuit_hashes,
+            leaf_verifier_circuit_digest,
+            &node_verifier_circuit_digests[..1]
+        )
+        .is_err());
+        assert!(circuit_commitment::<F, H>(
+            &user_circuit_hashes,
+            leaf_verifier_circuit_digest,
+            &[]
+        )
+        .is_err());
+        assert!(circuit_commitment_with_arity::<F, H>(
+            &user_circuit_hashes,
+            leaf_verifier_circuit_digest,
+            &node_verifier_circuit_digests,
+            1
+        )
+        .is_err());
+    }
+}
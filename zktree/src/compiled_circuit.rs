@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    plonk::{circuit_data::CircuitData, config::GenericConfig},
+};
+
+/// `CompiledCircuit` holds the outcome of compiling and building a circuit once, so that it can be
+/// reused to generate many proofs that share the same circuit. Proving with a `CompiledCircuit`
+/// only requires filling the witness and running the prover.
+///
+/// # Type Parameters
+///
+/// * `F`: The field type that must implement `RichField` and `Extendable<D>`.
+/// * `C`: The configuration of the circuit, satisfying `GenericConfig`.
+/// * `T`: The type of the input targets of the circuit.
+/// * `O`: The type of the output targets of the circuit.
+/// * `D`: The dimension of the field extension, specified as a compile-time constant.
+///
+/// # Fields
+///
+/// * `circuit_data`: The built circuit data, shared with every proof generated from it.
+/// * `targets`: The input targets of the circuit.
+/// * `out_targets`: The output targets of the circuit.
+pub struct CompiledCircuit<F, C, T, O, const D: usize>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+{
+    pub(crate) circuit_data: Arc<CircuitData<F, C, D>>,
+    pub(crate) targets: T,
+    pub(crate) out_targets: O,
+}
+
+impl<F, C, T, O, const D: usize> CompiledCircuit<F, C, T, O, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+{
+    /// Creates a new `CompiledCircuit` from the output of
+    /// [`CircuitCompiler::compile_and_build`](crate::traits::circuit_compiler::CircuitCompiler::compile_and_build).
+    ///
+    /// # Arguments
+    ///
+    /// * `circuit_data`: The built circuit data.
+    /// * `targets`: The input targets of the circuit.
+    /// * `out_targets`: The output targets of the circuit.
+    ///
+    /// # Returns
+    ///
+    /// A new `CompiledCircuit` instance.
+    pub fn new(circuit_data: CircuitData<F, C, D>, targets: T, out_targets: O) -> Self {
+        Self {
+            circuit_data: Arc::new(circuit_data),
+            targets,
+            out_targets,
+        }
+    }
+
+    /// Returns the circuit data of the compiled circuit.
+    pub fn circuit_data(&self) -> &CircuitData<F, C, D> {
+        &self.circuit_data
+    }
+}
@@ -0,0 +1,132 @@
+use plonky2::{
+    hash::{
+        hash_types::{HashOut, RichField, NUM_HASH_OUT_ELTS},
+        merkle_proofs::MerkleProof,
+        merkle_tree::MerkleTree,
+    },
+    plonk::config::AlgebraicHasher,
+};
+
+use crate::error::{hash_elements, ZkTreeError};
+
+/// Offset of the allow-list root in the public inputs of leaf and node proofs built with an
+/// allow-list, right after the input hash and the circuit hash.
+pub(crate) const ALLOW_LIST_ROOT_OFFSET: usize = 2 * NUM_HASH_OUT_ELTS;
+
+/// Number of public inputs of leaf and node proofs built without an allow-list.
+pub(crate) const NUM_PUBLIC_INPUTS: usize = ALLOW_LIST_ROOT_OFFSET;
+
+/// Number of public inputs of leaf and node proofs built with an allow-list.
+pub(crate) const NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST: usize =
+    ALLOW_LIST_ROOT_OFFSET + NUM_HASH_OUT_ELTS;
+
+/// Retrieves the allow-list root from the public inputs of a leaf or node proof, if the proof was
+/// built with an allow-list.
+pub(crate) fn allow_list_root_from_public_inputs<F: RichField>(
+    public_inputs: &[F],
+) -> Option<HashOut<F>> {
+    if public_inputs.len() != NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST {
+        return None;
+    }
+    Some(HashOut::from_partial(
+        &public_inputs[ALLOW_LIST_ROOT_OFFSET..NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST],
+    ))
+}
+
+/// `CircuitDigestAllowList` is a registry of approved user circuits, committed in a Merkle tree
+/// whose leaves are the user verifier circuit digests. Leaf circuits built with an allow-list
+/// prove that the digest of the verified user circuit is a member of the tree, and expose the
+/// tree root as a public input, which is propagated up to the zkTree root.
+///
+/// # Type Parameters
+///
+/// * `F`: The field type that must implement `RichField`.
+/// * `H`: The hasher type that implements `AlgebraicHasher<F>`, used to build the Merkle tree.
+///
+/// # Fields
+///
+/// * `circuit_digests`: The allowed user verifier circuit digests, in the order of the tree
+///   leaves.
+/// * `merkle_tree`: The Merkle tree committing to the allowed digests, padded to a power of two
+///   number of leaves with zero digests.
+pub struct CircuitDigestAllowList<F, H>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    circuit_digests: Vec<HashOut<F>>,
+    merkle_tree: MerkleTree<F, H>,
+}
+
+impl<F, H> CircuitDigestAllowList<F, H>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    /// Builds a new `CircuitDigestAllowList` from the allowed user verifier circuit digests.
+    ///
+    /// # Arguments
+    ///
+    /// * `circuit_digests`: The allowed user verifier circuit digests.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `CircuitDigestAllowList`, or a `ZkTreeError` if no digest is
+    /// provided.
+    pub fn new(circuit_digests: Vec<HashOut<F>>) -> Result<Self, ZkTreeError> {
+        if circuit_digests.is_empty() {
+            return Err(ZkTreeError::EmptyAllowList);
+        }
+        let merkle_tree_leaves = circuit_digests
+            .iter()
+            .map(|circuit_digest| circuit_digest.elements.to_vec())
+            .chain(std::iter::repeat(HashOut::<F>::ZERO.elements.to_vec()))
+            .take(circuit_digests.len().next_power_of_two())
+            .collect::<Vec<_>>();
+        let merkle_tree = MerkleTree::new(merkle_tree_leaves, 0);
+        Ok(Self {
+            circuit_digests,
+            merkle_tree,
+        })
+    }
+
+    /// Returns the root of the Merkle tree of allowed circuit digests.
+    pub fn root(&self) -> HashOut<F> {
+        self.merkle_tree.cap.0[0]
+    }
+
+    /// Returns the height of the Merkle tree of allowed circuit digests, i.e. the length of every
+    /// membership proof.
+    pub fn height(&self) -> usize {
+        self.circuit_digests.len().next_power_of_two().ilog2() as usize
+    }
+
+    /// Returns `true` if `circuit_digest` is allowed.
+    pub fn contains(&self, circuit_digest: &HashOut<F>) -> bool {
+        self.circuit_digests.contains(circuit_digest)
+    }
+
+    /// Generates a membership proof for `circuit_digest`.
+    ///
+    /// # Arguments
+    ///
+    /// * `circuit_digest`: The user verifier circuit digest to prove membership of.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the leaf index of `circuit_digest` together with its Merkle proof, or
+    /// a `ZkTreeError` if the digest is not allowed.
+    pub fn prove(
+        &self,
+        circuit_digest: &HashOut<F>,
+    ) -> Result<(usize, MerkleProof<F, H>), ZkTreeError> {
+        let index = self
+            .circuit_digests
+            .iter()
+            .position(|d| d == circuit_digest)
+            .ok_or_else(|| ZkTreeError::CircuitNotAllowed {
+                circuit_digest: hash_elements(*circuit_digest),
+            })?;
+        Ok((index, self.merkle_tree.prove(index)))
+    }
+}
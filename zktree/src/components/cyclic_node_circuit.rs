@@ -0,0 +1,737 @@
+use plonky2::{
+    field::extension::Extendable,
+    gates::noop::NoopGate,
+    hash::hash_types::{HashOut, HashOutTarget, RichField, NUM_HASH_OUT_ELTS},
+    iop::{
+        target::BoolTarget,
+        witness::{PartialWitness, WitnessWrite},
+    },
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{
+            CircuitConfig, CircuitData, CommonCircuitData, VerifierCircuitTarget,
+            VerifierOnlyCircuitData,
+        },
+        config::{AlgebraicHasher, GenericConfig, GenericHashOut},
+        proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget},
+    },
+    recursion::dummy_circuit::cyclic_base_proof,
+};
+
+use crate::{
+    circuit_commitment::{node_circuit_hash, node_circuit_hash_circuit},
+    compiled_circuit::CompiledCircuit,
+    components::{
+        allow_list::{
+            ALLOW_LIST_ROOT_OFFSET, NUM_PUBLIC_INPUTS, NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST,
+        },
+        cyclic_node_proof::CyclicNodeProof,
+        leaf_proof::LeafProof,
+        node_circuit::{check_arity, check_children_public_inputs, NodeCircuitOutTargets},
+    },
+    error::ZkTreeError,
+    input_commitment::{node_input_hash, node_input_hash_circuit},
+    proof_data::ProofData,
+    traits::proof::Proof,
+};
+
+/// The default number of rounds after which the common data of a `CyclicNodeCircuit` must have
+/// reached its fixed point. The degree of the circuit never decreases from a round to the next, as
+/// each round is padded to more than half the degree of the previous one, and the verifier of a
+/// circuit of a given degree fits in that very degree after a couple of rounds in practice: the
+/// default leaves a wide margin, and [`CyclicNodeCircuit::with_max_common_data_rounds`] overrides
+/// it.
+pub const MAX_COMMON_DATA_ROUNDS: usize = 8;
+
+/// Input targets of a `CyclicNodeCircuit`: whether the children are leaves, the leaf proofs and
+/// the cyclic node proofs of the children, only one of which is used per child, and the verifier
+/// data of the circuit itself, exposed as its last public inputs.
+pub type CyclicNodeCircuitTargets<const D: usize> = (
+    BoolTarget,
+    Vec<ProofWithPublicInputsTarget<D>>,
+    Vec<ProofWithPublicInputsTarget<D>>,
+    VerifierCircuitTarget,
+);
+
+/// The compiled circuit of a `CyclicNodeCircuit`, see [`CompiledCyclicNodeCircuit`] for the
+/// compiled circuit together with the proofs filling its unused child proof targets.
+pub type CompiledCyclicCircuit<F, C, const D: usize> =
+    CompiledCircuit<F, C, CyclicNodeCircuitTargets<D>, NodeCircuitOutTargets, D>;
+
+/// Returns the number of public inputs a cyclic node proof exposes after those of a leaf proof,
+/// i.e. the verifier data of the cyclic node circuit built with `config`.
+pub(crate) fn num_verifier_data_public_inputs(config: &CircuitConfig) -> usize {
+    NUM_HASH_OUT_ELTS + NUM_HASH_OUT_ELTS * config.fri_config.num_cap_elements()
+}
+
+/// Returns whether a proof of a circuit built with `config` exposing `num_public_inputs` public
+/// inputs is a cyclic node proof, i.e. exposes the verifier data of its circuit after those of a
+/// leaf proof.
+pub(crate) fn has_verifier_data_public_inputs(
+    num_public_inputs: usize,
+    config: &CircuitConfig,
+) -> bool {
+    let num_verifier_data_public_inputs = num_verifier_data_public_inputs(config);
+    num_public_inputs == NUM_PUBLIC_INPUTS + num_verifier_data_public_inputs
+        || num_public_inputs == NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST + num_verifier_data_public_inputs
+}
+
+/// Checks that the last public inputs of a cyclic node proof are `verifier_only`, i.e. its circuit
+/// digest followed by the elements of its constants sigmas cap, as plonky2's
+/// `check_cyclic_proof_verifier_data` does, without requiring an algebraic hasher.
+pub(crate) fn check_verifier_data_public_inputs<F, C, const D: usize>(
+    public_inputs: &[F],
+    verifier_only: &VerifierOnlyCircuitData<C, D>,
+) -> Result<(), ZkTreeError>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+{
+    let verifier_data_public_inputs = verifier_only
+        .circuit_digest
+        .to_vec()
+        .into_iter()
+        .chain(
+            verifier_only
+                .constants_sigmas_cap
+                .0
+                .iter()
+                .flat_map(|cap_hash| cap_hash.to_vec()),
+        )
+        .collect::<Vec<_>>();
+    if !public_inputs.ends_with(&verifier_data_public_inputs) {
+        return Err(ZkTreeError::CyclicVerifierDataMismatch);
+    }
+    Ok(())
+}
+
+/// The children of a `CyclicNodeCircuit`, which are either all leaves, at the bottom of the
+/// zkTree, or all proofs of the cyclic node circuit itself.
+pub enum CyclicNodeChildren<'a, C, F, H, const D: usize>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    Leaves(Vec<&'a LeafProof<C, F, H, D>>),
+    Nodes(Vec<&'a CyclicNodeProof<C, F, H, D>>),
+}
+
+impl<'a, C, F, H, const D: usize> CyclicNodeChildren<'a, C, F, H, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    /// Returns the number of children.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Leaves(leaf_proofs) => leaf_proofs.len(),
+            Self::Nodes(node_proofs) => node_proofs.len(),
+        }
+    }
+
+    /// Returns whether there is no child at all.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns whether the children are leaves.
+    pub fn is_leaves(&self) -> bool {
+        matches!(self, Self::Leaves(_))
+    }
+
+    /// Returns the proofs of the children, from left to right.
+    pub fn proofs(&self) -> Vec<&dyn Proof<C, F, D>> {
+        match self {
+            Self::Leaves(leaf_proofs) => leaf_proofs
+                .iter()
+                .map(|leaf_proof| *leaf_proof as &dyn Proof<C, F, D>)
+                .collect(),
+            Self::Nodes(node_proofs) => node_proofs
+                .iter()
+                .map(|node_proof| *node_proof as &dyn Proof<C, F, D>)
+                .collect(),
+        }
+    }
+}
+
+/// `CyclicNodeCircuit` is a node circuit of a zkTree built with cyclic recursion: the very same
+/// circuit aggregates either leaf proofs, verified against the constant verifier data of the leaf
+/// circuit, or proofs of itself, verified against its own verifier data. The latter is exposed as
+/// the last public inputs of the circuit, and the verifier data exposed by every child node proof
+/// is constrained to be equal to it, so that the verifier circuit digest folded into the node
+/// circuit hash is the actual digest of the circuit. The circuit hash of a root proof is thus
+/// soundly bound to the leaf circuit, the cyclic node circuit and the whole shape of the tree.
+///
+/// The circuit must be built with a config without zero-knowledge, the leaves already hiding the
+/// user proofs, as cyclic recursion verifies dummy proofs of non zero-knowledge circuits. Unlike
+/// the other circuits, it does not implement `CircuitCompiler`, as building the cyclic recursion
+/// can fail: it is compiled with [`CyclicNodeCircuit::build_compiled_circuit`] instead.
+///
+/// # Type Parameters
+///
+/// * `'a`: Lifetime parameter that dictates the lifetime of the references to the child proofs.
+/// * `C`: Circuit configuration which must satisfy `GenericConfig`.
+/// * `F`: Field type that must implement `RichField` and `Extendable<D>`.
+/// * `H`: Hasher type that implements `AlgebraicHasher<F>`, the hasher of `C`.
+/// * `D`: Dimension of the field extension, a compile-time constant.
+///
+/// # Fields
+///
+/// * `leaf_proof_data`: The proof data of any leaf of the zkTree, whose circuit is the only leaf
+///   circuit accepted, and whose proof fills the leaf proof targets of nodes above the leaves.
+/// * `children`: The children of this node, from left to right.
+/// * `base_proof`: An optional cyclic base proof filling the node proof targets of nodes right
+///   above the leaves, generated when proving otherwise.
+/// * `config`: The `CircuitConfig` the circuit is built with, the recursion config without
+///   zero-knowledge by default.
+/// * `max_common_data_rounds`: The number of rounds of building the circuit after which its common
+///   data must have reached its fixed point, `MAX_COMMON_DATA_ROUNDS` by default.
+pub struct CyclicNodeCircuit<'a, C, F, H, const D: usize>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    leaf_proof_data: &'a ProofData<F, C, D>,
+    children: CyclicNodeChildren<'a, C, F, H, D>,
+    base_proof: Option<&'a ProofWithPublicInputs<F, C, D>>,
+    config: CircuitConfig,
+    max_common_data_rounds: usize,
+}
+
+impl<'a, C, F, H, const D: usize> CyclicNodeCircuit<'a, C, F, H, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    /// Constructs a new `CyclicNodeCircuit` aggregating `children`, whose number is the arity of
+    /// the node.
+    ///
+    /// # Arguments
+    ///
+    /// * `leaf_proof_data`: The proof data of any leaf of the zkTree.
+    /// * `children`: The children of the node, from left to right.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new instance of `CyclicNodeCircuit`.
+    pub fn new(
+        leaf_proof_data: &'a ProofData<F, C, D>,
+        children: CyclicNodeChildren<'a, C, F, H, D>,
+    ) -> Self {
+        Self {
+            leaf_proof_data,
+            children,
+            base_proof: None,
+            config: CircuitConfig::standard_recursion_config(),
+            max_common_data_rounds: MAX_COMMON_DATA_ROUNDS,
+        }
+    }
+
+    /// Sets the `CircuitConfig` the circuit is built with, which must not be zero-knowledge.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The `CircuitConfig` of the circuit.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `CyclicNodeCircuit` with the config set.
+    pub fn with_config(mut self, config: CircuitConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the number of rounds of building the circuit after which its common data must have
+    /// reached its fixed point, see [`MAX_COMMON_DATA_ROUNDS`].
+    ///
+    /// # Arguments
+    ///
+    /// * `max_common_data_rounds`: The maximum number of rounds.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `CyclicNodeCircuit` with the maximum number of rounds set.
+    pub fn with_max_common_data_rounds(mut self, max_common_data_rounds: usize) -> Self {
+        self.max_common_data_rounds = max_common_data_rounds;
+        self
+    }
+
+    /// Sets the cyclic base proof filling the node proof targets when the children are leaves, so
+    /// that it is not generated again for every node.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_proof`: A cyclic base proof of the compiled circuit.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `CyclicNodeCircuit` with the base proof set.
+    pub fn with_base_proof(mut self, base_proof: &'a ProofWithPublicInputs<F, C, D>) -> Self {
+        self.base_proof = Some(base_proof);
+        self
+    }
+
+    /// Computes the `CommonCircuitData` of the circuit, which is the fixed point of building the
+    /// circuit verifying proofs of a given `CommonCircuitData`. Each round builds the circuit with
+    /// the common data of the previous round, starting from the one of an empty circuit, until it
+    /// no longer changes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the common data, or a `ZkTreeError` if it does not reach a fixed
+    /// point within `max_common_data_rounds` rounds.
+    fn common_data(&self) -> Result<CommonCircuitData<F, D>, ZkTreeError> {
+        let mut common_data = CircuitBuilder::<F, D>::new(self.config.clone())
+            .build::<C>()
+            .common;
+        common_data.num_public_inputs = self.leaf_proof_data.circuit_data.common.num_public_inputs
+            + num_verifier_data_public_inputs(&self.config);
+
+        for _ in 0..self.max_common_data_rounds {
+            let (circuit_builder, _, _) = self.compile_with_common_data(&common_data, false)?;
+            let next_common_data = circuit_builder.build::<C>().common;
+            if next_common_data == common_data {
+                return Ok(common_data);
+            }
+            common_data = next_common_data;
+        }
+        Err(ZkTreeError::CyclicCommonDataFixedPoint {
+            rounds: self.max_common_data_rounds,
+        })
+    }
+
+    /// Compiles the circuit verifying child node proofs of `common_data`. When `cyclic` is
+    /// `false`, the child node proofs are verified against the verifier data of the circuit with
+    /// the same gates, but without the cyclic recursion constraints, which require `common_data`
+    /// to be the common data of the circuit itself. This is only used to compute the latter.
+    fn compile_with_common_data(
+        &self,
+        common_data: &CommonCircuitData<F, D>,
+        cyclic: bool,
+    ) -> Result<
+        (
+            CircuitBuilder<F, D>,
+            CyclicNodeCircuitTargets<D>,
+            NodeCircuitOutTargets,
+        ),
+        ZkTreeError,
+    > {
+        let leaf_circuit_data = &self.leaf_proof_data.circuit_data;
+        let leaf_common_data = &leaf_circuit_data.common;
+        let num_child_public_inputs = leaf_common_data.num_public_inputs;
+        let mut circuit_builder = CircuitBuilder::<F, D>::new(self.config.clone());
+
+        // the node input hash, circuit hash and allow-list root lead the public inputs as in a
+        // `NodeCircuit`, followed by the verifier data, which must be registered last
+        let node_input_hash_targets = circuit_builder.add_virtual_hash();
+        circuit_builder.register_public_inputs(&node_input_hash_targets.elements);
+        let node_circuit_hash_targets = circuit_builder.add_virtual_hash();
+        circuit_builder.register_public_inputs(&node_circuit_hash_targets.elements);
+        let allow_list_root_targets =
+            (num_child_public_inputs == NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST).then(|| {
+                let allow_list_root_targets = circuit_builder.add_virtual_hash();
+                circuit_builder.register_public_inputs(&allow_list_root_targets.elements);
+                allow_list_root_targets
+            });
+        let verifier_data_targets = circuit_builder.add_verifier_data_public_inputs();
+
+        let is_leaf_target = circuit_builder.add_virtual_bool_target_safe();
+        let is_node_target = circuit_builder.not(is_leaf_target);
+
+        // leaf proofs are always verified, against the constant verifier data of the leaf circuit
+        let leaf_verifier_data_targets =
+            circuit_builder.constant_verifier_data(&leaf_circuit_data.verifier_only);
+        let leaf_proofs_targets = (0..self.children.len())
+            .map(|_| {
+                let leaf_proof_targets =
+                    circuit_builder.add_virtual_proof_with_pis(leaf_common_data);
+                circuit_builder.verify_proof::<C>(
+                    &leaf_proof_targets,
+                    &leaf_verifier_data_targets,
+                    leaf_common_data,
+                );
+                leaf_proof_targets
+            })
+            .collect::<Vec<_>>();
+
+        // node proofs are verified against the verifier data of this very circuit, unless the
+        // children are leaves
+        let node_proofs_targets = (0..self.children.len())
+            .map(|_| circuit_builder.add_virtual_proof_with_pis(common_data))
+            .collect::<Vec<_>>();
+        for node_proof_targets in &node_proofs_targets {
+            if cyclic {
+                circuit_builder.conditionally_verify_cyclic_proof_or_dummy::<C>(
+                    is_node_target,
+                    node_proof_targets,
+                    common_data,
+                )?;
+            } else {
+                let other_proof_targets = circuit_builder.add_virtual_proof_with_pis(common_data);
+                let other_verifier_data_targets =
+                    circuit_builder.add_virtual_verifier_data(self.config.fri_config.cap_height);
+                circuit_builder.conditionally_verify_proof::<C>(
+                    is_node_target,
+                    node_proof_targets,
+                    &verifier_data_targets,
+                    &other_proof_targets,
+                    &other_verifier_data_targets,
+                    common_data,
+                );
+                for gate in &common_data.gates {
+                    circuit_builder.add_gate_to_gate_set(gate.clone());
+                }
+            }
+        }
+
+        // the public inputs of the children are the ones of their leaf or node proof, which share
+        // the same layout apart from the trailing verifier data of node proofs
+        let children_public_inputs_targets = leaf_proofs_targets
+            .iter()
+            .zip(&node_proofs_targets)
+            .map(|(leaf_proof_targets, node_proof_targets)| {
+                (0..num_child_public_inputs)
+                    .map(|i| {
+                        circuit_builder.select(
+                            is_leaf_target,
+                            leaf_proof_targets.public_inputs[i],
+                            node_proof_targets.public_inputs[i],
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let children_input_hashes_targets = children_public_inputs_targets
+            .iter()
+            .map(|public_inputs_targets| {
+                HashOutTarget::from_vec(public_inputs_targets[..NUM_HASH_OUT_ELTS].to_vec())
+            })
+            .collect::<Vec<_>>();
+        let should_be_node_input_hash_targets = node_input_hash_circuit::<F, H, D>(
+            &mut circuit_builder,
+            &children_input_hashes_targets,
+        );
+        circuit_builder.connect_hashes(node_input_hash_targets, should_be_node_input_hash_targets);
+
+        let children_circuit_hashes_targets = children_public_inputs_targets
+            .iter()
+            .map(|public_inputs_targets| {
+                HashOutTarget::from_vec(
+                    public_inputs_targets[NUM_HASH_OUT_ELTS..2 * NUM_HASH_OUT_ELTS].to_vec(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let should_be_node_circuit_hash_targets = node_circuit_hash_circuit::<F, H, D>(
+            &mut circuit_builder,
+            &children_circuit_hashes_targets,
+            verifier_data_targets.circuit_digest,
+        );
+        circuit_builder.connect_hashes(
+            node_circuit_hash_targets,
+            should_be_node_circuit_hash_targets,
+        );
+
+        // the allow-list roots of all children must agree, and are propagated to the node
+        if let Some(allow_list_root_targets) = allow_list_root_targets {
+            for public_inputs_targets in &children_public_inputs_targets {
+                circuit_builder.connect_hashes(
+                    allow_list_root_targets,
+                    HashOutTarget::from_vec(
+                        public_inputs_targets
+                            [ALLOW_LIST_ROOT_OFFSET..NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST]
+                            .to_vec(),
+                    ),
+                );
+            }
+        }
+
+        // more than half the degree of `common_data`, so that building rounds the degree up to it
+        while circuit_builder.num_gates() <= 1 << common_data.degree_bits().saturating_sub(1) {
+            circuit_builder.add_gate(NoopGate, vec![]);
+        }
+
+        Ok((
+            circuit_builder,
+            (
+                is_leaf_target,
+                leaf_proofs_targets,
+                node_proofs_targets,
+                verifier_data_targets,
+            ),
+            (node_circuit_hash_targets, node_input_hash_targets),
+        ))
+    }
+
+    /// Checks that the children are proofs of the leaf circuit, or of the cyclic node circuit
+    /// whose verifier circuit digest is `verifier_circuit_digest`. The circuit could not be
+    /// satisfied otherwise.
+    fn check_children(&self, verifier_circuit_digest: HashOut<F>) -> Result<(), ZkTreeError> {
+        let expected = match &self.children {
+            CyclicNodeChildren::Leaves(leaf_proofs) => {
+                check_children_public_inputs(leaf_proofs)?;
+                self.leaf_proof_data
+                    .circuit_data
+                    .verifier_only
+                    .circuit_digest
+            }
+            CyclicNodeChildren::Nodes(_) => verifier_circuit_digest,
+        };
+        if let Some(position) = self
+            .children
+            .proofs()
+            .iter()
+            .position(|child| child.circuit_verifier_digest() != expected)
+        {
+            return Err(ZkTreeError::ChildVerifierDataMismatch {
+                level: None,
+                node_index: None,
+                position,
+            });
+        }
+        Ok(())
+    }
+
+    /// Compiles and builds the circuit into a `CompiledCircuit`, which can then be shared to prove
+    /// every node of a zkTree, at any height, without rebuilding it.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the compiled circuit, or a `ZkTreeError` if its common data does not
+    /// reach a fixed point or the cyclic recursion cannot be built.
+    pub fn build_compiled_circuit(&self) -> Result<CompiledCyclicCircuit<F, C, D>, ZkTreeError> {
+        let common_data = self.common_data()?;
+        let (circuit_builder, targets, out_targets) =
+            self.compile_with_common_data(&common_data, true)?;
+        Ok(CompiledCircuit::new(
+            circuit_builder.build::<C>(),
+            targets,
+            out_targets,
+        ))
+    }
+
+    /// Computes the node circuit hash and input hash out of the hashes of the children, the
+    /// verifier circuit digest of the compiled circuit being folded into the former.
+    fn evaluate(&self, verifier_circuit_digest: HashOut<F>) -> (HashOut<F>, HashOut<F>) {
+        let children = self.children.proofs();
+        let children_circuit_hashes = children
+            .iter()
+            .map(|child| child.circuit_hash())
+            .collect::<Vec<_>>();
+        let children_input_hashes = children
+            .iter()
+            .map(|child| child.input_hash())
+            .collect::<Vec<_>>();
+
+        let node_circuit_hash =
+            node_circuit_hash::<F, H>(&children_circuit_hashes, verifier_circuit_digest);
+        let node_input_hash = node_input_hash::<F, H>(&children_input_hashes);
+
+        (node_circuit_hash, node_input_hash)
+    }
+
+    /// Fills the witness of the circuit compiled into `circuit_data`.
+    fn fill(
+        &self,
+        circuit_data: &CircuitData<F, C, D>,
+        targets: CyclicNodeCircuitTargets<D>,
+        out_targets: NodeCircuitOutTargets,
+    ) -> Result<PartialWitness<F>, ZkTreeError> {
+        let mut partial_witness = PartialWitness::<F>::new();
+
+        let (is_leaf_target, leaf_proofs_targets, node_proofs_targets, verifier_data_targets) =
+            targets;
+        let (node_circuit_hash_targets, node_input_hash_targets) = out_targets;
+
+        if leaf_proofs_targets.len() != self.children.len() {
+            return Err(ZkTreeError::InvalidNumberOfChildren {
+                num_children: self.children.len(),
+                expected: leaf_proofs_targets.len(),
+            });
+        }
+
+        partial_witness.set_bool_target(is_leaf_target, self.children.is_leaves());
+        match &self.children {
+            CyclicNodeChildren::Leaves(leaf_proofs) => {
+                // the node proofs are not verified, a cyclic base proof only carries the verifier
+                // data they must expose
+                let generated_base_proof;
+                let base_proof = match self.base_proof {
+                    Some(base_proof) => base_proof,
+                    None => {
+                        generated_base_proof = cyclic_base_proof(
+                            &circuit_data.common,
+                            &circuit_data.verifier_only,
+                            Default::default(),
+                        );
+                        &generated_base_proof
+                    }
+                };
+                for ((leaf_proof_targets, node_proof_targets), leaf_proof) in leaf_proofs_targets
+                    .iter()
+                    .zip(&node_proofs_targets)
+                    .zip(leaf_proofs)
+                {
+                    partial_witness.set_proof_with_pis_target(
+                        leaf_proof_targets,
+                        &leaf_proof.proof().proof_with_pis,
+                    );
+                    partial_witness.set_proof_with_pis_target(node_proof_targets, base_proof);
+                }
+            }
+            CyclicNodeChildren::Nodes(node_proofs) => {
+                // the leaf proofs are always verified, any leaf proof satisfies them
+                for ((leaf_proof_targets, node_proof_targets), node_proof) in leaf_proofs_targets
+                    .iter()
+                    .zip(&node_proofs_targets)
+                    .zip(node_proofs)
+                {
+                    partial_witness.set_proof_with_pis_target(
+                        leaf_proof_targets,
+                        &self.leaf_proof_data.proof_with_pis,
+                    );
+                    partial_witness.set_proof_with_pis_target(
+                        node_proof_targets,
+                        &node_proof.proof().proof_with_pis,
+                    );
+                }
+            }
+        }
+        partial_witness
+            .set_verifier_data_target(&verifier_data_targets, &circuit_data.verifier_only);
+
+        let (node_circuit_hash, node_input_hash) =
+            self.evaluate(circuit_data.verifier_only.circuit_digest);
+
+        partial_witness.set_hash_target(node_circuit_hash_targets, node_circuit_hash);
+        partial_witness.set_hash_target(node_input_hash_targets, node_input_hash);
+
+        Ok(partial_witness)
+    }
+
+    /// Generates the proof data of the node, using a circuit previously compiled by
+    /// [`CyclicNodeCircuit::build_compiled_circuit`].
+    ///
+    /// # Arguments
+    ///
+    /// * `compiled_circuit`: The compiled circuit to generate the proof with.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the proof data, or a `ZkTreeError` if the children are not proofs of
+    /// the leaf circuit or of the compiled circuit, or if the proof generation fails.
+    pub fn proof_with_compiled_circuit(
+        self,
+        compiled_circuit: &CompiledCyclicCircuit<F, C, D>,
+    ) -> Result<ProofData<F, C, D>, ZkTreeError> {
+        let circuit_data = &compiled_circuit.circuit_data;
+        self.check_children(circuit_data.verifier_only.circuit_digest)?;
+        let partial_witness = self.fill(
+            circuit_data,
+            compiled_circuit.targets.clone(),
+            compiled_circuit.out_targets,
+        )?;
+        let proof_with_pis = circuit_data.prove(partial_witness)?;
+
+        Ok(ProofData {
+            proof_with_pis,
+            circuit_data: circuit_data.clone(),
+        })
+    }
+}
+
+/// A `CyclicNodeCircuit` compiled once for a given leaf circuit and arity, which proves every
+/// node of a zkTree at any height, together with the cyclic base proof and the leaf proof filling
+/// the unused child proof targets.
+///
+/// # Fields
+///
+/// * `compiled_circuit`: The compiled `CyclicNodeCircuit`.
+/// * `leaf_proof_data`: The proof data of a leaf of the leaf circuit.
+/// * `base_proof`: The cyclic base proof of the circuit.
+pub struct CompiledCyclicNodeCircuit<F, C, const D: usize>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+{
+    pub(crate) compiled_circuit: CompiledCyclicCircuit<F, C, D>,
+    pub(crate) leaf_proof_data: ProofData<F, C, D>,
+    pub(crate) base_proof: ProofWithPublicInputs<F, C, D>,
+}
+
+impl<F, C, const D: usize> CompiledCyclicNodeCircuit<F, C, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+{
+    /// Returns the circuit data of the compiled `CyclicNodeCircuit`.
+    pub fn circuit_data(&self) -> &CircuitData<F, C, D> {
+        &self.compiled_circuit.circuit_data
+    }
+
+    /// Returns the number of children of the nodes proven with the circuit.
+    pub fn arity(&self) -> usize {
+        self.compiled_circuit.targets.1.len()
+    }
+}
+
+impl<F, C, const D: usize> CompiledCyclicNodeCircuit<F, C, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F> + 'static,
+    C::Hasher: AlgebraicHasher<F>,
+{
+    /// Compiles the `CyclicNodeCircuit` of `arity` children accepting proofs of the circuit of
+    /// `leaf_proof`, built with `config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `leaf_proof`: Any leaf proof of the zkTree.
+    /// * `arity`: The number of children of every node, at least two.
+    /// * `config`: The `CircuitConfig` of the circuit, which must not be zero-knowledge, e.g.
+    ///   `CircuitConfig::standard_recursion_config()`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the compiled circuit, or a `ZkTreeError` if the arity or config is
+    /// invalid, if the leaf proof does not expose the public inputs of a leaf, or if the circuit
+    /// cannot be built.
+    pub fn new<H>(
+        leaf_proof: &LeafProof<C, F, H, D>,
+        arity: usize,
+        config: CircuitConfig,
+    ) -> Result<Self, ZkTreeError>
+    where
+        C: GenericConfig<D, F = F, Hasher = H>,
+        H: AlgebraicHasher<F>,
+    {
+        check_arity(arity)?;
+        if config.zero_knowledge {
+            return Err(ZkTreeError::ZeroKnowledgeCyclicConfig);
+        }
+        check_children_public_inputs(&[leaf_proof])?;
+
+        let leaf_proof_data = leaf_proof.proof().clone();
+        let compiled_circuit = CyclicNodeCircuit::<C, F, H, D>::new(
+            &leaf_proof_data,
+            CyclicNodeChildren::Leaves(vec![leaf_proof; arity]),
+        )
+        .with_config(config)
+        .build_compiled_circuit()?;
+        let base_proof = cyclic_base_proof(
+            &compiled_circuit.circuit_data.common,
+            &compiled_circuit.circuit_data.verifier_only,
+            Default::default(),
+        );
+
+        Ok(Self {
+            compiled_circuit,
+            leaf_proof_data,
+            base_proof,
+        })
+    }
+}
@@ -0,0 +1,238 @@
+use std::marker::PhantomData;
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::{HashOut, RichField},
+    plonk::config::{AlgebraicHasher, GenericConfig},
+    recursion::cyclic_recursion::check_cyclic_proof_verifier_data,
+};
+
+use crate::{
+    circuit_commitment::node_circuit_hash,
+    components::{
+        allow_list::allow_list_root_from_public_inputs,
+        cyclic_node_circuit::{
+            num_verifier_data_public_inputs, CompiledCyclicNodeCircuit, CyclicNodeChildren,
+            CyclicNodeCircuit,
+        },
+        leaf_proof::LeafProof,
+    },
+    error::ZkTreeError,
+    input_commitment::node_input_hash,
+    proof_data::ProofData,
+    traits::proof::Proof,
+};
+
+/// `CyclicNodeProof` is the proof of a `CyclicNodeCircuit`, aggregating either leaf proofs or
+/// other cyclic node proofs. It exposes the input hash, circuit hash and allow-list root of the
+/// node as a `NodeProof` does, followed by the verifier data of the cyclic node circuit.
+///
+/// # Type Parameters
+///
+/// * `C`: The configuration of the circuit, which must implement `GenericConfig`.
+/// * `F`: The field type that implements `RichField` and `Extendable<D>`.
+/// * `H`: The hasher type that implements `AlgebraicHasher<F>`, the hasher of `C`.
+/// * `D`: The dimension of the field extension, specified as a compile-time constant.
+///
+/// # Fields
+///
+/// * `proof_data`: The proof data of the `CyclicNodeCircuit`.
+/// * `input_hash`: The hash of the inputs to this node's circuit.
+/// * `circuit_hash`: The hash of this node's circuit.
+/// * `phantom_data`: `PhantomData` to mark the usage of the hasher type `H`.
+#[derive(Clone)]
+pub struct CyclicNodeProof<C, F, H, const D: usize>
+where
+    H: AlgebraicHasher<F>,
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+{
+    proof_data: ProofData<F, C, D>,
+    input_hash: HashOut<F>,
+    circuit_hash: HashOut<F>,
+    phantom_data: PhantomData<H>,
+}
+
+impl<C, F, H, const D: usize> CyclicNodeProof<C, F, H, D>
+where
+    H: AlgebraicHasher<F>,
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+{
+    /// Creates a new `CyclicNodeProof` instance using the provided proof data, input hash, and
+    /// circuit hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `proof_data`: The proof data of the `CyclicNodeCircuit`.
+    /// * `input_hash`: The hash of the inputs for this node.
+    /// * `circuit_hash`: The hash of this node's circuit.
+    ///
+    /// # Returns
+    ///
+    /// A new `CyclicNodeProof` instance.
+    pub fn new(
+        proof_data: ProofData<F, C, D>,
+        input_hash: HashOut<F>,
+        circuit_hash: HashOut<F>,
+    ) -> Self {
+        Self {
+            proof_data,
+            input_hash,
+            circuit_hash,
+            phantom_data: PhantomData,
+        }
+    }
+
+    /// Returns the root of the allow-list of user circuits the leaves were built with, or `None`
+    /// if they were built without an allow-list.
+    pub fn allow_list_root(&self) -> Option<HashOut<F>> {
+        allow_list_root_from_public_inputs(self.node_public_inputs())
+    }
+
+    /// Verifies the proof, and checks that the verifier data it exposes is the one of the
+    /// `CyclicNodeCircuit` it was proven with, which every child node proof down to the leaves was
+    /// constrained to expose as well.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that is `Ok` if the proof is valid, or a `ZkTreeError` otherwise.
+    pub fn verify(&self) -> Result<(), ZkTreeError> {
+        let circuit_data = &self.proof_data.circuit_data;
+        check_cyclic_proof_verifier_data(
+            &self.proof_data.proof_with_pis,
+            &circuit_data.verifier_only,
+            &circuit_data.common,
+        )
+        .map_err(|_| ZkTreeError::CyclicVerifierDataMismatch)?;
+        circuit_data.verify(self.proof_data.proof_with_pis.clone())?;
+        Ok(())
+    }
+}
+
+impl<C, F, H, const D: usize> CyclicNodeProof<C, F, H, D>
+where
+    H: AlgebraicHasher<F>,
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+{
+    /// Constructs a new `CyclicNodeProof` aggregating leaf proofs, reusing an already compiled
+    /// `CyclicNodeCircuit`.
+    ///
+    /// # Arguments
+    ///
+    /// * `leaf_proofs`: References to the leaf proofs, from left to right, as many as the arity
+    ///   of the compiled circuit.
+    /// * `compiled_cyclic_node_circuit`: A `CyclicNodeCircuit` compiled for the leaf circuit of
+    ///   `leaf_proofs`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `CyclicNodeProof`, or a `ZkTreeError` if the leaf proofs are
+    /// not proofs of the leaf circuit, or if the proof generation fails.
+    pub fn new_from_leaves_with_compiled_circuit(
+        leaf_proofs: &[&LeafProof<C, F, H, D>],
+        compiled_cyclic_node_circuit: &CompiledCyclicNodeCircuit<F, C, D>,
+    ) -> Result<Self, ZkTreeError> {
+        Self::new_from_children_with_compiled_circuit(
+            CyclicNodeChildren::Leaves(leaf_proofs.to_vec()),
+            compiled_cyclic_node_circuit,
+        )
+    }
+
+    /// Constructs a new `CyclicNodeProof` aggregating other cyclic node proofs, reusing the
+    /// compiled `CyclicNodeCircuit` they were proven with.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_proofs`: References to the cyclic node proofs, from left to right, as many as the
+    ///   arity of the compiled circuit.
+    /// * `compiled_cyclic_node_circuit`: The `CyclicNodeCircuit` `node_proofs` were proven with.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `CyclicNodeProof`, or a `ZkTreeError` if the node proofs are
+    /// not proofs of the compiled circuit, or if the proof generation fails.
+    pub fn new_from_nodes_with_compiled_circuit(
+        node_proofs: &[&Self],
+        compiled_cyclic_node_circuit: &CompiledCyclicNodeCircuit<F, C, D>,
+    ) -> Result<Self, ZkTreeError> {
+        Self::new_from_children_with_compiled_circuit(
+            CyclicNodeChildren::Nodes(node_proofs.to_vec()),
+            compiled_cyclic_node_circuit,
+        )
+    }
+
+    fn new_from_children_with_compiled_circuit(
+        children: CyclicNodeChildren<'_, C, F, H, D>,
+        compiled_cyclic_node_circuit: &CompiledCyclicNodeCircuit<F, C, D>,
+    ) -> Result<Self, ZkTreeError> {
+        let child_proofs = children.proofs();
+        let children_circuit_hashes = child_proofs
+            .iter()
+            .map(|child| child.circuit_hash())
+            .collect::<Vec<_>>();
+        let children_input_hashes = child_proofs
+            .iter()
+            .map(|child| child.input_hash())
+            .collect::<Vec<_>>();
+        let circuit_hash = node_circuit_hash::<F, H>(
+            &children_circuit_hashes,
+            compiled_cyclic_node_circuit
+                .circuit_data()
+                .verifier_only
+                .circuit_digest,
+        );
+        let input_hash = node_input_hash::<F, H>(&children_input_hashes);
+
+        let proof_data = CyclicNodeCircuit::<C, F, H, D>::new(
+            &compiled_cyclic_node_circuit.leaf_proof_data,
+            children,
+        )
+        .with_base_proof(&compiled_cyclic_node_circuit.base_proof)
+        .proof_with_compiled_circuit(&compiled_cyclic_node_circuit.compiled_circuit)?;
+
+        Ok(Self::new(proof_data, input_hash, circuit_hash))
+    }
+}
+
+impl<C, F, H, const D: usize> Proof<C, F, D> for CyclicNodeProof<C, F, H, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    fn user_public_inputs(&self) -> Vec<&[F]> {
+        vec![]
+    }
+
+    fn circuit_hash(&self) -> HashOut<F> {
+        self.circuit_hash
+    }
+
+    fn input_hash(&self) -> HashOut<F> {
+        self.input_hash
+    }
+
+    fn proof(&self) -> &ProofData<F, C, D> {
+        &self.proof_data
+    }
+
+    fn circuit_verifier_digest(&self) -> HashOut<F> {
+        self.proof().circuit_data.verifier_only.circuit_digest
+    }
+
+    fn node_public_inputs<'a>(&'a self) -> &'a [F]
+    where
+        C: 'a,
+    {
+        let public_inputs = &self.proof_data.proof_with_pis.public_inputs;
+        let num_node_public_inputs =
+            public_inputs
+                .len()
+                .saturating_sub(num_verifier_data_public_inputs(
+                    &self.proof_data.circuit_data.common.config,
+                ));
+        &public_inputs[..num_node_public_inputs]
+    }
+}
@@ -1,25 +1,27 @@
-use anyhow::anyhow;
 use plonky2::{
     field::extension::Extendable,
     hash::{
-        hash_types::{HashOut, HashOutTarget, RichField},
-        poseidon::PoseidonHash,
+        hash_types::{HashOut, HashOutTarget, RichField, NUM_HASH_OUT_ELTS},
+        merkle_proofs::MerkleProofTarget,
     },
     iop::{
-        target::Target,
+        target::{BoolTarget, Target},
         witness::{PartialWitness, WitnessWrite},
     },
     plonk::{
         circuit_builder::CircuitBuilder,
         circuit_data::{CircuitConfig, CircuitData, VerifierCircuitTarget},
-        config::{AlgebraicHasher, GenericConfig, Hasher},
+        config::{AlgebraicHasher, GenericConfig},
         proof::ProofWithPublicInputsTarget,
     },
 };
 use std::marker::PhantomData;
 
 use crate::{
-    components::user_proof::UserProof,
+    compiled_circuit::CompiledCircuit,
+    components::{allow_list::CircuitDigestAllowList, user_proof::UserProof},
+    error::ZkTreeError,
+    input_commitment::{leaf_input_hash_circuit, padding_input_hash, padding_input_hash_circuit},
     proof_data::ProofData,
     traits::{
         proof::Proof,
@@ -28,6 +30,30 @@ use crate::{
     },
 };
 
+/// Allow-list targets of a `LeafCircuit`: the allow-list root, the index of the user circuit
+/// digest in the allow-list and its Merkle proof.
+pub type AllowListTargets = (HashOutTarget, Target, MerkleProofTarget);
+
+/// Input targets of a `LeafCircuit`: the user public inputs, the user input hash, user circuit
+/// digest and leaf verifier circuit digest, the user proof and verifier data, the padding flag and
+/// the allow-list targets, if any.
+pub type LeafCircuitTargets<const D: usize> = (
+    Vec<Target>,
+    [HashOutTarget; 3],
+    ProofWithPublicInputsTarget<D>,
+    VerifierCircuitTarget,
+    BoolTarget,
+    Option<AllowListTargets>,
+);
+
+/// Output targets of a `LeafCircuit`: the leaf circuit hash.
+pub type LeafCircuitOutTargets = HashOutTarget;
+
+/// A `LeafCircuit` compiled once and shared by all the leaves whose user proofs have the same
+/// `CommonCircuitData`, public inputs layout and allow-list height.
+pub type CompiledLeafCircuit<F, C, const D: usize> =
+    CompiledCircuit<F, C, LeafCircuitTargets<D>, LeafCircuitOutTargets, D>;
+
 /// `LeafCircuit` is a data structure intended to represent a leaf node circuit in a zkTree structure,
 /// specifically within the context of zero-knowledge proofs. It holds a reference to a user's proof
 /// and optionally a verifier circuit digest, typically used for verifying sub-components of a larger
@@ -46,7 +72,16 @@ use crate::{
 ///
 /// * `user_proof`: A reference to the `UserProof` associated with this leaf circuit.
 /// * `verifier_circuit_digest`: An optional hash of the verifier circuit, representing the
-///   compiled and hashed version of the circuit used to verify proofs.
+///   compiled and hashed version of the circuit used to verify proofs. It is folded into the leaf
+///   circuit hash without being constrained in-circuit.
+/// * `is_padding`: Whether this leaf is a padding leaf. A padding leaf still verifies the
+///   referenced user proof, but commits to the canonical padding input hash instead of the
+///   user's public inputs.
+/// * `allow_list`: An optional allow-list of user circuits. If set, the circuit proves that the
+///   verified user circuit digest is a member of the allow-list, and exposes its root as a public
+///   input.
+/// * `config`: The `CircuitConfig` the circuit is built with, the zero-knowledge recursion config
+///   by default.
 /// * `phantom_data`: `PhantomData` used to indicate the use of generic types `C` and `F`.
 pub struct LeafCircuit<'a, C, F, H, const D: usize>
 where
@@ -56,6 +91,9 @@ where
 {
     user_proof: &'a UserProof<C, F, D>,
     verifier_circuit_digest: Option<H::Hash>,
+    is_padding: bool,
+    allow_list: Option<&'a CircuitDigestAllowList<F, H>>,
+    config: CircuitConfig,
     phantom_data: PhantomData<(C, F)>,
 }
 
@@ -80,9 +118,64 @@ where
         Self {
             user_proof,
             verifier_circuit_digest: None,
+            is_padding: false,
+            allow_list: None,
+            config: CircuitConfig::standard_recursion_zk_config(),
             phantom_data: PhantomData,
         }
     }
+
+    /// Constructs a new padding `LeafCircuit`. The circuit is the same as the one built by
+    /// [`LeafCircuit::new`], so padding leaves can be paired with regular leaves, but its input
+    /// hash is the canonical padding input hash and its user circuit hash is zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_proof`: A reference to a `UserProof` whose circuit shape the padding leaf mimics.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new padding instance of `LeafCircuit`.
+    pub fn new_padding(user_proof: &'a UserProof<C, F, D>) -> Self {
+        Self {
+            user_proof,
+            verifier_circuit_digest: None,
+            is_padding: true,
+            allow_list: None,
+            config: CircuitConfig::standard_recursion_zk_config(),
+            phantom_data: PhantomData,
+        }
+    }
+
+    /// Sets the allow-list of user circuits the verified user circuit digest must belong to. A
+    /// `LeafCircuit` without an allow-list accepts proofs of any user circuit.
+    ///
+    /// # Arguments
+    ///
+    /// * `allow_list`: An optional reference to the `CircuitDigestAllowList`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `LeafCircuit` with the allow-list set.
+    pub fn with_allow_list(mut self, allow_list: Option<&'a CircuitDigestAllowList<F, H>>) -> Self {
+        self.allow_list = allow_list;
+        self
+    }
+
+    /// Sets the `CircuitConfig` the circuit is built with, e.g. a config without zero-knowledge
+    /// or with another FRI rate. Leaves built with different configs cannot share a node.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The `CircuitConfig` of the circuit.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `LeafCircuit` with the config set.
+    pub fn with_config(mut self, config: CircuitConfig) -> Self {
+        self.config = config;
+        self
+    }
 }
 
 impl<'a, C, F, H, const D: usize> CircuitCompiler<C, F, D> for LeafCircuit<'a, C, F, H, D>
@@ -91,17 +184,11 @@ where
     F: RichField + Extendable<D>,
     H: AlgebraicHasher<F>,
 {
-    type Targets = (
-        Vec<Target>,
-        [HashOutTarget; 3],
-        ProofWithPublicInputsTarget<D>,
-        VerifierCircuitTarget,
-    );
-    type OutTargets = HashOutTarget;
+    type Targets = LeafCircuitTargets<D>;
+    type OutTargets = LeafCircuitOutTargets;
 
     fn compile(&self) -> (CircuitBuilder<F, D>, Self::Targets, Self::OutTargets) {
-        let mut circuit_builder =
-            CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_zk_config());
+        let mut circuit_builder = CircuitBuilder::<F, D>::new(self.config.clone());
 
         // add targets for hash <- user public inputs
         let user_public_inputs = self.user_proof.user_public_inputs();
@@ -124,8 +211,26 @@ where
             .flatten()
             .collect::<Vec<_>>();
 
-        let should_be_hash_user_public_inputs_targets =
-            circuit_builder.hash_or_noop::<H>(flatten_user_public_inputs_targets.clone());
+        let user_public_inputs_hash_targets = leaf_input_hash_circuit::<F, H, D>(
+            &mut circuit_builder,
+            flatten_user_public_inputs_targets.clone(),
+        );
+
+        // padding leaves commit to the canonical padding input hash instead
+        let is_padding_target = circuit_builder.add_virtual_bool_target_safe();
+        let padding_input_hash_targets =
+            padding_input_hash_circuit::<F, H, D>(&mut circuit_builder);
+        let should_be_hash_user_public_inputs_targets = HashOutTarget::from_vec(
+            (0..NUM_HASH_OUT_ELTS)
+                .map(|i| {
+                    circuit_builder.select(
+                        is_padding_target,
+                        padding_input_hash_targets.elements[i],
+                        user_public_inputs_hash_targets.elements[i],
+                    )
+                })
+                .collect(),
+        );
 
         // assert that user hash is well formed
         circuit_builder.connect_hashes(
@@ -133,7 +238,9 @@ where
             hash_user_public_inputs_targets,
         );
 
-        // circuit hash verification
+        // circuit hash verification. The digest of the leaf circuit itself is an unconstrained
+        // witness, as a circuit cannot constrain its own digest without cyclic recursion: verifiers
+        // must pin the expected digest, see `ZkTree::verify_with_circuit_digests`
         let user_verifier_circuit_digest_targets = circuit_builder.add_virtual_hash();
         let verifier_circuit_digest_targets = circuit_builder.add_virtual_hash();
 
@@ -173,22 +280,68 @@ where
             &self.user_proof.proof().circuit_data.common,
         );
 
-        // User proof public inputs verification
-        let true_bool_target = circuit_builder._true();
-        let false_bool_target = circuit_builder._false();
+        // the user circuit hash must be the digest of the verified user circuit, padding leaves
+        // commit to a zero user circuit hash instead
+        let zero_hash_targets = circuit_builder.constant_hash(HashOut::ZERO);
+        let should_be_user_verifier_circuit_digest_targets = HashOutTarget::from_vec(
+            (0..NUM_HASH_OUT_ELTS)
+                .map(|i| {
+                    circuit_builder.select(
+                        is_padding_target,
+                        zero_hash_targets.elements[i],
+                        user_verifier_data_targets.circuit_digest.elements[i],
+                    )
+                })
+                .collect(),
+        );
+
+        circuit_builder.connect_hashes(
+            user_verifier_circuit_digest_targets,
+            should_be_user_verifier_circuit_digest_targets,
+        );
+
+        // allow-list membership of the verified user circuit digest
+        let allow_list_targets = self.allow_list.map(|allow_list| {
+            let allow_list_root_targets = circuit_builder.add_virtual_hash();
+            circuit_builder.register_public_inputs(&allow_list_root_targets.elements);
+
+            let allow_list_index_target = circuit_builder.add_virtual_target();
+            let allow_list_index_bits =
+                circuit_builder.split_le(allow_list_index_target, allow_list.height());
+            let allow_list_proof_targets = MerkleProofTarget {
+                siblings: circuit_builder.add_virtual_hashes(allow_list.height()),
+            };
+            circuit_builder.verify_merkle_proof::<H>(
+                user_verifier_data_targets.circuit_digest.elements.to_vec(),
+                &allow_list_index_bits,
+                allow_list_root_targets,
+                &allow_list_proof_targets,
+            );
 
+            (
+                allow_list_root_targets,
+                allow_list_index_target,
+                allow_list_proof_targets,
+            )
+        });
+
+        // User proof public inputs verification. A length mismatch is rejected natively before
+        // proving (see `UserProof::check_public_inputs`), the circuit is only kept unsatisfiable
         if flatten_user_public_inputs_targets.len()
             != user_proof_with_pis_targets.public_inputs.len()
         {
+            let true_bool_target = circuit_builder._true();
+            let false_bool_target = circuit_builder._false();
             circuit_builder.connect(true_bool_target.target, false_bool_target.target);
         }
 
-        (0..flatten_user_public_inputs_targets.len()).for_each(|i| {
-            circuit_builder.connect(
-                user_proof_with_pis_targets.public_inputs[i],
-                flatten_user_public_inputs_targets[i],
-            );
-        });
+        user_proof_with_pis_targets
+            .public_inputs
+            .iter()
+            .zip(&flatten_user_public_inputs_targets)
+            .for_each(|(public_input_target, user_public_input_target)| {
+                circuit_builder.connect(*public_input_target, *user_public_input_target);
+            });
 
         (
             circuit_builder,
@@ -201,6 +354,8 @@ where
                 ],
                 user_proof_with_pis_targets,
                 user_verifier_data_targets,
+                is_padding_target,
+                allow_list_targets,
             ),
             leaf_circuit_hash_targets,
         )
@@ -223,48 +378,47 @@ where
 {
     type Value = (HashOut<F>, HashOut<F>);
     fn evaluate(&self) -> Self::Value {
-        (self.user_proof.input_hash(), self.user_proof.circuit_hash())
+        if self.is_padding {
+            (padding_input_hash::<F, H>(), HashOut::ZERO)
+        } else {
+            (self.user_proof.input_hash(), self.user_proof.circuit_hash())
+        }
     }
 
     fn fill(
         &self,
         targets: Self::Targets,
         out_targets: Self::OutTargets,
-    ) -> Result<PartialWitness<F>, anyhow::Error> {
+    ) -> Result<PartialWitness<F>, ZkTreeError> {
         let (
             flatten_user_public_inputs_targets,
             [hash_user_public_inputs_targets, user_verifier_circuit_digest_targets, verifier_circuit_digest_targets],
             user_proof_with_pis_targets,
             user_verifier_data_targets,
+            is_padding_target,
+            allow_list_targets,
         ) = targets;
         let leaf_circuit_hash_targets = out_targets;
+        self.user_proof.check_public_inputs()?;
+        let (input_hash, user_circuit_hash) = self.evaluate();
 
         let mut partial_witness = PartialWitness::<F>::new();
         partial_witness.set_target_arr(
             &flatten_user_public_inputs_targets,
             &self.user_proof.user_public_inputs().concat(),
         );
-        partial_witness.set_hash_target(
-            hash_user_public_inputs_targets,
-            self.user_proof.input_hash(),
-        );
-        partial_witness.set_hash_target(
-            user_verifier_circuit_digest_targets,
-            self.user_proof.circuit_verifier_digest(),
-        );
+        partial_witness.set_bool_target(is_padding_target, self.is_padding);
+        partial_witness.set_hash_target(hash_user_public_inputs_targets, input_hash);
+        partial_witness.set_hash_target(user_verifier_circuit_digest_targets, user_circuit_hash);
         if let Some(verifier_circuit_digest) = self.verifier_circuit_digest {
             partial_witness
                 .set_hash_target(verifier_circuit_digest_targets, verifier_circuit_digest);
-            let leaf_circuit_hash = PoseidonHash::hash_or_noop(
-                &[
-                    verifier_circuit_digest.elements,
-                    self.user_proof.circuit_verifier_digest().elements,
-                ]
-                .concat(),
+            let leaf_circuit_hash = H::hash_or_noop(
+                &[verifier_circuit_digest.elements, user_circuit_hash.elements].concat(),
             );
             partial_witness.set_hash_target(leaf_circuit_hash_targets, leaf_circuit_hash);
         } else {
-            return Err(anyhow!("Failed to generate the verifier circuit digest. Please compile the circuit once again"));
+            return Err(ZkTreeError::MissingVerifierCircuitDigest);
         }
         partial_witness.set_proof_with_pis_target(
             &user_proof_with_pis_targets,
@@ -275,6 +429,36 @@ where
             &self.user_proof.proof().circuit_data.verifier_only,
         );
 
+        match (allow_list_targets, self.allow_list) {
+            (
+                Some((allow_list_root_targets, allow_list_index_target, allow_list_proof_targets)),
+                Some(allow_list),
+            ) => {
+                let (allow_list_index, allow_list_proof) = allow_list.prove(
+                    &self
+                        .user_proof
+                        .proof()
+                        .circuit_data
+                        .verifier_only
+                        .circuit_digest,
+                )?;
+                partial_witness.set_hash_target(allow_list_root_targets, allow_list.root());
+                partial_witness.set_target(
+                    allow_list_index_target,
+                    F::from_canonical_usize(allow_list_index),
+                );
+                allow_list_proof_targets
+                    .siblings
+                    .into_iter()
+                    .zip(allow_list_proof.siblings)
+                    .for_each(|(sibling_targets, sibling)| {
+                        partial_witness.set_hash_target(sibling_targets, sibling)
+                    });
+            }
+            (None, None) => {}
+            _ => return Err(ZkTreeError::CompiledAllowListMismatch),
+        }
+
         Ok(partial_witness)
     }
 }
@@ -285,15 +469,21 @@ where
     C: GenericConfig<D, F = F, Hasher = H>,
     H: AlgebraicHasher<F>,
 {
-    fn proof(mut self) -> Result<ProofData<F, C, D>, anyhow::Error> {
-        let (circuit_data, targets, out_targets) = self.compile_and_build();
-        let partial_witness = self.fill(targets, out_targets)?;
-        if circuit_data.verifier_only.circuit_digest != self.verifier_circuit_digest.unwrap() {
-            return Err(anyhow!("Verifier circuit digest is not valid !"));
+    fn proof_with_compiled_circuit(
+        mut self,
+        compiled_circuit: &CompiledCircuit<F, C, Self::Targets, Self::OutTargets, D>,
+    ) -> Result<ProofData<F, C, D>, ZkTreeError> {
+        let circuit_digest = compiled_circuit.circuit_data.verifier_only.circuit_digest;
+        if *self.verifier_circuit_digest.get_or_insert(circuit_digest) != circuit_digest {
+            return Err(ZkTreeError::InvalidVerifierCircuitDigest);
         }
-        let proof_with_pis = circuit_data.prove(partial_witness)?;
+        let partial_witness = self.fill(
+            compiled_circuit.targets.clone(),
+            compiled_circuit.out_targets,
+        )?;
+        let proof_with_pis = compiled_circuit.circuit_data.prove(partial_witness)?;
         Ok(ProofData {
-            circuit_data,
+            circuit_data: compiled_circuit.circuit_data.clone(),
             proof_with_pis,
         })
     }
@@ -0,0 +1,339 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, RwLock},
+};
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    plonk::{
+        circuit_data::{CircuitConfig, CommonCircuitData},
+        config::{AlgebraicHasher, GenericConfig},
+    },
+};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    components::{
+        allow_list::CircuitDigestAllowList,
+        leaf_circuit::{CompiledLeafCircuit, LeafCircuit},
+        user_proof::UserProof,
+    },
+    traits::{circuit_compiler::CircuitCompiler, proof::Proof},
+};
+
+/// `LeafCircuitKey` identifies the `LeafCircuit` needed to aggregate a user proof. Two user proofs
+/// with the same key are verified by the exact same leaf circuit, even if they come from different
+/// user circuits, as the leaf circuit only depends on the shape of the user circuit. Keys are hashed
+/// by a digest of the user `CommonCircuitData`, the public inputs layout and the allow-list height
+/// only, as neither `CommonCircuitData` nor `CircuitConfig` implement `Hash`.
+///
+/// # Fields
+///
+/// * `user_common_data`: The `CommonCircuitData` of the user circuit.
+/// * `user_common_data_digest`: The digest of `user_common_data`, see [`common_data_digest`].
+/// * `user_public_inputs_layout`: The length of each of the user public inputs.
+/// * `allow_list_height`: The height of the allow-list of user circuits, if any.
+/// * `config`: The `CircuitConfig` of the leaf circuit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct LeafCircuitKey<F, const D: usize>
+where
+    F: RichField + Extendable<D>,
+{
+    user_common_data: CommonCircuitData<F, D>,
+    user_common_data_digest: u64,
+    user_public_inputs_layout: Vec<usize>,
+    allow_list_height: Option<usize>,
+    config: CircuitConfig,
+}
+
+impl<F, const D: usize> LeafCircuitKey<F, D>
+where
+    F: RichField + Extendable<D>,
+{
+    fn new<C>(
+        user_proof: &UserProof<C, F, D>,
+        allow_list_height: Option<usize>,
+        config: &CircuitConfig,
+    ) -> Self
+    where
+        C: GenericConfig<D, F = F>,
+        C::Hasher: AlgebraicHasher<F>,
+    {
+        let user_common_data = &user_proof.proof().circuit_data.common;
+        Self {
+            user_common_data: user_common_data.clone(),
+            user_common_data_digest: common_data_digest(user_common_data),
+            user_public_inputs_layout: user_proof
+                .user_public_inputs()
+                .iter()
+                .map(|user_public_inputs| user_public_inputs.len())
+                .collect(),
+            allow_list_height,
+            config: config.clone(),
+        }
+    }
+}
+
+impl<F, const D: usize> Hash for LeafCircuitKey<F, D>
+where
+    F: RichField + Extendable<D>,
+{
+    fn hash<S: Hasher>(&self, state: &mut S) {
+        self.user_common_data_digest.hash(state);
+        self.user_public_inputs_layout.hash(state);
+        self.allow_list_height.hash(state);
+    }
+}
+
+/// Digests the shape of a circuit described by its `CommonCircuitData`, i.e. its degree, gates and
+/// numbers of constants, public inputs, gate constraints and partial products. Equal common data
+/// always have the same digest.
+fn common_data_digest<F, const D: usize>(common_data: &CommonCircuitData<F, D>) -> u64
+where
+    F: RichField + Extendable<D>,
+{
+    let mut hasher = DefaultHasher::new();
+    common_data.degree_bits().hash(&mut hasher);
+    common_data.gates.hash(&mut hasher);
+    common_data.num_constants.hash(&mut hasher);
+    common_data.num_public_inputs.hash(&mut hasher);
+    common_data.num_gate_constraints.hash(&mut hasher);
+    common_data.num_partial_products.hash(&mut hasher);
+    hasher.finish()
+}
+
+type CompiledLeafCircuits<F, C, const D: usize> =
+    HashMap<LeafCircuitKey<F, D>, Arc<CompiledLeafCircuit<F, C, D>>>;
+
+/// `LeafCircuitCache` stores the compiled `LeafCircuit`s, keyed by the `CommonCircuitData` and
+/// public inputs layout of the user circuits they verify, by the height of their allow-list and by
+/// their `CircuitConfig`. Many user proofs usually come from the same user circuit, or from user
+/// circuits of the same shape, in which case the leaf circuit is built once and only proving is
+/// performed for each leaf. The cache is
+/// thread safe, and can be shared across `ZkTree` constructions.
+///
+/// # Type Parameters
+///
+/// * `C`: The configuration of the circuit, satisfying `GenericConfig`.
+/// * `F`: The field type that must implement `RichField` and `Extendable<D>`.
+/// * `D`: The dimension of the field extension, specified as a compile-time constant.
+///
+/// # Fields
+///
+/// * `compiled_leaf_circuits`: The compiled leaf circuits, by their keys.
+pub struct LeafCircuitCache<C, F, const D: usize>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+{
+    compiled_leaf_circuits: RwLock<CompiledLeafCircuits<F, C, D>>,
+}
+
+impl<C, F, const D: usize> LeafCircuitCache<C, F, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+{
+    /// Creates a new empty `LeafCircuitCache`.
+    pub fn new() -> Self {
+        Self {
+            compiled_leaf_circuits: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the number of compiled leaf circuits stored in the cache.
+    pub fn len(&self) -> usize {
+        self.compiled_leaf_circuits
+            .read()
+            .expect("Leaf circuit cache lock is poisoned")
+            .len()
+    }
+
+    /// Returns `true` if the cache does not store any compiled leaf circuit.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Retrieves the compiled `LeafCircuit` for the given user proof, compiling and storing it in
+    /// the cache if no user proof with the same `CommonCircuitData` and public inputs layout has
+    /// been seen before with an allow-list of the same height.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_proof`: A reference to the `UserProof` to be verified by the leaf circuit.
+    /// * `allow_list`: An optional allow-list of user circuits the leaf circuit checks membership
+    ///   of the user circuit digest against.
+    ///
+    /// # Returns
+    ///
+    /// The compiled `LeafCircuit` for `user_proof`.
+    pub fn get_or_compile<H>(
+        &self,
+        user_proof: &UserProof<C, F, D>,
+        allow_list: Option<&CircuitDigestAllowList<F, H>>,
+    ) -> Arc<CompiledLeafCircuit<F, C, D>>
+    where
+        C: GenericConfig<D, F = F, Hasher = H>,
+        H: AlgebraicHasher<F>,
+    {
+        self.get_or_compile_with_config(
+            user_proof,
+            allow_list,
+            &CircuitConfig::standard_recursion_zk_config(),
+        )
+    }
+
+    /// Retrieves the compiled `LeafCircuit` for the given user proof as
+    /// [`LeafCircuitCache::get_or_compile`] does, for a leaf circuit built with `config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_proof`: A reference to the `UserProof` to be verified by the leaf circuit.
+    /// * `allow_list`: An optional allow-list of user circuits the leaf circuit checks membership
+    ///   of the user circuit digest against.
+    /// * `config`: The `CircuitConfig` of the leaf circuit.
+    ///
+    /// # Returns
+    ///
+    /// The compiled `LeafCircuit` for `user_proof`.
+    pub fn get_or_compile_with_config<H>(
+        &self,
+        user_proof: &UserProof<C, F, D>,
+        allow_list: Option<&CircuitDigestAllowList<F, H>>,
+        config: &CircuitConfig,
+    ) -> Arc<CompiledLeafCircuit<F, C, D>>
+    where
+        C: GenericConfig<D, F = F, Hasher = H>,
+        H: AlgebraicHasher<F>,
+    {
+        let key = LeafCircuitKey::new(user_proof, allow_list.map(|a| a.height()), config);
+        if let Some(compiled_leaf_circuit) = self.get(&key) {
+            return compiled_leaf_circuit;
+        }
+
+        // compile outside of the lock, so that other leaf circuits can be retrieved meanwhile
+        let compiled_leaf_circuit = Arc::new(compile_leaf_circuit(user_proof, allow_list, config));
+        self.insert(key, compiled_leaf_circuit)
+    }
+
+    /// Retrieves the compiled `LeafCircuit` of each of the given user proofs as
+    /// [`LeafCircuitCache::get_or_compile_with_config`] does. The missing leaf circuits are compiled
+    /// in parallel, each exactly once however many of the user proofs it verifies, so that they
+    /// can then be shared by the parallel proving of the leaves.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_proofs`: The `UserProof`s to be verified by the leaf circuits.
+    /// * `allow_list`: An optional allow-list of user circuits the leaf circuits check membership
+    ///   of the user circuit digests against.
+    /// * `config`: The `CircuitConfig` of the leaf circuits.
+    ///
+    /// # Returns
+    ///
+    /// The compiled `LeafCircuit` of each of `user_proofs`, in the same order.
+    pub(crate) fn get_or_compile_all_with_config<H>(
+        &self,
+        user_proofs: &[&UserProof<C, F, D>],
+        allow_list: Option<&CircuitDigestAllowList<F, H>>,
+        config: &CircuitConfig,
+    ) -> Vec<Arc<CompiledLeafCircuit<F, C, D>>>
+    where
+        C: GenericConfig<D, F = F, Hasher = H>,
+        H: AlgebraicHasher<F> + Send + Sync,
+    {
+        let keys = user_proofs
+            .iter()
+            .map(|user_proof| {
+                LeafCircuitKey::new(*user_proof, allow_list.map(|a| a.height()), config)
+            })
+            .collect::<Vec<_>>();
+
+        let mut missing_user_proofs = HashMap::new();
+        {
+            let compiled_leaf_circuits = self
+                .compiled_leaf_circuits
+                .read()
+                .expect("Leaf circuit cache lock is poisoned");
+            for (key, user_proof) in keys.iter().zip(user_proofs) {
+                if !compiled_leaf_circuits.contains_key(key) {
+                    missing_user_proofs.entry(key).or_insert(*user_proof);
+                }
+            }
+        }
+
+        missing_user_proofs
+            .into_iter()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(key, user_proof)| {
+                (
+                    key.clone(),
+                    Arc::new(compile_leaf_circuit(user_proof, allow_list, config)),
+                )
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .for_each(|(key, compiled_leaf_circuit)| {
+                self.insert(key, compiled_leaf_circuit);
+            });
+
+        keys.iter()
+            .map(|key| {
+                self.get(key)
+                    .expect("Leaf circuit should have been compiled")
+            })
+            .collect()
+    }
+
+    fn get(&self, key: &LeafCircuitKey<F, D>) -> Option<Arc<CompiledLeafCircuit<F, C, D>>> {
+        self.compiled_leaf_circuits
+            .read()
+            .expect("Leaf circuit cache lock is poisoned")
+            .get(key)
+            .cloned()
+    }
+
+    fn insert(
+        &self,
+        key: LeafCircuitKey<F, D>,
+        compiled_leaf_circuit: Arc<CompiledLeafCircuit<F, C, D>>,
+    ) -> Arc<CompiledLeafCircuit<F, C, D>> {
+        let mut compiled_leaf_circuits = self
+            .compiled_leaf_circuits
+            .write()
+            .expect("Leaf circuit cache lock is poisoned");
+        // another thread might have compiled the same leaf circuit in the meantime
+        compiled_leaf_circuits
+            .entry(key)
+            .or_insert(compiled_leaf_circuit)
+            .clone()
+    }
+}
+
+fn compile_leaf_circuit<C, F, H, const D: usize>(
+    user_proof: &UserProof<C, F, D>,
+    allow_list: Option<&CircuitDigestAllowList<F, H>>,
+    config: &CircuitConfig,
+) -> CompiledLeafCircuit<F, C, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F>,
+{
+    LeafCircuit::<C, F, H, D>::new(user_proof)
+        .with_allow_list(allow_list)
+        .with_config(config.clone())
+        .build_compiled_circuit()
+}
+
+impl<C, F, const D: usize> Default for LeafCircuitCache<C, F, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,20 +1,25 @@
 use std::marker::PhantomData;
 
-use anyhow::Error;
 use plonky2::{
     field::extension::Extendable,
-    hash::{
-        hash_types::{HashOut, RichField},
-        poseidon::PoseidonHash,
-    },
-    plonk::config::{AlgebraicHasher, GenericConfig, Hasher},
+    hash::hash_types::{HashOut, RichField},
+    plonk::config::{AlgebraicHasher, GenericConfig},
+    util::serialization::{Buffer, GateSerializer, WitnessGeneratorSerializer},
 };
 
 use crate::{
-    components::leaf_circuit::LeafCircuit,
+    circuit_commitment::leaf_circuit_hash,
+    components::allow_list::CircuitDigestAllowList,
+    components::leaf_circuit::{CompiledLeafCircuit, LeafCircuit},
     components::user_proof::UserProof,
+    error::ZkTreeError,
+    input_commitment::{leaf_input_hash, padding_input_hash},
     proof_data::ProofData,
-    traits::{proof::Proof, provable::Provable},
+    serialization::{check_public_input_hashes, read_hash, write_hash},
+    traits::{
+        circuit_compiler::CircuitCompiler, proof::Proof, provable::Provable,
+        serializable::Serializable,
+    },
 };
 
 /// `LeafProof` is a structure representing a proof for a leaf node in a zkTree.
@@ -34,6 +39,7 @@ use crate::{
 /// * `user_circuit_hash`: A cryptographic hash representing the user's circuit.
 /// * `proof_data`: The proof data related to the user's interactions with the circuit.
 /// * `_phantom_data`: `PhantomData` used to mark the usage of the hasher type `H`.
+#[derive(Clone)]
 pub struct LeafProof<C, F, H, const D: usize>
 where
     F: RichField + Extendable<D>,
@@ -85,20 +91,46 @@ where
     ///
     /// # Returns
     ///
-    /// Returns a `Result` that is `Ok(Self)` if the `LeafProof` is successfully created, or an `Error` if
-    /// any operation within the proof generation fails.
+    /// Returns a `Result` that is `Ok(Self)` if the `LeafProof` is successfully created, or a
+    /// `ZkTreeError` if any operation within the proof generation fails.
     ///
     /// # Errors
     ///
-    /// This function can return an `Error` if the proof data generation fails.
-    pub fn new_from_user_proof(user_proof: &UserProof<C, F, D>) -> Result<Self, Error> {
+    /// This function can return a `ZkTreeError` if the user public inputs or the user circuit hash
+    /// do not match the proof, or if the proof data generation fails.
+    pub fn new_from_user_proof(user_proof: &UserProof<C, F, D>) -> Result<Self, ZkTreeError> {
+        user_proof.check_public_inputs()?;
+        user_proof.check_circuit_hash()?;
+        let compiled_leaf_circuit = LeafCircuit::new(user_proof).build_compiled_circuit();
+        Self::new_from_user_proof_with_compiled_circuit(user_proof, None, &compiled_leaf_circuit)
+    }
+
+    /// Constructs a new `LeafProof` from a `UserProof`, reusing an already compiled `LeafCircuit`
+    /// (see [`LeafCircuitCache`](crate::components::leaf_circuit_cache::LeafCircuitCache)). Only
+    /// the witness generation and proving are performed.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_proof`: A reference to the `UserProof` from which to generate the `LeafProof`.
+    /// * `allow_list`: An optional allow-list of user circuits `user_proof` must belong to.
+    /// * `compiled_leaf_circuit`: A `LeafCircuit` compiled for user proofs with the same
+    ///   `CommonCircuitData` and public inputs layout as `user_proof`, and for `allow_list`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` that is `Ok(Self)` if the `LeafProof` is successfully created, or a
+    /// `ZkTreeError` if any operation within the proof generation fails.
+    pub fn new_from_user_proof_with_compiled_circuit(
+        user_proof: &UserProof<C, F, D>,
+        allow_list: Option<&CircuitDigestAllowList<F, H>>,
+        compiled_leaf_circuit: &CompiledLeafCircuit<F, C, D>,
+    ) -> Result<Self, ZkTreeError> {
         let user_proof_public_inputs = user_proof.user_public_inputs();
-        let hash_user_public_inputs =
-            PoseidonHash::hash_or_noop(&user_proof_public_inputs.concat());
+        let hash_user_public_inputs = leaf_input_hash::<F, H>(&user_proof_public_inputs.concat());
         let user_circuit_hash = user_proof.circuit_hash();
 
-        let leaf_circuit = LeafCircuit::new(user_proof);
-        let proof_data = leaf_circuit.proof()?;
+        let leaf_circuit = LeafCircuit::new(user_proof).with_allow_list(allow_list);
+        let proof_data = leaf_circuit.proof_with_compiled_circuit(compiled_leaf_circuit)?;
         Ok(Self {
             hash_user_public_inputs,
             proof_data,
@@ -106,6 +138,54 @@ where
             _phantom_data: PhantomData,
         })
     }
+
+    /// Constructs a new padding `LeafProof`. Padding leaves are used to fill a zkTree up to a
+    /// power of two number of leaves. They verify the given `UserProof` with the same leaf circuit
+    /// as regular leaves, but commit to the canonical padding input hash and a zero user circuit
+    /// hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_proof`: A reference to a `UserProof` whose circuit the padding leaf is built for.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` that is `Ok(Self)` if the padding `LeafProof` is successfully created, or
+    /// a `ZkTreeError` if the proof generation fails.
+    pub fn new_padding(user_proof: &UserProof<C, F, D>) -> Result<Self, ZkTreeError> {
+        user_proof.check_public_inputs()?;
+        let compiled_leaf_circuit = LeafCircuit::new_padding(user_proof).build_compiled_circuit();
+        Self::new_padding_with_compiled_circuit(user_proof, None, &compiled_leaf_circuit)
+    }
+
+    /// Constructs a new padding `LeafProof`, reusing an already compiled `LeafCircuit`. See
+    /// [`LeafProof::new_padding`].
+    ///
+    /// # Arguments
+    ///
+    /// * `user_proof`: A reference to a `UserProof` whose circuit the padding leaf is built for.
+    /// * `allow_list`: An optional allow-list of user circuits `user_proof` must belong to.
+    /// * `compiled_leaf_circuit`: A `LeafCircuit` compiled for user proofs with the same
+    ///   `CommonCircuitData` and public inputs layout as `user_proof`, and for `allow_list`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` that is `Ok(Self)` if the padding `LeafProof` is successfully created, or
+    /// a `ZkTreeError` if the proof generation fails.
+    pub fn new_padding_with_compiled_circuit(
+        user_proof: &UserProof<C, F, D>,
+        allow_list: Option<&CircuitDigestAllowList<F, H>>,
+        compiled_leaf_circuit: &CompiledLeafCircuit<F, C, D>,
+    ) -> Result<Self, ZkTreeError> {
+        let leaf_circuit = LeafCircuit::new_padding(user_proof).with_allow_list(allow_list);
+        let proof_data = leaf_circuit.proof_with_compiled_circuit(compiled_leaf_circuit)?;
+        Ok(Self {
+            hash_user_public_inputs: padding_input_hash::<F, H>(),
+            proof_data,
+            user_circuit_hash: HashOut::ZERO,
+            _phantom_data: PhantomData,
+        })
+    }
 }
 
 impl<C, F, H, const D: usize> Proof<C, F, D> for LeafProof<C, F, H, D>
@@ -115,11 +195,7 @@ where
     H: AlgebraicHasher<F>,
 {
     fn circuit_hash(&self) -> HashOut<F> {
-        let user_circuit_hash = self.user_circuit_hash;
-        let circuit_verifier_hash = self.circuit_verifier_digest();
-        PoseidonHash::hash_or_noop(
-            &[circuit_verifier_hash.elements, user_circuit_hash.elements].concat(),
-        )
+        leaf_circuit_hash::<F, H>(self.circuit_verifier_digest(), self.user_circuit_hash)
     }
 
     fn circuit_verifier_digest(&self) -> HashOut<F> {
@@ -138,3 +214,39 @@ where
         vec![]
     }
 }
+
+impl<C, F, H, const D: usize> Serializable<F, D> for LeafProof<C, F, H, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F>,
+{
+    fn write(
+        &self,
+        buffer: &mut Vec<u8>,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<(), ZkTreeError> {
+        write_hash(buffer, self.hash_user_public_inputs)?;
+        write_hash(buffer, self.user_circuit_hash)?;
+        self.proof_data
+            .write(buffer, gate_serializer, generator_serializer)
+    }
+
+    fn read(
+        buffer: &mut Buffer,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<Self, ZkTreeError> {
+        let hash_user_public_inputs = read_hash(buffer)?;
+        let user_circuit_hash = read_hash(buffer)?;
+        let proof_data = ProofData::read(buffer, gate_serializer, generator_serializer)?;
+        let leaf_proof = Self::new(hash_user_public_inputs, user_circuit_hash, proof_data);
+        check_public_input_hashes(
+            &leaf_proof.proof_data.proof_with_pis.public_inputs,
+            leaf_proof.input_hash(),
+            leaf_proof.circuit_hash(),
+        )?;
+        Ok(leaf_proof)
+    }
+}
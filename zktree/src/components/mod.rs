@@ -1,6 +1,16 @@
+pub mod allow_list;
+pub mod cyclic_node_circuit;
+pub mod cyclic_node_proof;
 pub mod leaf_circuit;
+pub mod leaf_circuit_cache;
 pub mod leaf_proof;
 pub mod node_circuit;
 pub mod node_proof;
+pub mod padded_user_circuit;
+pub mod shrink_circuit;
+pub mod shrink_proof;
+#[cfg(test)]
 mod tests;
 pub mod user_proof;
+pub mod wrapped_proof;
+pub mod wrapper_circuit;
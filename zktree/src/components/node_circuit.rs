@@ -1,22 +1,25 @@
 use std::marker::PhantomData;
 
-use anyhow::{anyhow, Error};
 use plonky2::{
     field::extension::Extendable,
-    hash::{
-        hash_types::{HashOut, HashOutTarget, RichField},
-        poseidon::PoseidonHash,
-    },
+    hash::hash_types::{HashOut, HashOutTarget, RichField},
     iop::witness::{PartialWitness, WitnessWrite},
     plonk::{
         circuit_builder::CircuitBuilder,
         circuit_data::{CircuitConfig, CircuitData, VerifierCircuitTarget},
-        config::{AlgebraicHasher, GenericConfig, Hasher},
+        config::{AlgebraicHasher, GenericConfig},
         proof::ProofWithPublicInputsTarget,
     },
 };
 
 use crate::{
+    circuit_commitment::{node_circuit_hash, node_circuit_hash_circuit},
+    compiled_circuit::CompiledCircuit,
+    components::allow_list::{
+        ALLOW_LIST_ROOT_OFFSET, NUM_PUBLIC_INPUTS, NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST,
+    },
+    error::ZkTreeError,
+    input_commitment::{node_input_hash, node_input_hash_circuit},
     proof_data::ProofData,
     traits::{
         proof::Proof,
@@ -25,9 +28,76 @@ use crate::{
     },
 };
 
+/// Input targets of a `NodeCircuit`: the child proofs, the child verifier data, the child input
+/// hashes, the child circuit hashes and the node verifier circuit digest.
+pub type NodeCircuitTargets<const D: usize> = (
+    Vec<ProofWithPublicInputsTarget<D>>,
+    Vec<VerifierCircuitTarget>,
+    Vec<HashOutTarget>,
+    Vec<HashOutTarget>,
+    HashOutTarget,
+);
+
+/// Output targets of a `NodeCircuit`: the node circuit hash and the node input hash.
+pub type NodeCircuitOutTargets = (HashOutTarget, HashOutTarget);
+
+/// A `NodeCircuit` compiled once and shared by all the nodes whose children have the same
+/// `CommonCircuitData`, e.g. all the nodes at a given height of a zkTree.
+pub type CompiledNodeCircuit<F, C, const D: usize> =
+    CompiledCircuit<F, C, NodeCircuitTargets<D>, NodeCircuitOutTargets, D>;
+
+/// Checks that the children of a node expose the public inputs of leaf or node proofs, with or
+/// without an allow-list root, and all as many as the first child. A `NodeCircuit` compiled for
+/// other children could not be satisfied.
+pub(crate) fn check_children_public_inputs<C, F, P, const D: usize>(
+    children: &[&P],
+) -> Result<(), ZkTreeError>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    P: Proof<C, F, D>,
+{
+    if children.is_empty() {
+        return Ok(());
+    }
+    let num_public_inputs = |child: &P| child.proof().proof_with_pis.public_inputs.len();
+    let expected = num_public_inputs(children[0]);
+    if expected != NUM_PUBLIC_INPUTS && expected != NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST {
+        return Err(ZkTreeError::ChildPublicInputsLengthMismatch {
+            position: 0,
+            expected: if expected < NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST {
+                NUM_PUBLIC_INPUTS
+            } else {
+                NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST
+            },
+            actual: expected,
+        });
+    }
+    if let Some(position) = children
+        .iter()
+        .position(|child| num_public_inputs(child) != expected)
+    {
+        return Err(ZkTreeError::ChildPublicInputsLengthMismatch {
+            position,
+            expected,
+            actual: num_public_inputs(children[position]),
+        });
+    }
+    Ok(())
+}
+
+/// Checks that a node aggregates at least two children.
+pub(crate) fn check_arity(arity: usize) -> Result<(), ZkTreeError> {
+    if arity < 2 {
+        return Err(ZkTreeError::InvalidArity { arity });
+    }
+    Ok(())
+}
+
 /// `NodeCircuit` represents an internal node in a zkTree structure for zero-knowledge proofs.
-/// It contains references to proof data for its left and right children and optionally includes a verifier
-/// circuit digest that is used for verifying the integrity and correctness of the entire subtree rooted at this node.
+/// It contains references to proof data for its children, two for binary trees and `k` for
+/// `k`-ary trees, and optionally includes a verifier circuit digest that is used for verifying the
+/// integrity and correctness of the entire subtree rooted at this node.
 ///
 /// # Type Parameters
 ///
@@ -40,9 +110,11 @@ use crate::{
 ///
 /// # Fields
 ///
-/// * `left_child`: A reference to the `Proof` implementation associated with the left child of this node.
-/// * `right_child`: A reference to the `Proof` implementation associated with the right child of this node.
+/// * `children`: References to the `Proof` implementations associated with the children of this node, from left to right.
 /// * `verifier_circuit_digest`: An optional hash of the verifier circuit, which is used for verifying the proofs.
+///   It is folded into the node circuit hash without being constrained in-circuit.
+/// * `config`: The `CircuitConfig` the circuit is built with, the zero-knowledge recursion config
+///   by default.
 /// * `phantom_data`: `PhantomData` to indicate the use of the generic types `C` and `F`.
 pub struct NodeCircuit<'a, C, F, H, P, const D: usize>
 where
@@ -51,9 +123,9 @@ where
     H: AlgebraicHasher<F>,
     P: Proof<C, F, D>,
 {
-    left_child: &'a P,
-    right_child: &'a P,
+    children: Vec<&'a P>,
     verifier_circuit_digest: Option<H::Hash>,
+    config: CircuitConfig,
     phantom_data: PhantomData<(C, F)>,
 }
 
@@ -64,7 +136,7 @@ where
     H: AlgebraicHasher<F>,
     P: Proof<C, F, D>,
 {
-    /// Constructs a new `NodeCircuit` with references to the left and right child proofs.
+    /// Constructs a new binary `NodeCircuit` with references to the left and right child proofs.
     /// The `verifier_circuit_digest` is initially set to `None` and can be populated later as needed.
     ///
     /// # Arguments
@@ -76,13 +148,44 @@ where
     ///
     /// Returns a new instance of `NodeCircuit`.
     pub fn new(left_child: &'a P, right_child: &'a P) -> Self {
+        Self::new_from_children(vec![left_child, right_child])
+    }
+
+    /// Constructs a new `NodeCircuit` verifying all the given child proofs at once, whose number is
+    /// the arity of the node. The `verifier_circuit_digest` is initially set to `None` and can be
+    /// populated later as needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `children`: References to the children's proof implementations, from left to right. A
+    ///   node must have at least two children, see [`NodeProof::new_from_k_children`](crate::components::node_proof::NodeProof::new_from_k_children).
+    ///
+    /// # Returns
+    ///
+    /// Returns a new instance of `NodeCircuit`.
+    pub fn new_from_children(children: Vec<&'a P>) -> Self {
         Self {
-            left_child,
-            right_child,
+            children,
             verifier_circuit_digest: None,
+            config: CircuitConfig::standard_recursion_zk_config(),
             phantom_data: PhantomData,
         }
     }
+
+    /// Sets the `CircuitConfig` the circuit is built with, e.g. a config without zero-knowledge,
+    /// which is faster to prove for inner nodes as the leaves already hide the user proofs.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The `CircuitConfig` of the circuit.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `NodeCircuit` with the config set.
+    pub fn with_config(mut self, config: CircuitConfig) -> Self {
+        self.config = config;
+        self
+    }
 }
 
 impl<'a, C, F, H, P, const D: usize> CircuitCompiler<C, F, D> for NodeCircuit<'a, C, F, H, P, D>
@@ -92,157 +195,148 @@ where
     H: AlgebraicHasher<F>,
     P: Proof<C, F, D>,
 {
-    type Targets = (
-        [ProofWithPublicInputsTarget<D>; 2],
-        [VerifierCircuitTarget; 2],
-        [HashOutTarget; 5],
-    ); // [HashOutTarget; 4];
-    type OutTargets = (HashOutTarget, HashOutTarget);
+    type Targets = NodeCircuitTargets<D>;
+    type OutTargets = NodeCircuitOutTargets;
 
     fn compile(&self) -> (CircuitBuilder<F, D>, Self::Targets, Self::OutTargets) {
-        let mut circuit_builder =
-            CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_zk_config());
+        let mut circuit_builder = CircuitBuilder::<F, D>::new(self.config.clone());
 
         // targets for recursive proof verification
-        let left_proof_with_pis_targets = circuit_builder
-            .add_virtual_proof_with_pis(&self.left_child.proof().circuit_data.common);
-
-        let left_verifier_data_targets = circuit_builder.add_virtual_verifier_data(
-            self.left_child
-                .proof()
-                .circuit_data
-                .common
-                .config
-                .fri_config
-                .cap_height,
-        );
-
-        circuit_builder.verify_proof::<C>(
-            &left_proof_with_pis_targets,
-            &left_verifier_data_targets,
-            &self.left_child.proof().circuit_data.common,
-        );
-
-        let right_proof_with_pis_targets = circuit_builder
-            .add_virtual_proof_with_pis(&self.right_child.proof().circuit_data.common);
-
-        let right_verifier_data_targets = circuit_builder.add_virtual_verifier_data(
-            self.right_child
-                .proof()
-                .circuit_data
-                .common
-                .config
-                .fri_config
-                .cap_height,
-        );
-
-        circuit_builder.verify_proof::<C>(
-            &right_proof_with_pis_targets,
-            &right_verifier_data_targets,
-            &self.right_child.proof().circuit_data.common,
-        );
+        let (children_proof_with_pis_targets, children_verifier_data_targets): (Vec<_>, Vec<_>) =
+            self.children
+                .iter()
+                .map(|child| {
+                    let child_common_data = &child.proof().circuit_data.common;
+                    let proof_with_pis_targets =
+                        circuit_builder.add_virtual_proof_with_pis(child_common_data);
+                    let verifier_data_targets = circuit_builder
+                        .add_virtual_verifier_data(child_common_data.config.fri_config.cap_height);
+                    circuit_builder.verify_proof::<C>(
+                        &proof_with_pis_targets,
+                        &verifier_data_targets,
+                        child_common_data,
+                    );
+                    (proof_with_pis_targets, verifier_data_targets)
+                })
+                .unzip();
 
         // input hash digest verifications
-        let left_child_input_hash_targets = circuit_builder.add_virtual_hash();
-        let right_child_input_hash_targets = circuit_builder.add_virtual_hash();
+        let children_input_hashes_targets = self
+            .children
+            .iter()
+            .map(|_| circuit_builder.add_virtual_hash())
+            .collect::<Vec<_>>();
         let node_input_hash_targets = circuit_builder.add_virtual_hash();
 
         circuit_builder.register_public_inputs(&node_input_hash_targets.elements);
 
-        let should_be_node_input_hash_targets = circuit_builder
-            .hash_or_noop::<<C as GenericConfig<D>>::Hasher>(
-                [
-                    left_child_input_hash_targets.elements,
-                    right_child_input_hash_targets.elements,
-                ]
-                .concat(),
-            );
+        let should_be_node_input_hash_targets = node_input_hash_circuit::<F, H, D>(
+            &mut circuit_builder,
+            &children_input_hashes_targets,
+        );
 
         circuit_builder.connect_hashes(node_input_hash_targets, should_be_node_input_hash_targets);
 
-        let left_child_circuit_hash_targets = circuit_builder.add_virtual_hash();
-        let right_child_circuit_hash_targets = circuit_builder.add_virtual_hash();
+        let children_circuit_hashes_targets = self
+            .children
+            .iter()
+            .map(|_| circuit_builder.add_virtual_hash())
+            .collect::<Vec<_>>();
         let node_circuit_hash_targets = circuit_builder.add_virtual_hash();
 
         circuit_builder.register_public_inputs(&node_circuit_hash_targets.elements);
 
-        // the two child circuit digests must be the same
-        circuit_builder.connect_hashes(
-            left_verifier_data_targets.circuit_digest,
-            right_verifier_data_targets.circuit_digest,
-        );
+        // the child circuit digests must all be the same
+        for verifier_data_targets in &children_verifier_data_targets[1..] {
+            circuit_builder.connect_hashes(
+                children_verifier_data_targets[0].circuit_digest,
+                verifier_data_targets.circuit_digest,
+            );
+        }
 
+        // the digest of the node circuit itself is an unconstrained witness, as for the leaves, and
+        // is not tied to the digest of the children: verifiers must pin the expected digest of
+        // every level, see `ZkTree::verify_with_circuit_digests`. `CyclicNodeCircuit` constrains it
         let verifier_circuit_digest_targets = circuit_builder.add_virtual_hash();
 
-        let should_be_node_circuit_hash_targets = circuit_builder
-            .hash_or_noop::<<C as GenericConfig<D>>::Hasher>(
-                [
-                    left_child_circuit_hash_targets.elements,
-                    verifier_circuit_digest_targets.elements,
-                    right_child_circuit_hash_targets.elements,
-                ]
-                .concat(),
-            );
+        let should_be_node_circuit_hash_targets = node_circuit_hash_circuit::<F, H, D>(
+            &mut circuit_builder,
+            &children_circuit_hashes_targets,
+            verifier_circuit_digest_targets,
+        );
 
         circuit_builder.connect_hashes(
             node_circuit_hash_targets,
             should_be_node_circuit_hash_targets,
         );
 
-        // public inputs verification
+        // public inputs verification. Length mismatches are rejected natively before proving (see
+        // `check_children_public_inputs`), the circuit is only kept unsatisfiable
         let true_bool_target = circuit_builder._true();
         let false_bool_target = circuit_builder._false();
 
-        if left_proof_with_pis_targets.public_inputs.len() != 8 {
+        // children are built either with or without an allow-list, in which case all share it
+        let num_child_public_inputs = children_proof_with_pis_targets[0].public_inputs.len();
+        if num_child_public_inputs != NUM_PUBLIC_INPUTS
+            && num_child_public_inputs != NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST
+        {
             circuit_builder.connect(true_bool_target.target, false_bool_target.target);
         }
 
-        (0..4).for_each(|i| {
-            circuit_builder.connect(
-                left_proof_with_pis_targets.public_inputs[i],
-                left_child_input_hash_targets.elements[i],
-            )
-        });
-
-        // TODO: replace these values with hardcoded constants
-        (4..8).for_each(|i| {
-            circuit_builder.connect(
-                left_proof_with_pis_targets.public_inputs[i],
-                left_child_circuit_hash_targets.elements[i - 4],
-            )
-        });
-
-        if right_proof_with_pis_targets.public_inputs.len() != 8 {
-            circuit_builder.connect(true_bool_target.target, false_bool_target.target);
+        for ((proof_with_pis_targets, input_hash_targets), circuit_hash_targets) in
+            children_proof_with_pis_targets
+                .iter()
+                .zip(&children_input_hashes_targets)
+                .zip(&children_circuit_hashes_targets)
+        {
+            if proof_with_pis_targets.public_inputs.len() != num_child_public_inputs {
+                circuit_builder.connect(true_bool_target.target, false_bool_target.target);
+            }
+
+            // the input hash, then the circuit hash, lead the public inputs of a child
+            proof_with_pis_targets
+                .public_inputs
+                .iter()
+                .zip(
+                    input_hash_targets
+                        .elements
+                        .iter()
+                        .chain(&circuit_hash_targets.elements),
+                )
+                .for_each(|(public_input_target, hash_element_target)| {
+                    circuit_builder.connect(*public_input_target, *hash_element_target)
+                });
         }
 
-        (0..4).for_each(|i| {
-            circuit_builder.connect(
-                right_proof_with_pis_targets.public_inputs[i],
-                right_child_input_hash_targets.elements[i],
-            )
-        });
-
-        (4..8).for_each(|i| {
-            circuit_builder.connect(
-                right_proof_with_pis_targets.public_inputs[i],
-                right_child_circuit_hash_targets.elements[i - 4],
-            )
-        });
+        // the allow-list roots of all children must agree, and are propagated to the node
+        if num_child_public_inputs == NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST
+            && children_proof_with_pis_targets
+                .iter()
+                .all(|proof_with_pis_targets| {
+                    proof_with_pis_targets.public_inputs.len() == num_child_public_inputs
+                })
+        {
+            let allow_list_root_targets = &children_proof_with_pis_targets[0].public_inputs
+                [ALLOW_LIST_ROOT_OFFSET..NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST];
+            for proof_with_pis_targets in &children_proof_with_pis_targets[1..] {
+                (ALLOW_LIST_ROOT_OFFSET..NUM_PUBLIC_INPUTS_WITH_ALLOW_LIST).for_each(|i| {
+                    circuit_builder.connect(
+                        allow_list_root_targets[i - ALLOW_LIST_ROOT_OFFSET],
+                        proof_with_pis_targets.public_inputs[i],
+                    )
+                });
+            }
+            circuit_builder.register_public_inputs(allow_list_root_targets);
+        }
 
-        // TODO: Need to add a check that the circuit digest agrees with the left and right childs
         (
             circuit_builder,
             (
-                [left_proof_with_pis_targets, right_proof_with_pis_targets],
-                [left_verifier_data_targets, right_verifier_data_targets],
-                [
-                    left_child_input_hash_targets,
-                    right_child_input_hash_targets,
-                    left_child_circuit_hash_targets,
-                    right_child_circuit_hash_targets,
-                    verifier_circuit_digest_targets,
-                ],
+                children_proof_with_pis_targets,
+                children_verifier_data_targets,
+                children_input_hashes_targets,
+                children_circuit_hashes_targets,
+                verifier_circuit_digest_targets,
             ),
             (node_circuit_hash_targets, node_input_hash_targets),
         )
@@ -267,28 +361,24 @@ where
     type Value = (HashOut<F>, HashOut<F>);
 
     fn evaluate(&self) -> Self::Value {
-        let left_child_circuit_hash = self.left_child.circuit_hash();
-        let right_child_circuit_hash = self.right_child.circuit_hash();
-
-        let left_child_input_hash = self.left_child.input_hash();
-        let right_child_input_hash = self.right_child.input_hash();
-
-        let node_circuit_hash = PoseidonHash::hash_or_noop(
-            &[
-                left_child_circuit_hash.elements,
-                self.verifier_circuit_digest.unwrap().elements,
-                right_child_circuit_hash.elements,
-            ]
-            .concat(),
+        let children_circuit_hashes = self
+            .children
+            .iter()
+            .map(|child| child.circuit_hash())
+            .collect::<Vec<_>>();
+
+        let children_input_hashes = self
+            .children
+            .iter()
+            .map(|child| child.input_hash())
+            .collect::<Vec<_>>();
+
+        let node_circuit_hash = node_circuit_hash::<F, H>(
+            &children_circuit_hashes,
+            self.verifier_circuit_digest.unwrap(),
         );
 
-        let node_input_hash = PoseidonHash::hash_or_noop(
-            &[
-                left_child_input_hash.elements,
-                right_child_input_hash.elements,
-            ]
-            .concat(),
-        );
+        let node_input_hash = node_input_hash::<F, H>(&children_input_hashes);
 
         (node_circuit_hash, node_input_hash)
     }
@@ -297,60 +387,40 @@ where
         &self,
         targets: Self::Targets,
         out_targets: Self::OutTargets,
-    ) -> Result<PartialWitness<F>, anyhow::Error> {
+    ) -> Result<PartialWitness<F>, ZkTreeError> {
         let mut partial_witness = PartialWitness::<F>::new();
 
         let (
-            [left_proof_with_pis_targets, right_proof_with_pis_targets],
-            [left_verifier_data_targets, right_verifier_data_targets],
-            [left_child_input_hash_targets, right_child_input_hash_targets, left_child_circuit_hash_targets, right_child_circuit_hash_targets, verifier_circuit_data_targets],
+            children_proof_with_pis_targets,
+            children_verifier_data_targets,
+            children_input_hashes_targets,
+            children_circuit_hashes_targets,
+            verifier_circuit_data_targets,
         ) = targets;
 
         let (node_circuit_hash_targets, node_input_hash_targets) = out_targets;
 
-        partial_witness.set_proof_with_pis_target(
-            &left_proof_with_pis_targets,
-            &self.left_child.proof().proof_with_pis,
-        );
-        partial_witness.set_proof_with_pis_target(
-            &right_proof_with_pis_targets,
-            &self.right_child.proof().proof_with_pis,
-        );
-
-        partial_witness.set_verifier_data_target(
-            &left_verifier_data_targets,
-            &self
-                .left_child
-                .proof()
-                .circuit_data
-                .verifier_data()
-                .verifier_only,
-        );
-        partial_witness.set_verifier_data_target(
-            &right_verifier_data_targets,
-            &self
-                .right_child
-                .proof()
-                .circuit_data
-                .verifier_data()
-                .verifier_only,
-        );
-
-        partial_witness.set_hash_target(
-            left_child_circuit_hash_targets,
-            self.left_child.circuit_hash(),
-        );
-        partial_witness.set_hash_target(
-            right_child_circuit_hash_targets,
-            self.right_child.circuit_hash(),
-        );
+        if children_proof_with_pis_targets.len() != self.children.len() {
+            return Err(ZkTreeError::InvalidNumberOfChildren {
+                num_children: self.children.len(),
+                expected: children_proof_with_pis_targets.len(),
+            });
+        }
+        check_children_public_inputs(&self.children)?;
 
-        partial_witness
-            .set_hash_target(left_child_input_hash_targets, self.left_child.input_hash());
-        partial_witness.set_hash_target(
-            right_child_input_hash_targets,
-            self.right_child.input_hash(),
-        );
+        for (i, child) in self.children.iter().enumerate() {
+            partial_witness.set_proof_with_pis_target(
+                &children_proof_with_pis_targets[i],
+                &child.proof().proof_with_pis,
+            );
+            partial_witness.set_verifier_data_target(
+                &children_verifier_data_targets[i],
+                &child.proof().circuit_data.verifier_data().verifier_only,
+            );
+            partial_witness
+                .set_hash_target(children_circuit_hashes_targets[i], child.circuit_hash());
+            partial_witness.set_hash_target(children_input_hashes_targets[i], child.input_hash());
+        }
 
         partial_witness.set_hash_target(
             verifier_circuit_data_targets,
@@ -373,18 +443,23 @@ where
     H: AlgebraicHasher<F>,
     P: Proof<C, F, D>,
 {
-    fn proof(mut self) -> Result<ProofData<F, C, D>, Error> {
-        let (circuit_data, targets, out_targets) = self.compile_and_build();
-        let partial_witness = self.fill(targets, out_targets)?;
-
-        if circuit_data.verifier_only.circuit_digest != self.verifier_circuit_digest.unwrap() {
-            return Err(anyhow!("Verifier circuit digest is not valid !"));
+    fn proof_with_compiled_circuit(
+        mut self,
+        compiled_circuit: &CompiledCircuit<F, C, Self::Targets, Self::OutTargets, D>,
+    ) -> Result<ProofData<F, C, D>, ZkTreeError> {
+        let circuit_digest = compiled_circuit.circuit_data.verifier_only.circuit_digest;
+        if *self.verifier_circuit_digest.get_or_insert(circuit_digest) != circuit_digest {
+            return Err(ZkTreeError::InvalidVerifierCircuitDigest);
         }
-        let proof_with_pis = circuit_data.prove(partial_witness)?;
+        let partial_witness = self.fill(
+            compiled_circuit.targets.clone(),
+            compiled_circuit.out_targets,
+        )?;
+        let proof_with_pis = compiled_circuit.circuit_data.prove(partial_witness)?;
 
         Ok(ProofData {
             proof_with_pis,
-            circuit_data,
+            circuit_data: compiled_circuit.circuit_data.clone(),
         })
     }
 }
@@ -1,16 +1,28 @@
-use anyhow::{anyhow, Error};
 use plonky2::{
     field::extension::Extendable,
     hash::hash_types::{HashOut, RichField},
     plonk::config::{AlgebraicHasher, GenericConfig},
+    util::serialization::{Buffer, GateSerializer, WitnessGeneratorSerializer},
 };
 
 use std::marker::PhantomData;
 
 use crate::{
-    components::node_circuit::NodeCircuit,
+    circuit_commitment::node_circuit_hash,
+    components::{
+        allow_list::allow_list_root_from_public_inputs,
+        node_circuit::{
+            check_arity, check_children_public_inputs, CompiledNodeCircuit, NodeCircuit,
+        },
+    },
+    error::ZkTreeError,
+    input_commitment::node_input_hash,
     proof_data::ProofData,
-    traits::{proof::Proof, provable::Provable},
+    serialization::{check_public_input_hashes, read_hash, write_hash},
+    traits::{
+        circuit_compiler::CircuitCompiler, proof::Proof, provable::Provable,
+        serializable::Serializable,
+    },
 };
 
 /// `NodeProof` represents proof data for an internal node in a zkTree structure. It holds the combined proof data of the node's children
@@ -30,6 +42,7 @@ use crate::{
 /// * `input_hash`: The hash of the inputs to this node's circuit.
 /// * `circuit_hash`: The hash of this node's circuit.
 /// * `phantom_data`: `PhantomData` to mark the usage of the hasher type `H`.
+#[derive(Clone)]
 pub struct NodeProof<C, F, H, const D: usize>
 where
     H: AlgebraicHasher<F>,
@@ -83,8 +96,8 @@ where
     ///
     /// # Returns
     ///
-    /// A `Result` that, upon success, contains the new `NodeProof` instance, or an `Error` if the child nodes
-    /// have mismatched verifier data or if the proof generation fails.
+    /// A `Result` that, upon success, contains the new `NodeProof` instance, or a `ZkTreeError` if
+    /// the child nodes have mismatched verifier data or if the proof generation fails.
     ///
     /// # Errors
     ///
@@ -92,50 +105,98 @@ where
     pub fn new_from_children<'a, P: Proof<C, F, D>>(
         left_node_proof: &'a P,
         right_node_proof: &'a P,
-    ) -> Result<Self, Error> {
-        let left_node_input_hash = left_node_proof.input_hash();
-        let right_node_input_hash = right_node_proof.input_hash();
-        let input_hash = H::hash_or_noop(
-            &[
-                left_node_input_hash.elements,
-                right_node_input_hash.elements,
-            ]
-            .concat(),
-        );
+    ) -> Result<Self, ZkTreeError> {
+        Self::new_from_k_children(&[left_node_proof, right_node_proof])
+    }
 
-        let left_node_circuit_hash = left_node_proof.circuit_hash();
-        let right_node_circuit_hash = right_node_proof.circuit_hash();
-        let left_node_verifier_data_hash = left_node_proof
-            .proof()
-            .circuit_data
-            .verifier_only
-            .circuit_digest;
-        let right_node_verifier_data_hash = right_node_proof
-            .proof()
-            .circuit_data
-            .verifier_only
-            .circuit_digest;
-
-        if left_node_verifier_data_hash != right_node_verifier_data_hash {
-            return Err(anyhow!(
-                "Invalid circuit verifier data for node 1 and node 2"
-            ));
-        }
+    /// Constructs a new `NodeProof` aggregating any number of child nodes at once, as
+    /// [`NodeProof::new_from_children`] does for two children. Aggregating `k` children per node
+    /// divides the depth of a zkTree by `log2(k)`, at the cost of a larger node circuit.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_proofs`: References to the proofs of the child nodes, from left to right. There must
+    ///   be at least two of them.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that, upon success, contains the new `NodeProof` instance, or a `ZkTreeError` if
+    /// there are less than two children, if the child nodes have mismatched verifier data or if the
+    /// proof generation fails.
+    pub fn new_from_k_children<P: Proof<C, F, D>>(node_proofs: &[&P]) -> Result<Self, ZkTreeError> {
+        check_children_verifier_data(node_proofs)?;
+        check_children_public_inputs(node_proofs)?;
+        let compiled_node_circuit =
+            NodeCircuit::<C, F, H, P, D>::new_from_children(node_proofs.to_vec())
+                .build_compiled_circuit();
+        Self::new_from_k_children_with_compiled_circuit(node_proofs, &compiled_node_circuit)
+    }
 
-        let node_circuit = NodeCircuit::new(left_node_proof, right_node_proof);
-        let proof_data = node_circuit.proof()?;
+    /// Constructs a new `NodeProof` from the proof data of its child nodes, reusing an already
+    /// compiled `NodeCircuit`. Every node whose children share the same `CommonCircuitData` can
+    /// be proven with the same compiled circuit, so that only the witness generation and proving
+    /// are performed for each node.
+    ///
+    /// # Arguments
+    ///
+    /// * `left_node_proof`: A reference to the proof of the left child node.
+    /// * `right_node_proof`: A reference to the proof of the right child node.
+    /// * `compiled_node_circuit`: A `NodeCircuit` compiled for children with the same
+    ///   `CommonCircuitData` as `left_node_proof` and `right_node_proof`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that, upon success, contains the new `NodeProof` instance, or a `ZkTreeError` if
+    /// the child nodes have mismatched verifier data or if the proof generation fails.
+    pub fn new_from_children_with_compiled_circuit<'a, P: Proof<C, F, D>>(
+        left_node_proof: &'a P,
+        right_node_proof: &'a P,
+        compiled_node_circuit: &CompiledNodeCircuit<F, C, D>,
+    ) -> Result<Self, ZkTreeError> {
+        Self::new_from_k_children_with_compiled_circuit(
+            &[left_node_proof, right_node_proof],
+            compiled_node_circuit,
+        )
+    }
 
-        let verifier_circuit_digest = proof_data.circuit_data.verifier_only.circuit_digest;
+    /// Constructs a new `NodeProof` aggregating any number of child nodes at once, reusing an
+    /// already compiled `NodeCircuit`, see [`NodeProof::new_from_children_with_compiled_circuit`].
+    ///
+    /// # Arguments
+    ///
+    /// * `node_proofs`: References to the proofs of the child nodes, from left to right. There must
+    ///   be at least two of them.
+    /// * `compiled_node_circuit`: A `NodeCircuit` compiled for as many children as `node_proofs`,
+    ///   with the same `CommonCircuitData`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that, upon success, contains the new `NodeProof` instance, or a `ZkTreeError` if
+    /// there are less than two children, if the child nodes have mismatched verifier data or if the
+    /// proof generation fails.
+    pub fn new_from_k_children_with_compiled_circuit<P: Proof<C, F, D>>(
+        node_proofs: &[&P],
+        compiled_node_circuit: &CompiledNodeCircuit<F, C, D>,
+    ) -> Result<Self, ZkTreeError> {
+        check_children_verifier_data(node_proofs)?;
+
+        let children_input_hashes = node_proofs
+            .iter()
+            .map(|node_proof| node_proof.input_hash())
+            .collect::<Vec<_>>();
+        let input_hash = node_input_hash::<F, H>(&children_input_hashes);
+
+        let children_circuit_hashes = node_proofs
+            .iter()
+            .map(|node_proof| node_proof.circuit_hash())
+            .collect::<Vec<_>>();
+
+        let node_circuit = NodeCircuit::<C, F, H, P, D>::new_from_children(node_proofs.to_vec());
+        let proof_data = node_circuit.proof_with_compiled_circuit(compiled_node_circuit)?;
 
-        // TODO: this is duplicate code, should be removed
-        let circuit_hash = H::hash_or_noop(
-            &[
-                left_node_circuit_hash.elements,
-                verifier_circuit_digest.elements,
-                right_node_circuit_hash.elements,
-            ]
-            .concat(),
-        );
+        let verifier_circuit_digest = proof_data.circuit_data.verifier_only.circuit_digest;
+        let circuit_hash =
+            node_circuit_hash::<F, H>(&children_circuit_hashes, verifier_circuit_digest);
 
         Ok(Self {
             input_hash,
@@ -144,6 +205,41 @@ where
             phantom_data: PhantomData,
         })
     }
+
+    /// Returns the root of the allow-list of user circuits this node's subtree was built with, or
+    /// `None` if it was built without an allow-list.
+    pub fn allow_list_root(&self) -> Option<HashOut<F>> {
+        allow_list_root_from_public_inputs(&self.proof_data.proof_with_pis.public_inputs)
+    }
+}
+
+/// Checks that a node has at least two children, all sharing the same circuit verifier data.
+fn check_children_verifier_data<C, F, P, const D: usize>(
+    node_proofs: &[&P],
+) -> Result<(), ZkTreeError>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    P: Proof<C, F, D>,
+{
+    check_arity(node_proofs.len())?;
+    let first_node_verifier_data_hash = node_proofs[0]
+        .proof()
+        .circuit_data
+        .verifier_only
+        .circuit_digest;
+
+    if let Some(i) = node_proofs.iter().position(|node_proof| {
+        node_proof.proof().circuit_data.verifier_only.circuit_digest
+            != first_node_verifier_data_hash
+    }) {
+        return Err(ZkTreeError::ChildVerifierDataMismatch {
+            level: None,
+            node_index: None,
+            position: i,
+        });
+    }
+    Ok(())
 }
 
 impl<C, F, H, const D: usize> Proof<C, F, D> for NodeProof<C, F, H, D>
@@ -173,6 +269,41 @@ where
     }
 }
 
+impl<C, F, H, const D: usize> Serializable<F, D> for NodeProof<C, F, H, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    fn write(
+        &self,
+        buffer: &mut Vec<u8>,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<(), ZkTreeError> {
+        write_hash(buffer, self.input_hash)?;
+        write_hash(buffer, self.circuit_hash)?;
+        self.proof_data
+            .write(buffer, gate_serializer, generator_serializer)
+    }
+
+    fn read(
+        buffer: &mut Buffer,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<Self, ZkTreeError> {
+        let input_hash = read_hash(buffer)?;
+        let circuit_hash = read_hash(buffer)?;
+        let proof_data = ProofData::read(buffer, gate_serializer, generator_serializer)?;
+        check_public_input_hashes(
+            &proof_data.proof_with_pis.public_inputs,
+            input_hash,
+            circuit_hash,
+        )?;
+        Ok(Self::new(proof_data, input_hash, circuit_hash))
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -191,6 +322,7 @@ mod tests {
     };
 
     use super::*;
+    use std::sync::Arc;
 
     const D: usize = 2;
     const VERIFIER_CIRCUIT_DIGEST: [usize; 4] = [
@@ -258,7 +390,7 @@ mod tests {
             circuit_hash,
             ProofData {
                 proof_with_pis,
-                circuit_data,
+                circuit_data: Arc::new(circuit_data),
             },
         )
     }
@@ -298,7 +430,7 @@ mod tests {
         let should_be_circuit_hash = H::hash_or_noop(
             &[
                 left_circuit_hash.elements,
-                VERIFIER_CIRCUIT_DIGEST.map(|x| F::from_canonical_usize(x)),
+                VERIFIER_CIRCUIT_DIGEST.map(F::from_canonical_usize),
                 right_circuit_hash.elements,
             ]
             .concat(),
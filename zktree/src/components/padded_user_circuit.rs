@@ -0,0 +1,249 @@
+use std::marker::PhantomData;
+
+use plonky2::{
+    field::extension::Extendable,
+    gates::noop::NoopGate,
+    hash::hash_types::RichField,
+    iop::witness::{PartialWitness, WitnessWrite},
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::{AlgebraicHasher, GenericConfig},
+        proof::ProofWithPublicInputsTarget,
+    },
+};
+
+use crate::{
+    compiled_circuit::CompiledCircuit,
+    components::{shrink_circuit::compile_wrapper, user_proof::UserProof},
+    error::ZkTreeError,
+    proof_data::ProofData,
+    traits::{
+        circuit_compiler::CircuitCompiler, evaluate_and_fill::EvaluateFillCircuit, proof::Proof,
+        provable::Provable,
+    },
+};
+
+/// A `PaddedUserCircuit` compiled once for a given user circuit.
+pub type CompiledPaddedUserCircuit<F, C, const D: usize> =
+    CompiledCircuit<F, C, ProofWithPublicInputsTarget<D>, (), D>;
+
+/// `CommonLeafShape` is the shape every user proof is brought to before being aggregated, so that
+/// user circuits of different degrees are verified by the same leaf circuit. Each user proof is
+/// wrapped into a `PaddedUserCircuit` of degree `2^degree_bits`, whose `CommonCircuitData` is the
+/// same for all the user circuits of at most `2^max_user_degree_bits` gates with the same number
+/// of public inputs.
+///
+/// # Fields
+///
+/// * `max_user_degree_bits`: The log2 of the maximum degree of the user circuits.
+/// * `degree_bits`: The log2 of the degree of the `PaddedUserCircuit`s, which must be large enough
+///   to verify a proof of the largest user circuit.
+/// * `config`: The `CircuitConfig` of the `PaddedUserCircuit`s.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommonLeafShape {
+    pub max_user_degree_bits: usize,
+    pub degree_bits: usize,
+    pub config: CircuitConfig,
+}
+
+impl CommonLeafShape {
+    /// Constructs a new `CommonLeafShape` whose `PaddedUserCircuit`s are built with
+    /// `CircuitConfig::standard_recursion_config()`, the leaves hiding the user proofs already.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_user_degree_bits`: The log2 of the maximum degree of the user circuits.
+    /// * `degree_bits`: The log2 of the degree of the `PaddedUserCircuit`s.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new instance of `CommonLeafShape`.
+    pub fn new(max_user_degree_bits: usize, degree_bits: usize) -> Self {
+        Self {
+            max_user_degree_bits,
+            degree_bits,
+            config: CircuitConfig::standard_recursion_config(),
+        }
+    }
+
+    /// Sets the `CircuitConfig` the `PaddedUserCircuit`s are built with.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The `CircuitConfig` of the `PaddedUserCircuit`s.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `CommonLeafShape` with the config set.
+    pub fn with_config(mut self, config: CircuitConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Checks that the circuit of `user_proof` is at most of degree `2^max_user_degree_bits`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that is `Ok(())` if the user circuit fits, or a
+    /// `ZkTreeError::UserCircuitTooLarge` otherwise.
+    pub fn check_user_proof<C, F, const D: usize>(
+        &self,
+        user_proof: &UserProof<C, F, D>,
+    ) -> Result<(), ZkTreeError>
+    where
+        C: GenericConfig<D, F = F>,
+        C::Hasher: AlgebraicHasher<F>,
+        F: RichField + Extendable<D>,
+    {
+        let degree_bits = user_proof.proof().circuit_data.common.degree_bits();
+        if degree_bits > self.max_user_degree_bits {
+            return Err(ZkTreeError::UserCircuitTooLarge {
+                leaf_index: None,
+                degree_bits,
+                max_degree_bits: self.max_user_degree_bits,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// `PaddedUserCircuit` wraps a user proof into a proof of a circuit of a fixed degree, see
+/// [`CommonLeafShape`]. The circuit verifies the user proof against its verifier data, which is a
+/// constant of the circuit, so that its digest identifies the user circuit, and exposes the user
+/// public inputs unchanged. It is then padded with no-op gates up to the target degree.
+///
+/// # Type Parameters
+///
+/// * `'a`: Lifetime parameter that dictates the lifetime of the reference to the user proof.
+/// * `C`: Circuit configuration which must satisfy `GenericConfig`.
+/// * `F`: Field type that must implement `RichField` and `Extendable<D>`.
+/// * `H`: Hasher type that implements `AlgebraicHasher<F>`, the hasher of `C`.
+/// * `D`: Dimension of the field extension, a compile-time constant.
+///
+/// # Fields
+///
+/// * `user_proof`: A reference to the wrapped user proof.
+/// * `degree_bits`: The log2 of the degree the circuit is padded to.
+/// * `config`: The `CircuitConfig` the circuit is built with.
+/// * `phantom_data`: `PhantomData` to indicate the use of the generic types `F` and `H`.
+pub struct PaddedUserCircuit<'a, C, F, H, const D: usize>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    user_proof: &'a UserProof<C, F, D>,
+    degree_bits: usize,
+    config: CircuitConfig,
+    phantom_data: PhantomData<(F, H)>,
+}
+
+impl<'a, C, F, H, const D: usize> PaddedUserCircuit<'a, C, F, H, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    /// Constructs a new `PaddedUserCircuit` wrapping `user_proof` into the given shape.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_proof`: A reference to the user proof to wrap.
+    /// * `common_leaf_shape`: The shape of the circuit.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new instance of `PaddedUserCircuit`.
+    pub fn new(user_proof: &'a UserProof<C, F, D>, common_leaf_shape: &CommonLeafShape) -> Self {
+        Self {
+            user_proof,
+            degree_bits: common_leaf_shape.degree_bits,
+            config: common_leaf_shape.config.clone(),
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+impl<'a, C, F, H, const D: usize> CircuitCompiler<C, F, D> for PaddedUserCircuit<'a, C, F, H, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    type Targets = ProofWithPublicInputsTarget<D>;
+    type OutTargets = ();
+
+    fn compile(&self) -> (CircuitBuilder<F, D>, Self::Targets, Self::OutTargets) {
+        let user_proof_data = self.user_proof.proof();
+        let (mut circuit_builder, proof_with_pis_targets) = compile_wrapper(
+            &user_proof_data.circuit_data,
+            user_proof_data.proof_with_pis.public_inputs.len(),
+            self.config.clone(),
+        );
+
+        // more than half the target degree, so that building rounds the degree up to the target
+        while circuit_builder.num_gates() <= 1 << self.degree_bits.saturating_sub(1) {
+            circuit_builder.add_gate(NoopGate, vec![]);
+        }
+
+        (circuit_builder, proof_with_pis_targets, ())
+    }
+
+    fn compile_and_build(&mut self) -> (CircuitData<F, C, D>, Self::Targets, Self::OutTargets) {
+        let (circuit_builder, targets, out_targets) = self.compile();
+        (circuit_builder.build::<C>(), targets, out_targets)
+    }
+}
+
+impl<'a, C, F, H, const D: usize> EvaluateFillCircuit<C, F, D> for PaddedUserCircuit<'a, C, F, H, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    type Value = Vec<F>;
+
+    fn evaluate(&self) -> Self::Value {
+        self.user_proof.proof().proof_with_pis.public_inputs.clone()
+    }
+
+    fn fill(
+        &self,
+        targets: Self::Targets,
+        _out_targets: Self::OutTargets,
+    ) -> Result<PartialWitness<F>, ZkTreeError> {
+        let mut partial_witness = PartialWitness::<F>::new();
+        partial_witness
+            .set_proof_with_pis_target(&targets, &self.user_proof.proof().proof_with_pis);
+        Ok(partial_witness)
+    }
+}
+
+impl<'a, C, F, H, const D: usize> Provable<F, C, D> for PaddedUserCircuit<'a, C, F, H, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    fn proof_with_compiled_circuit(
+        self,
+        compiled_circuit: &CompiledCircuit<F, C, Self::Targets, Self::OutTargets, D>,
+    ) -> Result<ProofData<F, C, D>, ZkTreeError> {
+        let degree_bits = compiled_circuit.circuit_data.common.degree_bits();
+        if degree_bits != self.degree_bits {
+            return Err(ZkTreeError::CommonLeafShapeTooSmall {
+                degree_bits: self.degree_bits,
+                actual: degree_bits,
+            });
+        }
+        self.user_proof.check_public_inputs()?;
+        let partial_witness = self.fill(compiled_circuit.targets.clone(), ())?;
+        let proof_with_pis = compiled_circuit.circuit_data.prove(partial_witness)?;
+
+        Ok(ProofData {
+            proof_with_pis,
+            circuit_data: compiled_circuit.circuit_data.clone(),
+        })
+    }
+}
@@ -0,0 +1,253 @@
+use std::marker::PhantomData;
+
+use plonky2::{
+    field::extension::Extendable,
+    fri::FriConfig,
+    hash::hash_types::{HashOutTarget, RichField, NUM_HASH_OUT_ELTS},
+    iop::witness::{PartialWitness, WitnessWrite},
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::{AlgebraicHasher, GenericConfig},
+        proof::ProofWithPublicInputsTarget,
+    },
+};
+
+use crate::{
+    compiled_circuit::CompiledCircuit,
+    error::ZkTreeError,
+    proof_data::ProofData,
+    traits::{
+        circuit_compiler::CircuitCompiler, evaluate_and_fill::EvaluateFillCircuit, proof::Proof,
+        provable::Provable,
+    },
+};
+
+/// A `ShrinkCircuit` compiled once for a given child circuit, i.e. for a given zkTree root
+/// circuit or previous `ShrinkCircuit`.
+pub type CompiledShrinkCircuit<F, C, const D: usize> =
+    CompiledCircuit<F, C, ProofWithPublicInputsTarget<D>, (), D>;
+
+/// Returns a `CircuitConfig` suited to shrink a zkTree root proof: a high FRI rate lowers the
+/// number of FRI queries needed for the same security, hence the size of the proof and its
+/// verification cost, at the cost of a slower proving.
+pub fn high_rate_shrink_config() -> CircuitConfig {
+    CircuitConfig {
+        fri_config: FriConfig {
+            rate_bits: 7,
+            num_query_rounds: 12,
+            ..CircuitConfig::standard_recursion_config().fri_config
+        },
+        ..CircuitConfig::standard_recursion_config()
+    }
+}
+
+/// Starts a circuit built with `config` that verifies a proof of the circuit `child_circuit_data`,
+/// whose verifier data is a constant of the circuit, so that only proofs of this very circuit are
+/// accepted. The node public inputs of the child proof are exposed unchanged. The public inputs
+/// after them, if any, are the verifier data a cyclic child proof exposes, which are constrained to
+/// be the constant verifier data of the child circuit rather than exposed.
+///
+/// # Arguments
+///
+/// * `child_circuit_data`: The circuit data of the child proof.
+/// * `num_node_public_inputs`: The number of leading public inputs of the child proof to expose,
+///   see `Proof::node_public_inputs`.
+/// * `config`: The `CircuitConfig` the wrapper circuit is built with.
+///
+/// # Returns
+///
+/// The `CircuitBuilder` of the wrapper circuit, and the target of the child proof.
+pub(crate) fn compile_wrapper<F, C, const D: usize>(
+    child_circuit_data: &CircuitData<F, C, D>,
+    num_node_public_inputs: usize,
+    config: CircuitConfig,
+) -> (CircuitBuilder<F, D>, ProofWithPublicInputsTarget<D>)
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    C::Hasher: AlgebraicHasher<F>,
+{
+    let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+
+    let proof_with_pis_targets =
+        circuit_builder.add_virtual_proof_with_pis(&child_circuit_data.common);
+    let verifier_data_targets =
+        circuit_builder.constant_verifier_data(&child_circuit_data.verifier_only);
+    circuit_builder.verify_proof::<C>(
+        &proof_with_pis_targets,
+        &verifier_data_targets,
+        &child_circuit_data.common,
+    );
+
+    let (node_public_inputs, verifier_data_public_inputs) = proof_with_pis_targets
+        .public_inputs
+        .split_at(num_node_public_inputs);
+    let mut verifier_data_hashes = verifier_data_public_inputs
+        .chunks(NUM_HASH_OUT_ELTS)
+        .map(|elements| HashOutTarget::from_vec(elements.to_vec()));
+    if let Some(circuit_digest) = verifier_data_hashes.next() {
+        circuit_builder.connect_hashes(circuit_digest, verifier_data_targets.circuit_digest);
+        for (cap_hash, constant_cap_hash) in
+            verifier_data_hashes.zip(verifier_data_targets.constants_sigmas_cap.0.iter())
+        {
+            circuit_builder.connect_hashes(cap_hash, *constant_cap_hash);
+        }
+    }
+    circuit_builder.register_public_inputs(node_public_inputs);
+
+    (circuit_builder, proof_with_pis_targets)
+}
+
+/// `ShrinkCircuit` wraps the proof of a zkTree root into a proof of a smaller circuit, typically
+/// built with a higher FRI rate, see [`high_rate_shrink_config`]. The circuit verifies the child
+/// proof against its verifier data, which is a constant of the circuit, and exposes the node public
+/// inputs of the child proof unchanged, i.e. its input hash, its circuit hash and its allow-list
+/// root, if any.
+///
+/// # Type Parameters
+///
+/// * `'a`: Lifetime parameter that dictates the lifetime of the reference to the child proof.
+/// * `C`: Circuit configuration which must satisfy `GenericConfig`.
+/// * `F`: Field type that must implement `RichField` and `Extendable<D>`.
+/// * `H`: Hasher type that implements `AlgebraicHasher<F>`, the hasher of `C`.
+/// * `P`: Proof type that must implement the `Proof` trait, the type of the child proof.
+/// * `D`: Dimension of the field extension, a compile-time constant.
+///
+/// # Fields
+///
+/// * `child`: A reference to the wrapped proof.
+/// * `config`: The `CircuitConfig` the circuit is built with.
+/// * `phantom_data`: `PhantomData` to indicate the use of the generic types `C`, `F` and `H`.
+pub struct ShrinkCircuit<'a, C, F, H, P, const D: usize>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+    P: Proof<C, F, D> + ?Sized,
+{
+    child: &'a P,
+    config: CircuitConfig,
+    phantom_data: PhantomData<(C, F, H)>,
+}
+
+impl<'a, C, F, H, P, const D: usize> ShrinkCircuit<'a, C, F, H, P, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+    P: Proof<C, F, D> + ?Sized,
+{
+    /// Constructs a new `ShrinkCircuit` wrapping `child`, built with
+    /// [`high_rate_shrink_config`] unless another config is set with
+    /// [`ShrinkCircuit::with_config`].
+    ///
+    /// # Arguments
+    ///
+    /// * `child`: A reference to the proof to wrap.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new instance of `ShrinkCircuit`.
+    pub fn new(child: &'a P) -> Self {
+        Self {
+            child,
+            config: high_rate_shrink_config(),
+            phantom_data: PhantomData,
+        }
+    }
+
+    /// Sets the `CircuitConfig` the circuit is built with.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The `CircuitConfig` of the circuit.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `ShrinkCircuit` with the config set.
+    pub fn with_config(mut self, config: CircuitConfig) -> Self {
+        self.config = config;
+        self
+    }
+}
+
+impl<'a, C, F, H, P, const D: usize> CircuitCompiler<C, F, D> for ShrinkCircuit<'a, C, F, H, P, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+    P: Proof<C, F, D> + ?Sized,
+{
+    type Targets = ProofWithPublicInputsTarget<D>;
+    type OutTargets = ();
+
+    fn compile(&self) -> (CircuitBuilder<F, D>, Self::Targets, Self::OutTargets) {
+        let (circuit_builder, proof_with_pis_targets) = compile_wrapper(
+            &self.child.proof().circuit_data,
+            self.child.node_public_inputs().len(),
+            self.config.clone(),
+        );
+        (circuit_builder, proof_with_pis_targets, ())
+    }
+
+    fn compile_and_build(&mut self) -> (CircuitData<F, C, D>, Self::Targets, Self::OutTargets) {
+        let (circuit_builder, targets, out_targets) = self.compile();
+        (circuit_builder.build::<C>(), targets, out_targets)
+    }
+}
+
+impl<'a, C, F, H, P, const D: usize> EvaluateFillCircuit<C, F, D>
+    for ShrinkCircuit<'a, C, F, H, P, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+    P: Proof<C, F, D> + ?Sized,
+{
+    type Value = Vec<F>;
+
+    fn evaluate(&self) -> Self::Value {
+        self.child.node_public_inputs().to_vec()
+    }
+
+    fn fill(
+        &self,
+        targets: Self::Targets,
+        _out_targets: Self::OutTargets,
+    ) -> Result<PartialWitness<F>, ZkTreeError> {
+        let mut partial_witness = PartialWitness::<F>::new();
+        partial_witness.set_proof_with_pis_target(&targets, &self.child.proof().proof_with_pis);
+        Ok(partial_witness)
+    }
+}
+
+impl<'a, C, F, H, P, const D: usize> Provable<F, C, D> for ShrinkCircuit<'a, C, F, H, P, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+    P: Proof<C, F, D> + ?Sized,
+{
+    fn proof_with_compiled_circuit(
+        self,
+        compiled_circuit: &CompiledCircuit<F, C, Self::Targets, Self::OutTargets, D>,
+    ) -> Result<ProofData<F, C, D>, ZkTreeError> {
+        let expected = compiled_circuit.targets.public_inputs.len();
+        let actual = self.child.proof().proof_with_pis.public_inputs.len();
+        if actual != expected {
+            return Err(ZkTreeError::ChildPublicInputsLengthMismatch {
+                position: 0,
+                expected,
+                actual,
+            });
+        }
+        let partial_witness = self.fill(compiled_circuit.targets.clone(), ())?;
+        let proof_with_pis = compiled_circuit.circuit_data.prove(partial_witness)?;
+
+        Ok(ProofData {
+            proof_with_pis,
+            circuit_data: compiled_circuit.circuit_data.clone(),
+        })
+    }
+}
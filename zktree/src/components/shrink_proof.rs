@@ -0,0 +1,201 @@
+use std::marker::PhantomData;
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::{HashOut, RichField},
+    plonk::{
+        circuit_data::CircuitConfig,
+        config::{AlgebraicHasher, GenericConfig},
+    },
+    util::serialization::{Buffer, GateSerializer, WitnessGeneratorSerializer},
+};
+
+use crate::{
+    components::{
+        allow_list::allow_list_root_from_public_inputs,
+        shrink_circuit::{CompiledShrinkCircuit, ShrinkCircuit},
+    },
+    error::ZkTreeError,
+    proof_data::ProofData,
+    serialization::{check_public_input_hashes, read_hash, write_hash},
+    traits::{
+        circuit_compiler::CircuitCompiler, proof::Proof, provable::Provable,
+        serializable::Serializable,
+    },
+};
+
+/// `ShrinkProof` is the proof of a `ShrinkCircuit` wrapping a zkTree root proof, or a previous
+/// `ShrinkProof`. It exposes the same input hash, circuit hash and allow-list root as the wrapped
+/// proof, with a smaller proof that is cheaper to verify.
+///
+/// # Type Parameters
+///
+/// * `C`: The configuration of the circuit, which must implement `GenericConfig`.
+/// * `F`: The field type that implements `RichField` and `Extendable<D>`.
+/// * `H`: The hasher type that implements `AlgebraicHasher<F>`, the hasher of `C`.
+/// * `D`: The dimension of the field extension, specified as a compile-time constant.
+///
+/// # Fields
+///
+/// * `proof_data`: The proof data of the `ShrinkCircuit`.
+/// * `input_hash`: The input hash of the wrapped proof.
+/// * `circuit_hash`: The circuit hash of the wrapped proof.
+/// * `phantom_data`: `PhantomData` to mark the usage of the hasher type `H`.
+#[derive(Clone)]
+pub struct ShrinkProof<C, F, H, const D: usize>
+where
+    H: AlgebraicHasher<F>,
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+{
+    proof_data: ProofData<F, C, D>,
+    input_hash: HashOut<F>,
+    circuit_hash: HashOut<F>,
+    phantom_data: PhantomData<H>,
+}
+
+impl<C, F, H, const D: usize> ShrinkProof<C, F, H, D>
+where
+    H: AlgebraicHasher<F>,
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+{
+    /// Creates a new `ShrinkProof` instance using the provided proof data, input hash, and circuit
+    /// hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `proof_data`: The proof data of the `ShrinkCircuit`.
+    /// * `input_hash`: The input hash of the wrapped proof.
+    /// * `circuit_hash`: The circuit hash of the wrapped proof.
+    ///
+    /// # Returns
+    ///
+    /// A new `ShrinkProof` instance.
+    pub fn new(
+        proof_data: ProofData<F, C, D>,
+        input_hash: HashOut<F>,
+        circuit_hash: HashOut<F>,
+    ) -> Self {
+        Self {
+            proof_data,
+            input_hash,
+            circuit_hash,
+            phantom_data: PhantomData,
+        }
+    }
+
+    /// Constructs a new `ShrinkProof` wrapping `proof` into a `ShrinkCircuit` built with `config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `proof`: A reference to the proof to wrap, e.g. a zkTree root proof.
+    /// * `config`: The `CircuitConfig` of the `ShrinkCircuit`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `ShrinkProof`, or a `ZkTreeError` if the proof generation
+    /// fails.
+    pub fn new_from_proof<P: Proof<C, F, D> + ?Sized>(
+        proof: &P,
+        config: CircuitConfig,
+    ) -> Result<Self, ZkTreeError> {
+        let compiled_shrink_circuit = ShrinkCircuit::<C, F, H, P, D>::new(proof)
+            .with_config(config)
+            .build_compiled_circuit();
+        Self::new_from_proof_with_compiled_circuit(proof, &compiled_shrink_circuit)
+    }
+
+    /// Constructs a new `ShrinkProof` wrapping `proof`, reusing an already compiled
+    /// `ShrinkCircuit`.
+    ///
+    /// # Arguments
+    ///
+    /// * `proof`: A reference to the proof to wrap.
+    /// * `compiled_shrink_circuit`: A `ShrinkCircuit` compiled for the circuit of `proof`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `ShrinkProof`, or a `ZkTreeError` if the proof generation
+    /// fails.
+    pub fn new_from_proof_with_compiled_circuit<P: Proof<C, F, D> + ?Sized>(
+        proof: &P,
+        compiled_shrink_circuit: &CompiledShrinkCircuit<F, C, D>,
+    ) -> Result<Self, ZkTreeError> {
+        let proof_data = ShrinkCircuit::<C, F, H, P, D>::new(proof)
+            .proof_with_compiled_circuit(compiled_shrink_circuit)?;
+        Ok(Self::new(
+            proof_data,
+            proof.input_hash(),
+            proof.circuit_hash(),
+        ))
+    }
+
+    /// Returns the root of the allow-list of user circuits the wrapped zkTree was built with, or
+    /// `None` if it was built without an allow-list.
+    pub fn allow_list_root(&self) -> Option<HashOut<F>> {
+        allow_list_root_from_public_inputs(&self.proof_data.proof_with_pis.public_inputs)
+    }
+}
+
+impl<C, F, H, const D: usize> Proof<C, F, D> for ShrinkProof<C, F, H, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    fn user_public_inputs(&self) -> Vec<&[F]> {
+        vec![]
+    }
+
+    fn circuit_hash(&self) -> HashOut<F> {
+        self.circuit_hash
+    }
+
+    fn input_hash(&self) -> HashOut<F> {
+        self.input_hash
+    }
+
+    fn proof(&self) -> &ProofData<F, C, D> {
+        &self.proof_data
+    }
+
+    fn circuit_verifier_digest(&self) -> HashOut<F> {
+        self.proof().circuit_data.verifier_only.circuit_digest
+    }
+}
+
+impl<C, F, H, const D: usize> Serializable<F, D> for ShrinkProof<C, F, H, D>
+where
+    C: GenericConfig<D, F = F, Hasher = H>,
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    fn write(
+        &self,
+        buffer: &mut Vec<u8>,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<(), ZkTreeError> {
+        write_hash(buffer, self.input_hash)?;
+        write_hash(buffer, self.circuit_hash)?;
+        self.proof_data
+            .write(buffer, gate_serializer, generator_serializer)
+    }
+
+    fn read(
+        buffer: &mut Buffer,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<Self, ZkTreeError> {
+        let input_hash = read_hash(buffer)?;
+        let circuit_hash = read_hash(buffer)?;
+        let proof_data = ProofData::read(buffer, gate_serializer, generator_serializer)?;
+        check_public_input_hashes(
+            &proof_data.proof_with_pis.public_inputs,
+            input_hash,
+            circuit_hash,
+        )?;
+        Ok(Self::new(proof_data, input_hash, circuit_hash))
+    }
+}
@@ -1,11 +1,13 @@
 #![allow(dead_code)]
+use std::{marker::PhantomData, sync::Arc};
+
 use crate::{
     components::{leaf_proof::LeafProof, node_proof::NodeProof},
+    error::{hash_elements, ZkTreeError},
     proof_data::ProofData,
-    traits::proof::Proof,
+    serialization::SERIALIZATION_VERSION,
+    traits::{proof::Proof, serializable::Serializable},
 };
-#[cfg(tests)]
-use crate::{proof_components::node_proof::NodeProof, proof_data::ProofData, traits::proof::Proof};
 
 use plonky2::{
     field::{
@@ -19,6 +21,7 @@ use plonky2::{
         circuit_data::CircuitConfig,
         config::{Hasher, PoseidonGoldilocksConfig},
     },
+    util::serialization::{DefaultGateSerializer, DefaultGeneratorSerializer},
 };
 
 use super::user_proof::UserProof;
@@ -67,7 +70,7 @@ fn simple_circuit() -> (F, ProofData<F, C, D>) {
         .expect("Failed to generate proof");
 
     let proof_data = ProofData {
-        circuit_data,
+        circuit_data: Arc::new(circuit_data),
         proof_with_pis,
     };
 
@@ -131,7 +134,7 @@ fn simple_circuit_proof_data() -> (
         circuit_hash,
         ProofData {
             proof_with_pis,
-            circuit_data,
+            circuit_data: Arc::new(circuit_data),
         },
     )
 }
@@ -167,6 +170,41 @@ fn test_leaf_proof_2() {
         .expect("Failed to generate leaf proof from user proof");
 }
 
+#[test]
+fn test_leaf_proof_fails_with_forged_user_circuit_hash() {
+    let (c, proof_data) = simple_circuit();
+    let circuit_hash = proof_data.circuit_data.verifier_only.circuit_digest;
+
+    // claim a user circuit hash different from the digest of the circuit actually proven
+    let forged_circuit_hash = PoseidonHash::hash_or_noop(&F::rand_array::<4>());
+    let user_proof = UserProof::new(vec![vec![c]], forged_circuit_hash, proof_data);
+    assert!(matches!(
+        LeafProof::<C, F, H, D>::new_from_user_proof(&user_proof),
+        Err(ZkTreeError::UserCircuitHashMismatch {
+            leaf_index: None,
+            expected,
+            actual,
+        }) if expected == hash_elements(circuit_hash) && actual == hash_elements(forged_circuit_hash)
+    ));
+}
+
+#[test]
+fn test_leaf_proof_fails_with_public_inputs_length_mismatch() {
+    let (c, proof_data) = simple_circuit();
+    let circuit_hash = proof_data.circuit_data.verifier_only.circuit_digest;
+
+    // the user proof has a single public input
+    let user_proof = UserProof::new(vec![vec![c], vec![c]], circuit_hash, proof_data);
+    assert!(matches!(
+        LeafProof::<C, F, H, D>::new_from_user_proof(&user_proof),
+        Err(ZkTreeError::PublicInputsLengthMismatch {
+            leaf_index: None,
+            expected: 1,
+            actual: 2,
+        })
+    ));
+}
+
 #[test]
 fn test_node_proof() {
     let (left_input_hash, left_circuit_hash, left_proof_data) = simple_circuit_proof_data();
@@ -192,10 +230,225 @@ fn test_node_proof() {
     let should_be_circuit_hash = H::hash_or_noop(
         &[
             left_circuit_hash.elements,
-            VERIFIER_CIRCUIT_DIGEST.map(|x| F::from_canonical_usize(x)),
+            VERIFIER_CIRCUIT_DIGEST.map(F::from_canonical_usize),
             right_circuit_hash.elements,
         ]
         .concat(),
     );
     assert_eq!(node_proof.circuit_hash(), should_be_circuit_hash);
 }
+
+#[test]
+fn test_node_proof_with_k_children() {
+    let node_proofs = (0..4)
+        .map(|_| {
+            let (input_hash, circuit_hash, proof_data) = simple_circuit_proof_data();
+            NodeProof::new(proof_data, input_hash, circuit_hash)
+        })
+        .collect::<Vec<_>>();
+    let input_hashes = node_proofs
+        .iter()
+        .map(|node_proof| node_proof.input_hash())
+        .collect::<Vec<_>>();
+    let circuit_hashes = node_proofs
+        .iter()
+        .map(|node_proof| node_proof.circuit_hash())
+        .collect::<Vec<_>>();
+
+    let node_proof = NodeProof::new_from_k_children(&node_proofs.iter().collect::<Vec<_>>())
+        .expect("Failed to generate proof");
+
+    // the input hashes of the children are hashed in order
+    let should_be_input_hash = H::hash_or_noop(
+        &input_hashes
+            .iter()
+            .flat_map(|input_hash| input_hash.elements)
+            .collect::<Vec<_>>(),
+    );
+    assert_eq!(node_proof.input_hash(), should_be_input_hash);
+
+    // the node verifier circuit digest sits in the middle of the children circuit hashes
+    let should_be_circuit_hash = H::hash_or_noop(
+        &[
+            circuit_hashes[0].elements,
+            circuit_hashes[1].elements,
+            node_proof.circuit_verifier_digest().elements,
+            circuit_hashes[2].elements,
+            circuit_hashes[3].elements,
+        ]
+        .concat(),
+    );
+    assert_eq!(node_proof.circuit_hash(), should_be_circuit_hash);
+
+    assert!(NodeProof::<C, F, H, D>::new_from_k_children(&[&node_proofs[0]]).is_err());
+}
+
+fn generator_serializer() -> DefaultGeneratorSerializer<C, D> {
+    DefaultGeneratorSerializer {
+        _phantom: PhantomData,
+    }
+}
+
+fn assert_proof_data_eq(proof_data: &ProofData<F, C, D>, other_proof_data: &ProofData<F, C, D>) {
+    assert_eq!(proof_data.proof_with_pis, other_proof_data.proof_with_pis);
+    assert_eq!(
+        proof_data.circuit_data.verifier_only,
+        other_proof_data.circuit_data.verifier_only
+    );
+    assert_eq!(
+        proof_data.circuit_data.common,
+        other_proof_data.circuit_data.common
+    );
+}
+
+#[test]
+fn test_proof_data_serialization() {
+    let (_, proof_data) = simple_circuit();
+
+    let bytes = proof_data
+        .to_bytes(&DefaultGateSerializer, &generator_serializer())
+        .expect("Failed to serialize proof data");
+    assert_eq!(bytes[0], SERIALIZATION_VERSION);
+    let deserialized_proof_data =
+        ProofData::<F, C, D>::from_bytes(&bytes, &DefaultGateSerializer, &generator_serializer())
+            .expect("Failed to deserialize proof data");
+
+    assert_proof_data_eq(&proof_data, &deserialized_proof_data);
+    deserialized_proof_data
+        .circuit_data
+        .verify(deserialized_proof_data.proof_with_pis.clone())
+        .expect("Failed to verify deserialized proof");
+}
+
+#[test]
+fn test_proof_data_deserialization_fails_with_unknown_version() {
+    let (_, proof_data) = simple_circuit();
+
+    let mut bytes = proof_data
+        .to_bytes(&DefaultGateSerializer, &generator_serializer())
+        .expect("Failed to serialize proof data");
+    bytes[0] = SERIALIZATION_VERSION + 1;
+    assert!(ProofData::<F, C, D>::from_bytes(
+        &bytes,
+        &DefaultGateSerializer,
+        &generator_serializer()
+    )
+    .is_err());
+}
+
+#[test]
+fn test_user_proof_serialization() {
+    let (c, proof_data) = simple_circuit();
+
+    let circuit_hash = proof_data.circuit_data.verifier_only.circuit_digest;
+    let user_proof = UserProof::new(vec![vec![c], vec![c, c]], circuit_hash, proof_data);
+
+    let bytes = user_proof
+        .to_bytes(&DefaultGateSerializer, &generator_serializer())
+        .expect("Failed to serialize user proof");
+    let deserialized_user_proof =
+        UserProof::<C, F, D>::from_bytes(&bytes, &DefaultGateSerializer, &generator_serializer())
+            .expect("Failed to deserialize user proof");
+
+    assert_eq!(
+        deserialized_user_proof.user_public_inputs(),
+        user_proof.user_public_inputs()
+    );
+    assert_eq!(
+        deserialized_user_proof.circuit_hash(),
+        user_proof.circuit_hash()
+    );
+    assert_proof_data_eq(deserialized_user_proof.proof(), user_proof.proof());
+}
+
+#[test]
+fn test_leaf_and_node_proof_serialization() {
+    let (c, proof_data) = simple_circuit();
+
+    let circuit_hash = proof_data.circuit_data.verifier_only.circuit_digest;
+    let user_proof = UserProof::new(vec![vec![c]], circuit_hash, proof_data);
+    let leaf_proof = LeafProof::<C, F, H, D>::new_from_user_proof(&user_proof)
+        .expect("Failed to generate leaf proof from user proof");
+
+    let bytes = leaf_proof
+        .to_bytes(&DefaultGateSerializer, &generator_serializer())
+        .expect("Failed to serialize leaf proof");
+    let deserialized_leaf_proof = LeafProof::<C, F, H, D>::from_bytes(
+        &bytes,
+        &DefaultGateSerializer,
+        &generator_serializer(),
+    )
+    .expect("Failed to deserialize leaf proof");
+
+    assert_eq!(
+        deserialized_leaf_proof.input_hash(),
+        leaf_proof.input_hash()
+    );
+    assert_eq!(
+        deserialized_leaf_proof.circuit_hash(),
+        leaf_proof.circuit_hash()
+    );
+    assert_proof_data_eq(deserialized_leaf_proof.proof(), leaf_proof.proof());
+
+    // a deserialized proof can be aggregated further
+    let node_proof = NodeProof::new_from_children(&deserialized_leaf_proof, &leaf_proof)
+        .expect("Failed to generate node proof");
+
+    let bytes = node_proof
+        .to_bytes(&DefaultGateSerializer, &generator_serializer())
+        .expect("Failed to serialize node proof");
+    let deserialized_node_proof = NodeProof::<C, F, H, D>::from_bytes(
+        &bytes,
+        &DefaultGateSerializer,
+        &generator_serializer(),
+    )
+    .expect("Failed to deserialize node proof");
+
+    assert_eq!(
+        deserialized_node_proof.input_hash(),
+        node_proof.input_hash()
+    );
+    assert_eq!(
+        deserialized_node_proof.circuit_hash(),
+        node_proof.circuit_hash()
+    );
+    assert_proof_data_eq(deserialized_node_proof.proof(), node_proof.proof());
+}
+
+#[test]
+fn test_leaf_proof_deserialization_fails_with_tampered_hashes() {
+    let (c, proof_data) = simple_circuit();
+
+    let circuit_hash = proof_data.circuit_data.verifier_only.circuit_digest;
+    let user_proof = UserProof::new(vec![vec![c]], circuit_hash, proof_data);
+    let leaf_proof = LeafProof::<C, F, H, D>::new_from_user_proof(&user_proof)
+        .expect("Failed to generate leaf proof from user proof");
+
+    let bytes = leaf_proof
+        .to_bytes(&DefaultGateSerializer, &generator_serializer())
+        .expect("Failed to serialize leaf proof");
+
+    // the stored input hash directly follows the version byte, and is followed by the stored user
+    // circuit hash, each of them made of four 8-byte field elements
+    let mut tampered_input_hash_bytes = bytes.clone();
+    tampered_input_hash_bytes[1] ^= 1;
+    assert!(matches!(
+        LeafProof::<C, F, H, D>::from_bytes(
+            &tampered_input_hash_bytes,
+            &DefaultGateSerializer,
+            &generator_serializer(),
+        ),
+        Err(ZkTreeError::InputHashMismatch { .. })
+    ));
+
+    let mut tampered_circuit_hash_bytes = bytes;
+    tampered_circuit_hash_bytes[1 + 32] ^= 1;
+    assert!(matches!(
+        LeafProof::<C, F, H, D>::from_bytes(
+            &tampered_circuit_hash_bytes,
+            &DefaultGateSerializer,
+            &generator_serializer(),
+        ),
+        Err(ZkTreeError::CircuitHashMismatch { .. })
+    ));
+}
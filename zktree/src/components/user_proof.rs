@@ -1,13 +1,23 @@
 use plonky2::{
     field::extension::Extendable,
-    hash::{
-        hash_types::{HashOut, RichField},
-        poseidon::PoseidonHash,
-    },
-    plonk::config::{GenericConfig, Hasher},
+    hash::hash_types::{HashOut, RichField},
+    plonk::config::{AlgebraicHasher, GenericConfig},
+    util::serialization::{Buffer, GateSerializer, Read, WitnessGeneratorSerializer, Write},
 };
 
-use crate::{proof_data::ProofData, traits::proof::Proof};
+use crate::{
+    components::padded_user_circuit::{
+        CommonLeafShape, CompiledPaddedUserCircuit, PaddedUserCircuit,
+    },
+    error::{hash_elements, ZkTreeError},
+    input_commitment::leaf_input_hash,
+    proof_data::ProofData,
+    serialization::{io_error, read_hash, write_hash},
+    traits::{
+        circuit_compiler::CircuitCompiler, proof::Proof, provable::Provable,
+        serializable::Serializable,
+    },
+};
 
 pub type UserInput<F> = Vec<F>;
 
@@ -26,6 +36,7 @@ pub type UserInput<F> = Vec<F>;
 /// * `proof_data`: The proof data generated for the circuit.
 /// * `inputs`: A vector of user inputs, each being a vector of field elements.
 /// * `user_circuit_hash`: A hash output representing the circuit as used by the user.
+#[derive(Clone)]
 pub struct UserProof<C, F, const D: usize>
 where
     F: RichField + Extendable<D>,
@@ -63,11 +74,133 @@ where
             user_circuit_hash,
         }
     }
+
+    /// Checks that the user public inputs match the public inputs of the proof in number, as a
+    /// `LeafCircuit` could not be satisfied otherwise.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that is `Ok(())` if the numbers match, or a
+    /// `ZkTreeError::PublicInputsLengthMismatch` otherwise.
+    pub fn check_public_inputs(&self) -> Result<(), ZkTreeError> {
+        let expected = self.proof_data.proof_with_pis.public_inputs.len();
+        let actual = self.inputs.iter().map(Vec::len).sum::<usize>();
+        if actual != expected {
+            return Err(ZkTreeError::PublicInputsLengthMismatch {
+                leaf_index: None,
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<C, F, const D: usize> UserProof<C, F, D>
+where
+    C: GenericConfig<D, F = F>,
+    C::Hasher: AlgebraicHasher<F>,
+    F: RichField + Extendable<D>,
+{
+    /// Validates the user proof natively, i.e. checks that the user public inputs are the public
+    /// inputs of the proof, that the user circuit hash is the verifier digest of the circuit, and
+    /// that the proof verifies. A `LeafCircuit` could not be proven for an invalid user proof, so
+    /// checking it beforehand spares the recursive proving work.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that is `Ok(())` if the user proof is valid, or a `ZkTreeError` describing the
+    /// first failed check otherwise.
+    pub fn validate(&self) -> Result<(), ZkTreeError> {
+        self.check_public_inputs()?;
+        if self.inputs.concat() != self.proof_data.proof_with_pis.public_inputs {
+            return Err(ZkTreeError::PublicInputsMismatch { leaf_index: None });
+        }
+
+        self.check_circuit_hash()?;
+
+        self.proof_data
+            .circuit_data
+            .verify(self.proof_data.proof_with_pis.clone())
+            .map_err(|source| ZkTreeError::UserProofVerification {
+                leaf_index: None,
+                source,
+            })
+    }
+
+    /// Checks that the user circuit hash is the verifier digest of the circuit of the proof, as a
+    /// `LeafCircuit` could not be satisfied otherwise.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that is `Ok(())` if they match, or a `ZkTreeError::UserCircuitHashMismatch`
+    /// otherwise.
+    pub fn check_circuit_hash(&self) -> Result<(), ZkTreeError> {
+        let circuit_digest = self.proof_data.circuit_data.verifier_only.circuit_digest;
+        if self.user_circuit_hash != circuit_digest {
+            return Err(ZkTreeError::UserCircuitHashMismatch {
+                leaf_index: None,
+                expected: hash_elements(circuit_digest),
+                actual: hash_elements(self.user_circuit_hash),
+            });
+        }
+        Ok(())
+    }
+
+    /// Wraps the user proof into a `PaddedUserCircuit` of the given shape, so that it can be
+    /// aggregated with user proofs of circuits of other degrees. The padded user proof has the same
+    /// user public inputs, but its user circuit hash is the digest of the `PaddedUserCircuit`,
+    /// which identifies the user circuit.
+    ///
+    /// # Arguments
+    ///
+    /// * `common_leaf_shape`: The shape to bring the user proof to.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the padded user proof, or a `ZkTreeError` if the user circuit is too
+    /// large for the shape or the proof generation fails.
+    pub fn pad(&self, common_leaf_shape: &CommonLeafShape) -> Result<Self, ZkTreeError> {
+        common_leaf_shape.check_user_proof(self)?;
+        let compiled_padded_user_circuit =
+            PaddedUserCircuit::<C, F, C::Hasher, D>::new(self, common_leaf_shape)
+                .build_compiled_circuit();
+        self.pad_with_compiled_circuit(common_leaf_shape, &compiled_padded_user_circuit)
+    }
+
+    /// Wraps the user proof into a `PaddedUserCircuit` of the given shape, reusing an already
+    /// compiled circuit, see [`UserProof::pad`].
+    ///
+    /// # Arguments
+    ///
+    /// * `common_leaf_shape`: The shape to bring the user proof to.
+    /// * `compiled_padded_user_circuit`: A `PaddedUserCircuit` compiled for the circuit of the user
+    ///   proof and `common_leaf_shape`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the padded user proof, or a `ZkTreeError` if the proof generation
+    /// fails.
+    pub fn pad_with_compiled_circuit(
+        &self,
+        common_leaf_shape: &CommonLeafShape,
+        compiled_padded_user_circuit: &CompiledPaddedUserCircuit<F, C, D>,
+    ) -> Result<Self, ZkTreeError> {
+        let proof_data = PaddedUserCircuit::<C, F, C::Hasher, D>::new(self, common_leaf_shape)
+            .proof_with_compiled_circuit(compiled_padded_user_circuit)?;
+        let user_circuit_hash = proof_data.circuit_data.verifier_only.circuit_digest;
+        Ok(Self::new(
+            self.inputs.clone(),
+            user_circuit_hash,
+            proof_data,
+        ))
+    }
 }
 
 impl<C, F, const D: usize> Proof<C, F, D> for UserProof<C, F, D>
 where
     C: GenericConfig<D, F = F>,
+    C::Hasher: AlgebraicHasher<F>,
     F: RichField + Extendable<D>,
 {
     fn circuit_hash(&self) -> HashOut<F> {
@@ -75,7 +208,7 @@ where
     }
 
     fn input_hash(&self) -> HashOut<F> {
-        PoseidonHash::hash_or_noop(&self.inputs.concat())
+        leaf_input_hash::<F, C::Hasher>(&self.inputs.concat())
     }
 
     fn circuit_verifier_digest(&self) -> HashOut<F> {
@@ -90,3 +223,58 @@ where
         self.inputs.iter().map(AsRef::as_ref).collect::<Vec<_>>()
     }
 }
+
+impl<C, F, const D: usize> Serializable<F, D> for UserProof<C, F, D>
+where
+    C: GenericConfig<D, F = F>,
+    F: RichField + Extendable<D>,
+{
+    fn write(
+        &self,
+        buffer: &mut Vec<u8>,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<(), ZkTreeError> {
+        self.proof_data
+            .write(buffer, gate_serializer, generator_serializer)?;
+        buffer
+            .write_usize(self.inputs.len())
+            .map_err(io_error("write the number of user inputs"))?;
+        for input in &self.inputs {
+            buffer
+                .write_usize(input.len())
+                .map_err(io_error("write the length of a user input"))?;
+            buffer
+                .write_field_vec(input)
+                .map_err(io_error("write a user input"))?;
+        }
+        write_hash(buffer, self.user_circuit_hash)
+    }
+
+    fn read(
+        buffer: &mut Buffer,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<Self, ZkTreeError> {
+        let proof_data = ProofData::read(buffer, gate_serializer, generator_serializer)?;
+        let num_inputs = buffer
+            .read_usize()
+            .map_err(io_error("read the number of user inputs"))?;
+        let inputs = (0..num_inputs)
+            .map(|_| {
+                let len = buffer
+                    .read_usize()
+                    .map_err(io_error("read the length of a user input"))?;
+                buffer
+                    .read_field_vec(len)
+                    .map_err(io_error("read a user input"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let user_circuit_hash = read_hash(buffer)?;
+        Ok(Self {
+            proof_data,
+            inputs,
+            user_circuit_hash,
+        })
+    }
+}
@@ -0,0 +1,192 @@
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::{HashOut, RichField},
+    plonk::{
+        circuit_data::CircuitConfig,
+        config::{AlgebraicHasher, GenericConfig, Hasher},
+    },
+};
+
+use crate::{
+    components::{
+        allow_list::allow_list_root_from_public_inputs,
+        wrapper_circuit::{CompiledWrapperCircuit, WrapperCircuit},
+    },
+    error::ZkTreeError,
+    proof_data::ProofData,
+    root_verifier_bundle::RootVerifierBundle,
+    traits::{circuit_compiler::CircuitCompiler, proof::Proof, provable::Provable},
+};
+
+/// `WrappedProof` is the proof of a `WrapperCircuit` wrapping the final proof of a zkTree into a
+/// proof of the outer config `OC`, e.g. `KeccakGoldilocksConfig`. It exposes the same input hash,
+/// circuit hash and allow-list root as the wrapped proof. As the circuit digest of `OC` may not be
+/// a `HashOut<F>`, it does not implement the `Proof` trait and cannot be aggregated any further; it
+/// is meant to be verified natively, see [`WrappedProof::verify`], or exported with
+/// [`WrappedProof::verifier_bundle`].
+///
+/// # Type Parameters
+///
+/// * `F`: The field type that implements `RichField` and `Extendable<D>`.
+/// * `OC`: The configuration the proof is generated with, which must implement `GenericConfig`.
+/// * `D`: The dimension of the field extension, specified as a compile-time constant.
+///
+/// # Fields
+///
+/// * `proof_data`: The proof data of the `WrapperCircuit`.
+/// * `input_hash`: The input hash of the wrapped proof.
+/// * `circuit_hash`: The circuit hash of the wrapped proof.
+#[derive(Clone)]
+pub struct WrappedProof<F, OC, const D: usize>
+where
+    F: RichField + Extendable<D>,
+    OC: GenericConfig<D, F = F>,
+{
+    proof_data: ProofData<F, OC, D>,
+    input_hash: HashOut<F>,
+    circuit_hash: HashOut<F>,
+}
+
+impl<F, OC, const D: usize> WrappedProof<F, OC, D>
+where
+    F: RichField + Extendable<D>,
+    OC: GenericConfig<D, F = F>,
+{
+    /// Creates a new `WrappedProof` instance using the provided proof data, input hash, and
+    /// circuit hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `proof_data`: The proof data of the `WrapperCircuit`.
+    /// * `input_hash`: The input hash of the wrapped proof.
+    /// * `circuit_hash`: The circuit hash of the wrapped proof.
+    ///
+    /// # Returns
+    ///
+    /// A new `WrappedProof` instance.
+    pub fn new(
+        proof_data: ProofData<F, OC, D>,
+        input_hash: HashOut<F>,
+        circuit_hash: HashOut<F>,
+    ) -> Self {
+        Self {
+            proof_data,
+            input_hash,
+            circuit_hash,
+        }
+    }
+
+    /// Constructs a new `WrappedProof` wrapping `proof` into a `WrapperCircuit` built with
+    /// `config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `proof`: A reference to the proof to wrap, e.g. the final proof of a `ZkTree`.
+    /// * `config`: The `CircuitConfig` of the `WrapperCircuit`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `WrappedProof`, or a `ZkTreeError` if the proof generation
+    /// fails.
+    pub fn new_from_proof<C>(
+        proof: &dyn Proof<C, F, D>,
+        config: CircuitConfig,
+    ) -> Result<Self, ZkTreeError>
+    where
+        C: GenericConfig<D, F = F>,
+        C::Hasher: AlgebraicHasher<F>,
+    {
+        let compiled_wrapper_circuit = WrapperCircuit::<C, OC, F, D>::new(proof)
+            .with_config(config)
+            .build_compiled_circuit();
+        Self::new_from_proof_with_compiled_circuit(proof, &compiled_wrapper_circuit)
+    }
+
+    /// Constructs a new `WrappedProof` wrapping `proof`, reusing an already compiled
+    /// `WrapperCircuit`.
+    ///
+    /// # Arguments
+    ///
+    /// * `proof`: A reference to the proof to wrap.
+    /// * `compiled_wrapper_circuit`: A `WrapperCircuit` compiled for the circuit of `proof`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `WrappedProof`, or a `ZkTreeError` if the proof generation
+    /// fails.
+    pub fn new_from_proof_with_compiled_circuit<C>(
+        proof: &dyn Proof<C, F, D>,
+        compiled_wrapper_circuit: &CompiledWrapperCircuit<F, OC, D>,
+    ) -> Result<Self, ZkTreeError>
+    where
+        C: GenericConfig<D, F = F>,
+        C::Hasher: AlgebraicHasher<F>,
+    {
+        let proof_data = WrapperCircuit::<C, OC, F, D>::new(proof)
+            .proof_with_compiled_circuit(compiled_wrapper_circuit)?;
+        Ok(Self::new(
+            proof_data,
+            proof.input_hash(),
+            proof.circuit_hash(),
+        ))
+    }
+
+    /// Returns the proof data of the `WrapperCircuit`.
+    pub fn proof(&self) -> &ProofData<F, OC, D> {
+        &self.proof_data
+    }
+
+    /// Returns the input hash of the wrapped proof.
+    pub fn input_hash(&self) -> HashOut<F> {
+        self.input_hash
+    }
+
+    /// Returns the circuit hash of the wrapped proof.
+    pub fn circuit_hash(&self) -> HashOut<F> {
+        self.circuit_hash
+    }
+
+    /// Returns the root of the allow-list of user circuits the wrapped zkTree was built with, or
+    /// `None` if it was built without an allow-list.
+    pub fn allow_list_root(&self) -> Option<HashOut<F>> {
+        allow_list_root_from_public_inputs(&self.proof_data.proof_with_pis.public_inputs)
+    }
+
+    /// Exports a `RootVerifierBundle` for the wrapped proof, holding the verifier data of the
+    /// `WrapperCircuit` only, to be shipped to external verifiers.
+    pub fn verifier_bundle(&self) -> RootVerifierBundle<F, OC, D> {
+        RootVerifierBundle::new(
+            self.proof_data.circuit_data.verifier_data(),
+            self.proof_data.proof_with_pis.clone(),
+            self.input_hash,
+            self.circuit_hash,
+        )
+    }
+
+    /// Verifies the wrapped proof natively, and checks that the `WrapperCircuit` and the input and
+    /// circuit hashes it exposes are the ones the caller expects, see
+    /// [`RootVerifierBundle::verify`].
+    ///
+    /// # Arguments
+    ///
+    /// * `expected_input_hash`: The input commitment the wrapped proof must expose.
+    /// * `expected_circuit_hash`: The circuit hash the wrapped proof must expose.
+    /// * `expected_verifier_digest`: The circuit digest of the verifier data of the
+    ///   `WrapperCircuit`, pinned by the caller.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that is `Ok(())` if the proof is valid, or a `ZkTreeError` otherwise.
+    pub fn verify(
+        &self,
+        expected_input_hash: HashOut<F>,
+        expected_circuit_hash: HashOut<F>,
+        expected_verifier_digest: <OC::Hasher as Hasher<F>>::Hash,
+    ) -> Result<(), ZkTreeError> {
+        self.verifier_bundle().verify(
+            expected_input_hash,
+            expected_circuit_hash,
+            expected_verifier_digest,
+        )
+    }
+}
@@ -0,0 +1,181 @@
+use std::marker::PhantomData;
+
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    iop::witness::{PartialWitness, WitnessWrite},
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::{AlgebraicHasher, GenericConfig},
+        proof::ProofWithPublicInputsTarget,
+    },
+};
+
+use crate::{
+    compiled_circuit::CompiledCircuit,
+    components::shrink_circuit::compile_wrapper,
+    error::ZkTreeError,
+    proof_data::ProofData,
+    traits::{
+        circuit_compiler::CircuitCompiler, evaluate_and_fill::EvaluateFillCircuit, proof::Proof,
+        provable::Provable,
+    },
+};
+
+/// A `WrapperCircuit` compiled once for a given child circuit, proved with the outer config `OC`.
+pub type CompiledWrapperCircuit<F, OC, const D: usize> =
+    CompiledCircuit<F, OC, ProofWithPublicInputsTarget<D>, (), D>;
+
+/// `WrapperCircuit` wraps the final proof of a zkTree, proved with an algebraic config `C` such as
+/// `PoseidonGoldilocksConfig`, into a proof of another config `OC`, typically
+/// `KeccakGoldilocksConfig`, which is cheaper to verify for external verifiers such as smart
+/// contracts. As `OC` does not need an algebraic hasher, the wrapper is the last layer: its proof
+/// cannot be verified by another circuit. Like a `ShrinkCircuit`, it verifies the child proof
+/// against its verifier data, which is a constant of the circuit, and exposes the node public
+/// inputs of the child proof unchanged.
+///
+/// # Type Parameters
+///
+/// * `'a`: Lifetime parameter that dictates the lifetime of the reference to the child proof.
+/// * `C`: Circuit configuration of the child proof, whose hasher must implement `AlgebraicHasher`.
+/// * `OC`: Circuit configuration the wrapper circuit is proved with.
+/// * `F`: Field type that must implement `RichField` and `Extendable<D>`.
+/// * `D`: Dimension of the field extension, a compile-time constant.
+///
+/// # Fields
+///
+/// * `child`: A reference to the wrapped proof.
+/// * `config`: The `CircuitConfig` the circuit is built with.
+/// * `phantom_data`: `PhantomData` to indicate the use of the generic types `C`, `OC` and `F`.
+pub struct WrapperCircuit<'a, C, OC, F, const D: usize>
+where
+    C: GenericConfig<D, F = F>,
+    C::Hasher: AlgebraicHasher<F>,
+    OC: GenericConfig<D, F = F>,
+    F: RichField + Extendable<D>,
+{
+    child: &'a dyn Proof<C, F, D>,
+    config: CircuitConfig,
+    phantom_data: PhantomData<(C, OC, F)>,
+}
+
+impl<'a, C, OC, F, const D: usize> WrapperCircuit<'a, C, OC, F, D>
+where
+    C: GenericConfig<D, F = F>,
+    C::Hasher: AlgebraicHasher<F>,
+    OC: GenericConfig<D, F = F>,
+    F: RichField + Extendable<D>,
+{
+    /// Constructs a new `WrapperCircuit` wrapping `child`, built with
+    /// `CircuitConfig::standard_recursion_config()` unless another config is set with
+    /// [`WrapperCircuit::with_config`].
+    ///
+    /// # Arguments
+    ///
+    /// * `child`: A reference to the proof to wrap.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new instance of `WrapperCircuit`.
+    pub fn new(child: &'a dyn Proof<C, F, D>) -> Self {
+        Self {
+            child,
+            config: CircuitConfig::standard_recursion_config(),
+            phantom_data: PhantomData,
+        }
+    }
+
+    /// Sets the `CircuitConfig` the circuit is built with.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The `CircuitConfig` of the circuit.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `WrapperCircuit` with the config set.
+    pub fn with_config(mut self, config: CircuitConfig) -> Self {
+        self.config = config;
+        self
+    }
+}
+
+impl<'a, C, OC, F, const D: usize> CircuitCompiler<OC, F, D> for WrapperCircuit<'a, C, OC, F, D>
+where
+    C: GenericConfig<D, F = F>,
+    C::Hasher: AlgebraicHasher<F>,
+    OC: GenericConfig<D, F = F>,
+    F: RichField + Extendable<D>,
+{
+    type Targets = ProofWithPublicInputsTarget<D>;
+    type OutTargets = ();
+
+    fn compile(&self) -> (CircuitBuilder<F, D>, Self::Targets, Self::OutTargets) {
+        let (circuit_builder, proof_with_pis_targets) = compile_wrapper(
+            &self.child.proof().circuit_data,
+            self.child.node_public_inputs().len(),
+            self.config.clone(),
+        );
+        (circuit_builder, proof_with_pis_targets, ())
+    }
+
+    fn compile_and_build(&mut self) -> (CircuitData<F, OC, D>, Self::Targets, Self::OutTargets) {
+        let (circuit_builder, targets, out_targets) = self.compile();
+        (circuit_builder.build::<OC>(), targets, out_targets)
+    }
+}
+
+impl<'a, C, OC, F, const D: usize> EvaluateFillCircuit<OC, F, D> for WrapperCircuit<'a, C, OC, F, D>
+where
+    C: GenericConfig<D, F = F>,
+    C::Hasher: AlgebraicHasher<F>,
+    OC: GenericConfig<D, F = F>,
+    F: RichField + Extendable<D>,
+{
+    type Value = Vec<F>;
+
+    fn evaluate(&self) -> Self::Value {
+        self.child.node_public_inputs().to_vec()
+    }
+
+    fn fill(
+        &self,
+        targets: Self::Targets,
+        _out_targets: Self::OutTargets,
+    ) -> Result<PartialWitness<F>, ZkTreeError> {
+        let mut partial_witness = PartialWitness::<F>::new();
+        partial_witness.set_proof_with_pis_target(&targets, &self.child.proof().proof_with_pis);
+        Ok(partial_witness)
+    }
+}
+
+impl<'a, C, OC, F, const D: usize> Provable<F, OC, D> for WrapperCircuit<'a, C, OC, F, D>
+where
+    C: GenericConfig<D, F = F>,
+    C::Hasher: AlgebraicHasher<F>,
+    OC: GenericConfig<D, F = F>,
+    F: RichField + Extendable<D>,
+{
+    fn proof_with_compiled_circuit(
+        self,
+        compiled_circuit: &CompiledCircuit<F, OC, Self::Targets, Self::OutTargets, D>,
+    ) -> Result<ProofData<F, OC, D>, ZkTreeError> {
+        let expected = compiled_circuit.targets.public_inputs.len();
+        let actual = self.child.proof().proof_with_pis.public_inputs.len();
+        if actual != expected {
+            return Err(ZkTreeError::ChildPublicInputsLengthMismatch {
+                position: 0,
+                expected,
+                actual,
+            });
+        }
+        let partial_witness = self.fill(compiled_circuit.targets.clone(), ())?;
+        let proof_with_pis = compiled_circuit.circuit_data.prove(partial_witness)?;
+
+        Ok(ProofData {
+            proof_with_pis,
+            circuit_data: compiled_circuit.circuit_data.clone(),
+        })
+    }
+}
@@ -0,0 +1,537 @@
+//! Errors returned by the public APIs of the crate. Errors caused by the user proofs submitted for
+//! aggregation can be told apart from internal errors with [`ZkTreeError::is_user_fault`].
+use std::fmt;
+
+use plonky2::hash::hash_types::{HashOut, RichField, NUM_HASH_OUT_ELTS};
+
+/// The elements of a hash in canonical form, as carried by `ZkTreeError`.
+pub type HashElements = [u64; NUM_HASH_OUT_ELTS];
+
+/// Converts a hash into the canonical form carried by `ZkTreeError`.
+pub(crate) fn hash_elements<F: RichField>(hash: HashOut<F>) -> HashElements {
+    hash.elements.map(|element| element.to_canonical_u64())
+}
+
+/// Error returned by the public APIs of the crate.
+#[derive(Debug)]
+pub enum ZkTreeError {
+    /// The arity of a zkTree is lower than two.
+    InvalidArity { arity: usize },
+    /// The number of leaves is not a power of the arity greater than one.
+    InvalidNumberOfLeaves { num_leaves: usize, arity: usize },
+    /// The number of children of a node differs from the one its circuit was compiled for.
+    InvalidNumberOfChildren {
+        num_children: usize,
+        expected: usize,
+    },
+    /// The number of proofs of a level is not a multiple of the arity.
+    ChildrenNotMultipleOfArity { num_children: usize, arity: usize },
+    /// The child at `position` of a node does not share the verifier data of its first child,
+    /// `level` and `node_index` locating the node in its zkTree when known.
+    ChildVerifierDataMismatch {
+        level: Option<usize>,
+        node_index: Option<usize>,
+        position: usize,
+    },
+    /// The proof at `position` of a level does not share the common circuit data of the first
+    /// proof of the level.
+    CommonDataMismatch { position: usize },
+    /// The circuit was filled before being compiled.
+    MissingVerifierCircuitDigest,
+    /// The compiled circuit does not match the circuit being proven.
+    InvalidVerifierCircuitDigest,
+    /// The leaf circuit was compiled with another allow-list than the one it is proven with.
+    CompiledAllowListMismatch,
+    /// An allow-list was built without circuit digests.
+    EmptyAllowList,
+    /// The user public inputs of a user proof do not match the public inputs of its proof in
+    /// number, `leaf_index` being the index of its leaf when known.
+    PublicInputsLengthMismatch {
+        leaf_index: Option<usize>,
+        expected: usize,
+        actual: usize,
+    },
+    /// The user public inputs of a user proof are not the public inputs of its proof, `leaf_index`
+    /// being the index of its leaf when known.
+    PublicInputsMismatch { leaf_index: Option<usize> },
+    /// The user circuit hash of a user proof is not the verifier digest of its circuit, `leaf_index`
+    /// being the index of its leaf when known.
+    UserCircuitHashMismatch {
+        leaf_index: Option<usize>,
+        expected: HashElements,
+        actual: HashElements,
+    },
+    /// A user proof does not verify natively, `leaf_index` being the index of its leaf when known.
+    UserProofVerification {
+        leaf_index: Option<usize>,
+        source: anyhow::Error,
+    },
+    /// The circuit of a user proof is larger than the common leaf shape allows, `leaf_index` being
+    /// the index of its leaf when known.
+    UserCircuitTooLarge {
+        leaf_index: Option<usize>,
+        degree_bits: usize,
+        max_degree_bits: usize,
+    },
+    /// Some user proofs submitted for aggregation are invalid, each error being attributed to the
+    /// leaf of the offending user proof, in increasing leaf order.
+    InvalidUserProofs(Vec<ZkTreeError>),
+    /// The child at `position` of a node does not expose the public inputs of a leaf or node proof,
+    /// or not as many as the first child.
+    ChildPublicInputsLengthMismatch {
+        position: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// The user circuit digest is not a member of the allow-list.
+    CircuitNotAllowed { circuit_digest: HashElements },
+    /// A zkTree was built without user proofs.
+    NoUserProofs,
+    /// No zkTree was given to merge.
+    NoZkTrees,
+    /// The user proof index is out of the user proofs aggregated by the zkTree.
+    InvalidUserProofIndex {
+        index: usize,
+        num_user_proofs: usize,
+    },
+    /// The input hash of a root proof does not match the expected one.
+    InputHashMismatch {
+        expected: HashElements,
+        actual: HashElements,
+    },
+    /// The circuit hash of a root proof does not match the expected one.
+    CircuitHashMismatch {
+        expected: HashElements,
+        actual: HashElements,
+    },
+    /// The verifier data of a root proof is not the one of the expected circuit.
+    VerifierCircuitDigestMismatch,
+    /// The allow-list root of a proof does not match the expected one, `None` standing for a
+    /// proof built without an allow-list.
+    AllowListRootMismatch {
+        expected: Option<HashElements>,
+        actual: Option<HashElements>,
+    },
+    /// The root input hash recomputed from an inclusion proof does not match the expected one.
+    InclusionProofMismatch {
+        leaf_index: usize,
+        expected: HashElements,
+        actual: HashElements,
+    },
+    /// A zkTree of `num_leaves` leaves cannot aggregate `num_user_circuits` user proofs.
+    InvalidNumberOfUserCircuits {
+        num_user_circuits: usize,
+        num_leaves: usize,
+    },
+    /// The number of siblings of an inclusion proof is not a multiple of `arity - 1`.
+    InvalidNumberOfSiblings { num_siblings: usize, arity: usize },
+    /// A root proof has less public inputs than a zkTree root exposes.
+    TooFewPublicInputs { expected: usize, actual: usize },
+    /// The number of merged zkTrees differs from their arity.
+    InvalidNumberOfZkTrees { num_zktrees: usize, arity: usize },
+    /// The merged zkTree at `index` does not have the arity, height and circuit configs of the first
+    /// one.
+    IncompatibleZkTree { index: usize },
+    /// The merged zkTree at `index` has padding leaves while it is not the last one.
+    PaddedZkTree { index: usize },
+    /// The leaf of the user proof at `leaf_index` could not be proven. User proofs are validated
+    /// before being proven, so this is not attributed to the user.
+    LeafProving {
+        leaf_index: usize,
+        source: anyhow::Error,
+    },
+    /// A user circuit padded to the common leaf shape of degree `2^degree_bits` needs a degree of
+    /// `2^actual`.
+    CommonLeafShapeTooSmall { degree_bits: usize, actual: usize },
+    /// A shrink proof does not expose the public inputs of the root proof it wraps.
+    ShrinkPublicInputsMismatch,
+    /// A cyclic node circuit was configured with zero-knowledge, which cyclic recursion does not
+    /// support.
+    ZeroKnowledgeCyclicConfig,
+    /// The common data of a cyclic node circuit does not reach a fixed point within `rounds`
+    /// rounds of building the circuit.
+    CyclicCommonDataFixedPoint { rounds: usize },
+    /// A cyclic node proof does not expose the verifier data of the circuit it was proven with.
+    CyclicVerifierDataMismatch,
+    /// The serialized data was produced by an unsupported version of the encoding.
+    UnsupportedSerializationVersion { version: u8, expected: u8 },
+    /// The serialized data is malformed.
+    Serialization(String),
+    /// An error returned by plonky2 while building, proving or verifying a circuit.
+    Plonky2(anyhow::Error),
+}
+
+impl ZkTreeError {
+    /// Returns whether the error is caused by the user proofs submitted for aggregation, rather
+    /// than by the caller or the crate.
+    pub fn is_user_fault(&self) -> bool {
+        matches!(
+            self,
+            Self::PublicInputsLengthMismatch { .. }
+                | Self::PublicInputsMismatch { .. }
+                | Self::UserCircuitHashMismatch { .. }
+                | Self::UserProofVerification { .. }
+                | Self::UserCircuitTooLarge { .. }
+                | Self::InvalidUserProofs(_)
+                | Self::CircuitNotAllowed { .. }
+        )
+    }
+
+    /// Returns the index of the leaf of the user proof the error is attributed to, if any.
+    pub fn leaf_index(&self) -> Option<usize> {
+        match self {
+            Self::PublicInputsLengthMismatch { leaf_index, .. }
+            | Self::PublicInputsMismatch { leaf_index }
+            | Self::UserCircuitHashMismatch { leaf_index, .. }
+            | Self::UserProofVerification { leaf_index, .. }
+            | Self::UserCircuitTooLarge { leaf_index, .. } => *leaf_index,
+            Self::LeafProving { leaf_index, .. } => Some(*leaf_index),
+            _ => None,
+        }
+    }
+
+    /// Attributes an error raised while proving a leaf to the leaf of the user proof at
+    /// `leaf_index`.
+    pub(crate) fn at_leaf(self, leaf_index: usize) -> Self {
+        match self {
+            Self::PublicInputsLengthMismatch {
+                expected, actual, ..
+            } => Self::PublicInputsLengthMismatch {
+                leaf_index: Some(leaf_index),
+                expected,
+                actual,
+            },
+            Self::PublicInputsMismatch { .. } => Self::PublicInputsMismatch {
+                leaf_index: Some(leaf_index),
+            },
+            Self::UserCircuitHashMismatch {
+                expected, actual, ..
+            } => Self::UserCircuitHashMismatch {
+                leaf_index: Some(leaf_index),
+                expected,
+                actual,
+            },
+            Self::UserProofVerification { source, .. } => Self::UserProofVerification {
+                leaf_index: Some(leaf_index),
+                source,
+            },
+            Self::UserCircuitTooLarge {
+                degree_bits,
+                max_degree_bits,
+                ..
+            } => Self::UserCircuitTooLarge {
+                leaf_index: Some(leaf_index),
+                degree_bits,
+                max_degree_bits,
+            },
+            Self::Plonky2(source) => Self::LeafProving { leaf_index, source },
+            error => error,
+        }
+    }
+
+    /// Attributes an error raised while proving a node to the node at `node_index` within `level`,
+    /// level 0 being the one right above the leaves.
+    pub(crate) fn at_node(self, level: usize, node_index: usize) -> Self {
+        match self {
+            Self::ChildVerifierDataMismatch { position, .. } => Self::ChildVerifierDataMismatch {
+                level: Some(level),
+                node_index: Some(node_index),
+                position,
+            },
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for ZkTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidArity { arity } => write!(
+                f,
+                "Invalid arity {}, a node must aggregate at least two children",
+                arity
+            ),
+            Self::InvalidNumberOfLeaves { num_leaves, arity } => write!(
+                f,
+                "Invalid number of leaves {}, it must be a power of {} greater than one",
+                num_leaves, arity
+            ),
+            Self::InvalidNumberOfChildren {
+                num_children,
+                expected,
+            } => write!(
+                f,
+                "Invalid number of children {}, the node circuit was compiled for {}",
+                num_children, expected
+            ),
+            Self::ChildrenNotMultipleOfArity {
+                num_children,
+                arity,
+            } => write!(
+                f,
+                "Invalid number of children {}, it must be a multiple of the arity {}",
+                num_children, arity
+            ),
+            Self::ChildVerifierDataMismatch {
+                level: Some(level),
+                node_index: Some(node_index),
+                position,
+            } => write!(
+                f,
+                "Invalid circuit verifier data for child {} of node {} at level {}, all the children of a node must share it",
+                position, node_index, level
+            ),
+            Self::ChildVerifierDataMismatch { position, .. } => write!(
+                f,
+                "Invalid circuit verifier data for child {}, all the children of a node must share it",
+                position
+            ),
+            Self::CommonDataMismatch { position } => write!(
+                f,
+                "Invalid circuit common data for proof {}, all proofs at the same height must share it",
+                position
+            ),
+            Self::MissingVerifierCircuitDigest => write!(
+                f,
+                "Failed to generate the verifier circuit digest. Please compile the circuit once again"
+            ),
+            Self::InvalidVerifierCircuitDigest => write!(f, "Verifier circuit digest is not valid"),
+            Self::CompiledAllowListMismatch => write!(
+                f,
+                "The allow-list does not match the one the leaf circuit was compiled with"
+            ),
+            Self::EmptyAllowList => write!(f, "Cannot build an allow-list without circuit digests"),
+            Self::PublicInputsLengthMismatch {
+                leaf_index: Some(leaf_index),
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Invalid number of user public inputs for leaf {}, expected {} but got {}",
+                leaf_index, expected, actual
+            ),
+            Self::PublicInputsLengthMismatch {
+                leaf_index: None,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Invalid number of user public inputs, expected {} but got {}",
+                expected, actual
+            ),
+            Self::PublicInputsMismatch {
+                leaf_index: Some(leaf_index),
+            } => write!(
+                f,
+                "User public inputs for leaf {} are not the public inputs of its proof",
+                leaf_index
+            ),
+            Self::PublicInputsMismatch { leaf_index: None } => write!(
+                f,
+                "User public inputs are not the public inputs of the user proof"
+            ),
+            Self::UserCircuitHashMismatch {
+                leaf_index: Some(leaf_index),
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Invalid user circuit hash for leaf {}, expected {:?} but got {:?}",
+                leaf_index, expected, actual
+            ),
+            Self::UserCircuitHashMismatch {
+                leaf_index: None,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Invalid user circuit hash, expected {:?} but got {:?}",
+                expected, actual
+            ),
+            Self::UserProofVerification {
+                leaf_index: Some(leaf_index),
+                source,
+            } => write!(
+                f,
+                "User proof for leaf {} does not verify: {}",
+                leaf_index, source
+            ),
+            Self::UserProofVerification {
+                leaf_index: None,
+                source,
+            } => write!(f, "User proof does not verify: {}", source),
+            Self::UserCircuitTooLarge {
+                leaf_index: Some(leaf_index),
+                degree_bits,
+                max_degree_bits,
+            } => write!(
+                f,
+                "User circuit for leaf {} has degree 2^{}, more than the maximum 2^{}",
+                leaf_index, degree_bits, max_degree_bits
+            ),
+            Self::UserCircuitTooLarge {
+                leaf_index: None,
+                degree_bits,
+                max_degree_bits,
+            } => write!(
+                f,
+                "User circuit has degree 2^{}, more than the maximum 2^{}",
+                degree_bits, max_degree_bits
+            ),
+            Self::InvalidUserProofs(errors) => {
+                write!(f, "{} invalid user proofs", errors.len())?;
+                errors
+                    .iter()
+                    .try_for_each(|error| write!(f, "\n- {}", error))
+            }
+            Self::ChildPublicInputsLengthMismatch {
+                position,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Invalid number of public inputs for child {}, expected {} but got {}",
+                position, expected, actual
+            ),
+            Self::CircuitNotAllowed { circuit_digest } => write!(
+                f,
+                "User circuit digest {:?} is not in the allow-list",
+                circuit_digest
+            ),
+            Self::NoUserProofs => write!(f, "Cannot build a ZkTree without user proofs"),
+            Self::NoZkTrees => write!(f, "Cannot merge an empty list of ZkTrees"),
+            Self::InvalidUserProofIndex {
+                index,
+                num_user_proofs,
+            } => write!(
+                f,
+                "Invalid user proof index {}, the ZkTree aggregates {} user proofs",
+                index, num_user_proofs
+            ),
+            Self::InputHashMismatch { expected, actual } => write!(
+                f,
+                "Input hashes do not match, expected {:?} but got {:?}",
+                expected, actual
+            ),
+            Self::CircuitHashMismatch { expected, actual } => write!(
+                f,
+                "Circuit hashes do not match, expected {:?} but got {:?}",
+                expected, actual
+            ),
+            Self::VerifierCircuitDigestMismatch => write!(
+                f,
+                "Verifier circuit digests do not match the expected one"
+            ),
+            Self::AllowListRootMismatch {
+                expected,
+                actual: None,
+            } => write!(
+                f,
+                "Allow-list roots do not match, expected {:?} but the proof was built without an allow-list",
+                expected
+            ),
+            Self::AllowListRootMismatch { expected, actual } => write!(
+                f,
+                "Allow-list roots do not match, expected {:?} but got {:?}",
+                expected, actual
+            ),
+            Self::InclusionProofMismatch {
+                leaf_index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Inclusion proof of leaf {} does not match the root input hash, expected {:?} but got {:?}",
+                leaf_index, expected, actual
+            ),
+            Self::InvalidNumberOfUserCircuits {
+                num_user_circuits,
+                num_leaves,
+            } => write!(
+                f,
+                "Invalid number of user circuits {}, a zkTree of {} leaves aggregates between one and {} user proofs",
+                num_user_circuits, num_leaves, num_leaves
+            ),
+            Self::InvalidNumberOfSiblings {
+                num_siblings,
+                arity,
+            } => write!(
+                f,
+                "Invalid number of siblings {} for a zkTree of arity {}",
+                num_siblings, arity
+            ),
+            Self::TooFewPublicInputs { expected, actual } => write!(
+                f,
+                "Root proof has too few public inputs, expected at least {} but got {}",
+                expected, actual
+            ),
+            Self::InvalidNumberOfZkTrees { num_zktrees, arity } => write!(
+                f,
+                "Invalid number of ZkTrees {}, merging ZkTrees of arity {} requires {} of them",
+                num_zktrees, arity, arity
+            ),
+            Self::IncompatibleZkTree { index } => write!(
+                f,
+                "ZkTree {} does not have the arity, height and circuit configs of the first ZkTree",
+                index
+            ),
+            Self::PaddedZkTree { index } => write!(
+                f,
+                "ZkTree {} has padding leaves, only the last merged ZkTree can have some",
+                index
+            ),
+            Self::LeafProving { leaf_index, source } => {
+                write!(f, "Failed to prove leaf {}: {}", leaf_index, source)
+            }
+            Self::CommonLeafShapeTooSmall {
+                degree_bits,
+                actual,
+            } => write!(
+                f,
+                "Padded user circuit needs degree 2^{}, more than the 2^{} of the common leaf shape",
+                actual, degree_bits
+            ),
+            Self::ShrinkPublicInputsMismatch => write!(
+                f,
+                "Shrink proof does not expose the public inputs of the root proof"
+            ),
+            Self::ZeroKnowledgeCyclicConfig => write!(
+                f,
+                "Cyclic node circuits cannot be built with a zero-knowledge config"
+            ),
+            Self::CyclicCommonDataFixedPoint { rounds } => write!(
+                f,
+                "The common data of the cyclic node circuit does not reach a fixed point after {} rounds",
+                rounds
+            ),
+            Self::CyclicVerifierDataMismatch => write!(
+                f,
+                "Cyclic node proof does not expose the verifier data of its circuit"
+            ),
+            Self::UnsupportedSerializationVersion { version, expected } => write!(
+                f,
+                "Unsupported serialization version {}, expected {}",
+                version, expected
+            ),
+            Self::Serialization(message) => write!(f, "{}", message),
+            Self::Plonky2(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+impl std::error::Error for ZkTreeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::UserProofVerification { source, .. }
+            | Self::LeafProving { source, .. }
+            | Self::Plonky2(source) => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for ZkTreeError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::Plonky2(error)
+    }
+}
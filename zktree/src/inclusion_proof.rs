@@ -0,0 +1,206 @@
+use std::marker::PhantomData;
+
+use plonky2::{
+    hash::hash_types::{HashOut, RichField},
+    plonk::config::AlgebraicHasher,
+    util::serialization::{Buffer, Read, Write},
+};
+
+use crate::{
+    components::node_circuit::check_arity,
+    error::{hash_elements, ZkTreeError},
+    input_commitment::{leaf_input_hash, node_input_hash},
+    serialization::{
+        check_fully_read, io_error, read_hash, read_version, write_hash, write_version,
+    },
+};
+
+/// `InclusionProof` proves that the public inputs of a user are committed in the input hash of a
+/// zkTree root. It holds the sibling input hashes along the path from the user's leaf to the root,
+/// starting from the leaf level, `arity - 1` of them per level.
+///
+/// # Type Parameters
+///
+/// * `F`: The field type that must implement `RichField`.
+/// * `H`: The hasher type that implements `AlgebraicHasher<F>`, the one used by the zkTree.
+///
+/// # Fields
+///
+/// * `leaf_index`: The index of the user's leaf in the zkTree.
+/// * `arity`: The number of children of every node of the zkTree.
+/// * `siblings`: The input hashes of the siblings along the path to the root, from left to right
+///   within each level.
+/// * `_phantom_data`: `PhantomData` used to mark the usage of the hasher type `H`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InclusionProof<F, H>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    leaf_index: usize,
+    arity: usize,
+    siblings: Vec<HashOut<F>>,
+    _phantom_data: PhantomData<H>,
+}
+
+impl<F, H> InclusionProof<F, H>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    /// Constructs a new `InclusionProof`.
+    ///
+    /// # Arguments
+    ///
+    /// * `leaf_index`: The index of the user's leaf in the zkTree.
+    /// * `arity`: The number of children of every node of the zkTree.
+    /// * `siblings`: The input hashes of the siblings along the path to the root, starting from
+    ///   the leaf level, `arity - 1` of them per level.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `InclusionProof`, or a `ZkTreeError` if the arity is invalid
+    /// or the number of siblings is not a multiple of `arity - 1`.
+    pub fn new(
+        leaf_index: usize,
+        arity: usize,
+        siblings: Vec<HashOut<F>>,
+    ) -> Result<Self, ZkTreeError> {
+        check_arity(arity)?;
+        if siblings.len() % (arity - 1) != 0 {
+            return Err(ZkTreeError::InvalidNumberOfSiblings {
+                num_siblings: siblings.len(),
+                arity,
+            });
+        }
+        Ok(Self {
+            leaf_index,
+            arity,
+            siblings,
+            _phantom_data: PhantomData,
+        })
+    }
+
+    /// Returns the index of the user's leaf in the zkTree.
+    pub fn leaf_index(&self) -> usize {
+        self.leaf_index
+    }
+
+    /// Returns the number of children of every node of the zkTree.
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Returns the input hashes of the siblings along the path to the root.
+    pub fn siblings(&self) -> &[HashOut<F>] {
+        &self.siblings
+    }
+
+    /// Recomputes the root input hash from a leaf input hash, hashing each node input hash from
+    /// its children input hashes as `NodeCircuit` does, see
+    /// [`input_commitment`](crate::input_commitment).
+    ///
+    /// # Arguments
+    ///
+    /// * `leaf_input_hash`: The input hash of the user's leaf.
+    ///
+    /// # Returns
+    ///
+    /// The root input hash committing to `leaf_input_hash`.
+    pub fn compute_root_input_hash(&self, leaf_input_hash: HashOut<F>) -> HashOut<F> {
+        let mut position = self.leaf_index;
+        self.siblings.chunks(self.arity - 1).fold(
+            leaf_input_hash,
+            |input_hash, siblings_input_hashes| {
+                let mut children_input_hashes = siblings_input_hashes.to_vec();
+                children_input_hashes.insert(position % self.arity, input_hash);
+                position /= self.arity;
+                node_input_hash::<F, H>(&children_input_hashes)
+            },
+        )
+    }
+
+    /// Verifies that `user_public_inputs` are committed in `root_input_hash`.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_public_inputs`: The flattened public inputs of the user.
+    /// * `root_input_hash`: The input hash of the zkTree root.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that is `Ok(())` if the inclusion proof is valid, or a `ZkTreeError` otherwise.
+    pub fn verify(
+        &self,
+        user_public_inputs: &[F],
+        root_input_hash: HashOut<F>,
+    ) -> Result<(), ZkTreeError> {
+        let computed_root_input_hash =
+            self.compute_root_input_hash(leaf_input_hash::<F, H>(user_public_inputs));
+        if computed_root_input_hash != root_input_hash {
+            return Err(ZkTreeError::InclusionProofMismatch {
+                leaf_index: self.leaf_index,
+                expected: hash_elements(root_input_hash),
+                actual: hash_elements(computed_root_input_hash),
+            });
+        }
+        Ok(())
+    }
+
+    /// Serializes the inclusion proof into versioned bytes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the serialized bytes, or a `ZkTreeError` if serialization fails.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ZkTreeError> {
+        let mut buffer = Vec::new();
+        write_version(&mut buffer)?;
+        buffer
+            .write_usize(self.leaf_index)
+            .map_err(io_error("write the leaf index"))?;
+        buffer
+            .write_usize(self.arity)
+            .map_err(io_error("write the arity"))?;
+        buffer
+            .write_usize(self.siblings.len())
+            .map_err(io_error("write the number of siblings"))?;
+        for sibling in &self.siblings {
+            write_hash(&mut buffer, *sibling)?;
+        }
+        Ok(buffer)
+    }
+
+    /// Deserializes an inclusion proof from bytes produced by [`InclusionProof::to_bytes`].
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The serialized inclusion proof.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the deserialized inclusion proof, or a `ZkTreeError` if the version is
+    /// not supported or the bytes are malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ZkTreeError> {
+        let mut buffer = Buffer::new(bytes);
+        read_version(&mut buffer)?;
+        let leaf_index = buffer
+            .read_usize()
+            .map_err(io_error("read the leaf index"))?;
+        let arity = buffer.read_usize().map_err(io_error("read the arity"))?;
+        check_arity(arity)?;
+        let num_siblings = buffer
+            .read_usize()
+            .map_err(io_error("read the number of siblings"))?;
+        // a zkTree has at most `usize::BITS` levels, with `arity - 1` siblings each
+        if num_siblings / (arity - 1) > usize::BITS as usize {
+            return Err(ZkTreeError::Serialization(
+                "Inclusion proof has too many siblings".to_string(),
+            ));
+        }
+        let siblings = (0..num_siblings)
+            .map(|_| read_hash(&mut buffer))
+            .collect::<Result<Vec<_>, _>>()?;
+        check_fully_read(&buffer)?;
+        Self::new(leaf_index, arity, siblings)
+    }
+}
@@ -0,0 +1,246 @@
+//! Canonical definition of the input commitment of a zkTree, shared by the leaf and node circuits
+//! and by the native verifier. A leaf commits to the user public inputs with
+//! `H::hash_or_noop(user_public_inputs)`, and a node commits to its children with
+//! `H::hash_or_noop(child_0_input_hash || ... || child_k_input_hash)`, i.e.
+//! `H::hash_or_noop(left_input_hash || right_input_hash)` for binary trees. Padding leaves commit
+//! to `H::hash_no_pad(PADDING_DOMAIN_TAG)`, which is never the input hash of an empty or all-zero
+//! list of user public inputs.
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::{HashOut, HashOutTarget, RichField},
+    iop::target::Target,
+    plonk::{circuit_builder::CircuitBuilder, config::AlgebraicHasher},
+};
+
+use crate::{components::node_circuit::check_arity, error::ZkTreeError};
+
+/// Domain separation tag hashed into the input hash of padding leaves.
+const PADDING_DOMAIN_TAG: u64 = u64::from_le_bytes(*b"zkt:pad\0");
+
+/// Computes the input hash of a leaf committing to the given (flattened) user public inputs.
+pub fn leaf_input_hash<F, H>(user_public_inputs: &[F]) -> HashOut<F>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    H::hash_or_noop(user_public_inputs)
+}
+
+/// The input hash committed by padding leaves. It is domain separated from the user leaves:
+/// `hash_or_noop` is a no-op for up to four user public inputs, so hashing an empty list would
+/// give `HashOut::ZERO` and let a padding leaf pass for a user with empty or zero inputs.
+pub fn padding_input_hash<F, H>() -> HashOut<F>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    H::hash_no_pad(&[F::from_canonical_u64(PADDING_DOMAIN_TAG)])
+}
+
+/// Computes the input hash of a node from the input hashes of its children, in order.
+pub fn node_input_hash<F, H>(children_input_hashes: &[HashOut<F>]) -> HashOut<F>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    H::hash_or_noop(
+        &children_input_hashes
+            .iter()
+            .flat_map(|input_hash| input_hash.elements)
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Computes the input commitment of a binary zkTree, i.e. its root input hash, from the input
+/// hashes of all of its leaves, padding leaves included.
+///
+/// # Arguments
+///
+/// * `leaf_input_hashes`: The input hashes of the leaves, whose number must be a power of two
+///   greater than one.
+///
+/// # Returns
+///
+/// A `Result` containing the root input hash, or a `ZkTreeError` if the number of leaves is invalid.
+pub fn input_commitment<F, H>(leaf_input_hashes: &[HashOut<F>]) -> Result<HashOut<F>, ZkTreeError>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    input_commitment_with_arity::<F, H>(leaf_input_hashes, 2)
+}
+
+/// Computes the input commitment of a zkTree whose nodes aggregate `arity` children, from the
+/// input hashes of all of its leaves, padding leaves included.
+///
+/// # Arguments
+///
+/// * `leaf_input_hashes`: The input hashes of the leaves, whose number must be a power of `arity`
+///   greater than one.
+/// * `arity`: The number of children of every node, at least two.
+///
+/// # Returns
+///
+/// A `Result` containing the root input hash, or a `ZkTreeError` if the arity or the number of
+/// leaves is invalid.
+pub fn input_commitment_with_arity<F, H>(
+    leaf_input_hashes: &[HashOut<F>],
+    arity: usize,
+) -> Result<HashOut<F>, ZkTreeError>
+where
+    F: RichField,
+    H: AlgebraicHasher<F>,
+{
+    check_num_leaves(leaf_input_hashes.len(), arity)?;
+    let mut input_hashes = leaf_input_hashes.to_vec();
+    while input_hashes.len() > 1 {
+        input_hashes = input_hashes
+            .chunks(arity)
+            .map(node_input_hash::<F, H>)
+            .collect();
+    }
+    Ok(input_hashes[0])
+}
+
+/// In-circuit counterpart of [`leaf_input_hash`].
+pub(crate) fn leaf_input_hash_circuit<F, H, const D: usize>(
+    circuit_builder: &mut CircuitBuilder<F, D>,
+    user_public_inputs_targets: Vec<Target>,
+) -> HashOutTarget
+where
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    circuit_builder.hash_or_noop::<H>(user_public_inputs_targets)
+}
+
+/// In-circuit counterpart of [`padding_input_hash`].
+pub(crate) fn padding_input_hash_circuit<F, H, const D: usize>(
+    circuit_builder: &mut CircuitBuilder<F, D>,
+) -> HashOutTarget
+where
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    circuit_builder.constant_hash(padding_input_hash::<F, H>())
+}
+
+/// In-circuit counterpart of [`node_input_hash`].
+pub(crate) fn node_input_hash_circuit<F, H, const D: usize>(
+    circuit_builder: &mut CircuitBuilder<F, D>,
+    children_input_hashes_targets: &[HashOutTarget],
+) -> HashOutTarget
+where
+    F: RichField + Extendable<D>,
+    H: AlgebraicHasher<F>,
+{
+    circuit_builder.hash_or_noop::<H>(
+        children_input_hashes_targets
+            .iter()
+            .flat_map(|input_hash_targets| input_hash_targets.elements)
+            .collect(),
+    )
+}
+
+fn check_num_leaves(num_leaves: usize, arity: usize) -> Result<(), ZkTreeError> {
+    check_arity(arity)?;
+    let mut num_nodes = num_leaves;
+    while num_nodes > 1 && num_nodes % arity == 0 {
+        num_nodes /= arity;
+    }
+    if num_leaves < arity || num_nodes != 1 {
+        return Err(ZkTreeError::InvalidNumberOfLeaves { num_leaves, arity });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::{
+        field::{goldilocks_field::GoldilocksField, types::Sample},
+        hash::poseidon::PoseidonHash,
+        iop::witness::{PartialWitness, WitnessWrite},
+        plonk::{
+            circuit_builder::CircuitBuilder, circuit_data::CircuitConfig,
+            config::PoseidonGoldilocksConfig,
+        },
+    };
+
+    use super::*;
+
+    const D: usize = 2;
+    type F = GoldilocksField;
+    type C = PoseidonGoldilocksConfig;
+    type H = PoseidonHash;
+
+    #[test]
+    fn test_input_commitment_native_and_in_circuit_agree() {
+        for (arity, num_leaves) in [(2, 2), (2, 4), (2, 8), (2, 16), (2, 32), (3, 9), (4, 16)] {
+            let user_public_inputs = (0..num_leaves)
+                .map(|i| F::rand_vec(i % 6))
+                .collect::<Vec<_>>();
+            let leaf_input_hashes = user_public_inputs
+                .iter()
+                .map(|inputs| leaf_input_hash::<F, H>(inputs))
+                .collect::<Vec<_>>();
+            let native_input_commitment =
+                input_commitment_with_arity::<F, H>(&leaf_input_hashes, arity)
+                    .expect("Failed to compute input commitment");
+
+            let mut circuit_builder =
+                CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+            let mut partial_witness = PartialWitness::<F>::new();
+
+            let mut input_hashes_targets = user_public_inputs
+                .iter()
+                .map(|inputs| {
+                    let inputs_targets = circuit_builder.add_virtual_targets(inputs.len());
+                    partial_witness.set_target_arr(&inputs_targets, inputs);
+                    leaf_input_hash_circuit::<F, H, D>(&mut circuit_builder, inputs_targets)
+                })
+                .collect::<Vec<_>>();
+            while input_hashes_targets.len() > 1 {
+                input_hashes_targets = input_hashes_targets
+                    .chunks(arity)
+                    .map(|children| {
+                        node_input_hash_circuit::<F, H, D>(&mut circuit_builder, children)
+                    })
+                    .collect();
+            }
+            circuit_builder.register_public_inputs(&input_hashes_targets[0].elements);
+
+            let circuit_data = circuit_builder.build::<C>();
+            let proof_with_pis = circuit_data
+                .prove(partial_witness)
+                .expect("Failed to prove input commitment circuit");
+
+            assert_eq!(
+                proof_with_pis.public_inputs,
+                native_input_commitment.elements.to_vec()
+            );
+        }
+    }
+
+    #[test]
+    fn test_binary_input_commitment_matches_arity_two() {
+        let leaf_input_hashes = (0..8)
+            .map(|_| leaf_input_hash::<F, H>(&F::rand_vec(2)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            input_commitment::<F, H>(&leaf_input_hashes).expect("Failed to compute commitment"),
+            input_commitment_with_arity::<F, H>(&leaf_input_hashes, 2)
+                .expect("Failed to compute commitment")
+        );
+    }
+
+    #[test]
+    fn test_input_commitment_fails_with_invalid_number_of_leaves() {
+        let leaf_input_hashes = (0..3)
+            .map(|_| leaf_input_hash::<F, H>(&F::rand_vec(2)))
+            .collect::<Vec<_>>();
+        assert!(input_commitment::<F, H>(&leaf_input_hashes[..1]).is_err());
+        assert!(input_commitment::<F, H>(&leaf_input_hashes).is_err());
+        assert!(input_commitment_with_arity::<F, H>(&leaf_input_hashes[..2], 4).is_err());
+        assert!(input_commitment_with_arity::<F, H>(&leaf_input_hashes, 1).is_err());
+    }
+}
@@ -1,6 +1,17 @@
+pub mod circuit_commitment;
+pub mod compiled_circuit;
 pub mod components;
+pub mod error;
+pub mod inclusion_proof;
+pub mod input_commitment;
 pub mod proof_data;
+pub mod root_verifier_bundle;
+pub mod serialization;
+#[cfg(test)]
+mod test_utils;
+#[cfg(test)]
 mod tests;
 pub mod traits;
 mod utils;
 pub mod zktree;
+pub mod zktree_builder;
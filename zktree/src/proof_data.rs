@@ -1,13 +1,92 @@
+use std::sync::Arc;
+
 use plonky2::{
     field::extension::Extendable,
     hash::hash_types::RichField,
     plonk::{circuit_data::CircuitData, config::GenericConfig, proof::ProofWithPublicInputs},
+    util::serialization::{Buffer, GateSerializer, WitnessGeneratorSerializer},
+};
+
+use crate::{
+    error::ZkTreeError,
+    serialization::{io_error, read_bytes, write_bytes},
+    traits::serializable::Serializable,
 };
 
+#[derive(Clone)]
 pub struct ProofData<F, C: GenericConfig<D, F = F>, const D: usize>
 where
     F: RichField + Extendable<D>,
 {
     pub(crate) proof_with_pis: ProofWithPublicInputs<F, C, D>,
-    pub(crate) circuit_data: CircuitData<F, C, D>,
+    pub(crate) circuit_data: Arc<CircuitData<F, C, D>>,
+}
+
+impl<F, C: GenericConfig<D, F = F>, const D: usize> ProofData<F, C, D>
+where
+    F: RichField + Extendable<D>,
+{
+    /// Constructs a new `ProofData` from a proof and the data of the circuit it was generated
+    /// with.
+    pub fn new(
+        proof_with_pis: ProofWithPublicInputs<F, C, D>,
+        circuit_data: Arc<CircuitData<F, C, D>>,
+    ) -> Self {
+        Self {
+            proof_with_pis,
+            circuit_data,
+        }
+    }
+
+    /// Returns the proof, together with its public inputs.
+    pub fn proof_with_pis(&self) -> &ProofWithPublicInputs<F, C, D> {
+        &self.proof_with_pis
+    }
+
+    /// Returns the data of the circuit the proof was generated with.
+    pub fn circuit_data(&self) -> &CircuitData<F, C, D> {
+        &self.circuit_data
+    }
+}
+
+impl<F, C: GenericConfig<D, F = F>, const D: usize> Serializable<F, D> for ProofData<F, C, D>
+where
+    F: RichField + Extendable<D>,
+{
+    fn write(
+        &self,
+        buffer: &mut Vec<u8>,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<(), ZkTreeError> {
+        let circuit_data_bytes = self
+            .circuit_data
+            .to_bytes(gate_serializer, generator_serializer)
+            .map_err(io_error("serialize the circuit data"))?;
+        write_bytes(buffer, &circuit_data_bytes)?;
+        write_bytes(buffer, &self.proof_with_pis.to_bytes())
+    }
+
+    fn read(
+        buffer: &mut Buffer,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<Self, ZkTreeError> {
+        let circuit_data =
+            CircuitData::from_bytes(&read_bytes(buffer)?, gate_serializer, generator_serializer)
+                .map_err(io_error("deserialize the circuit data"))?;
+        let proof_with_pis =
+            ProofWithPublicInputs::from_bytes(read_bytes(buffer)?, &circuit_data.common).map_err(
+                |error| {
+                    ZkTreeError::Serialization(format!(
+                        "Failed to deserialize the proof with public inputs: {}",
+                        error
+                    ))
+                },
+            )?;
+        Ok(Self {
+            proof_with_pis,
+            circuit_data: Arc::new(circuit_data),
+        })
+    }
 }
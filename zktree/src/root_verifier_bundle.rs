@@ -0,0 +1,219 @@
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::{HashOut, RichField},
+    plonk::{
+        circuit_data::VerifierCircuitData,
+        config::{GenericConfig, Hasher},
+        proof::ProofWithPublicInputs,
+    },
+    util::serialization::{Buffer, GateSerializer},
+};
+
+use crate::{
+    components::cyclic_node_circuit::{
+        check_verifier_data_public_inputs, has_verifier_data_public_inputs,
+    },
+    error::ZkTreeError,
+    serialization::{
+        check_fully_read, check_public_input_hashes, io_error, read_bytes, read_hash, read_version,
+        write_bytes, write_hash, write_version,
+    },
+};
+
+/// `RootVerifierBundle` holds everything needed to verify the root proof of a zkTree, without any
+/// prover data or user proof. It is meant to be exported by the aggregator and shipped to light
+/// clients.
+///
+/// # Type Parameters
+///
+/// * `F`: The field type that must implement `RichField` and `Extendable<D>`.
+/// * `C`: The configuration of the circuit, satisfying `GenericConfig`.
+/// * `D`: The dimension of the field extension, specified as a compile-time constant.
+///
+/// # Fields
+///
+/// * `verifier_data`: The verifier data of the root circuit.
+/// * `proof_with_pis`: The root proof, together with its public inputs.
+/// * `input_hash`: The input commitment of the root claimed by the aggregator, i.e. of all the user
+///   public inputs.
+/// * `circuit_hash`: The circuit hash of the root claimed by the aggregator, i.e. of all the
+///   aggregated circuits.
+pub struct RootVerifierBundle<F, C, const D: usize>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+{
+    verifier_data: VerifierCircuitData<F, C, D>,
+    proof_with_pis: ProofWithPublicInputs<F, C, D>,
+    input_hash: HashOut<F>,
+    circuit_hash: HashOut<F>,
+}
+
+impl<F, C, const D: usize> RootVerifierBundle<F, C, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+{
+    /// Constructs a new `RootVerifierBundle`.
+    ///
+    /// # Arguments
+    ///
+    /// * `verifier_data`: The verifier data of the root circuit.
+    /// * `proof_with_pis`: The root proof, together with its public inputs.
+    /// * `input_hash`: The input commitment of the root claimed by the aggregator.
+    /// * `circuit_hash`: The circuit hash of the root claimed by the aggregator.
+    ///
+    /// # Returns
+    ///
+    /// A new `RootVerifierBundle` instance.
+    pub fn new(
+        verifier_data: VerifierCircuitData<F, C, D>,
+        proof_with_pis: ProofWithPublicInputs<F, C, D>,
+        input_hash: HashOut<F>,
+        circuit_hash: HashOut<F>,
+    ) -> Self {
+        Self {
+            verifier_data,
+            proof_with_pis,
+            input_hash,
+            circuit_hash,
+        }
+    }
+
+    /// Returns the verifier data of the root circuit.
+    pub fn verifier_data(&self) -> &VerifierCircuitData<F, C, D> {
+        &self.verifier_data
+    }
+
+    /// Returns the root proof, together with its public inputs.
+    pub fn proof_with_pis(&self) -> &ProofWithPublicInputs<F, C, D> {
+        &self.proof_with_pis
+    }
+
+    /// Returns the input commitment of the root claimed by the aggregator.
+    pub fn input_hash(&self) -> HashOut<F> {
+        self.input_hash
+    }
+
+    /// Returns the circuit hash of the root claimed by the aggregator.
+    pub fn circuit_hash(&self) -> HashOut<F> {
+        self.circuit_hash
+    }
+
+    /// Verifies the root proof against the root verifier data, and checks that the verifier data,
+    /// input hash and circuit hash are the ones the caller expects. The expectations are supplied
+    /// by the caller rather than taken from the bundle, as whoever ships the bundle could forge
+    /// both the proof and the hashes it claims. The hashes claimed by the bundle must still be the
+    /// ones the root proof exposes, so that its accessors can be relied upon once verified. The
+    /// verifier data a cyclic root exposes must also be the root verifier data, see
+    /// `CyclicNodeProof::verify`.
+    ///
+    /// # Arguments
+    ///
+    /// * `expected_input_hash`: The input commitment the root must expose.
+    /// * `expected_circuit_hash`: The circuit hash the root must expose.
+    /// * `expected_verifier_digest`: The circuit digest of the verifier data of the root circuit,
+    ///   pinned by the caller.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that is `Ok(())` if the bundle is valid, or a `ZkTreeError` otherwise.
+    pub fn verify(
+        &self,
+        expected_input_hash: HashOut<F>,
+        expected_circuit_hash: HashOut<F>,
+        expected_verifier_digest: <C::Hasher as Hasher<F>>::Hash,
+    ) -> Result<(), ZkTreeError> {
+        if self.verifier_data.verifier_only.circuit_digest != expected_verifier_digest {
+            return Err(ZkTreeError::VerifierCircuitDigestMismatch);
+        }
+        self.verifier_data.verify(self.proof_with_pis.clone())?;
+        if has_verifier_data_public_inputs(
+            self.proof_with_pis.public_inputs.len(),
+            &self.verifier_data.common.config,
+        ) {
+            check_verifier_data_public_inputs(
+                &self.proof_with_pis.public_inputs,
+                &self.verifier_data.verifier_only,
+            )?;
+        }
+
+        check_public_input_hashes(
+            &self.proof_with_pis.public_inputs,
+            self.input_hash,
+            self.circuit_hash,
+        )?;
+        check_public_input_hashes(
+            &self.proof_with_pis.public_inputs,
+            expected_input_hash,
+            expected_circuit_hash,
+        )?;
+        Ok(())
+    }
+
+    /// Serializes the bundle into versioned bytes. Only the gate serializer is needed, as the
+    /// bundle does not hold any prover data.
+    ///
+    /// # Arguments
+    ///
+    /// * `gate_serializer`: The serializer for the gates of the root circuit.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the serialized bytes, or a `ZkTreeError` if serialization fails.
+    pub fn to_bytes(
+        &self,
+        gate_serializer: &dyn GateSerializer<F, D>,
+    ) -> Result<Vec<u8>, ZkTreeError> {
+        let mut buffer = Vec::new();
+        write_version(&mut buffer)?;
+        let verifier_data_bytes = self
+            .verifier_data
+            .to_bytes(gate_serializer)
+            .map_err(io_error("serialize the root verifier data"))?;
+        write_bytes(&mut buffer, &verifier_data_bytes)?;
+        write_bytes(&mut buffer, &self.proof_with_pis.to_bytes())?;
+        write_hash(&mut buffer, self.input_hash)?;
+        write_hash(&mut buffer, self.circuit_hash)?;
+        Ok(buffer)
+    }
+
+    /// Deserializes a bundle from bytes produced by [`RootVerifierBundle::to_bytes`].
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The serialized bundle.
+    /// * `gate_serializer`: The serializer for the gates of the root circuit.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the deserialized bundle, or a `ZkTreeError` if the version is not
+    /// supported or the bytes are malformed.
+    pub fn from_bytes(
+        bytes: &[u8],
+        gate_serializer: &dyn GateSerializer<F, D>,
+    ) -> Result<Self, ZkTreeError> {
+        let mut buffer = Buffer::new(bytes);
+        read_version(&mut buffer)?;
+        let verifier_data =
+            VerifierCircuitData::from_bytes(read_bytes(&mut buffer)?, gate_serializer)
+                .map_err(io_error("deserialize the root verifier data"))?;
+        let proof_with_pis =
+            ProofWithPublicInputs::from_bytes(read_bytes(&mut buffer)?, &verifier_data.common)
+                .map_err(|error| {
+                    ZkTreeError::Serialization(format!(
+                        "Failed to deserialize the root proof with public inputs: {}",
+                        error
+                    ))
+                })?;
+        let input_hash = read_hash(&mut buffer)?;
+        let circuit_hash = read_hash(&mut buffer)?;
+        check_fully_read(&buffer)?;
+        Ok(Self::new(
+            verifier_data,
+            proof_with_pis,
+            input_hash,
+            circuit_hash,
+        ))
+    }
+}
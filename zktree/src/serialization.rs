@@ -0,0 +1,119 @@
+use plonky2::{
+    hash::hash_types::{HashOut, RichField, NUM_HASH_OUT_ELTS},
+    util::serialization::{Buffer, IoError, Read, Write},
+};
+
+use crate::error::{hash_elements, ZkTreeError};
+
+/// Version of the binary encoding of zkTree proofs. It is written at the start of every serialized
+/// proof, and must be bumped on every change to the encoding.
+pub const SERIALIZATION_VERSION: u8 = 2;
+
+/// Returns a conversion of a plonky2 serialization error into a `ZkTreeError`, describing what
+/// was being written or read when it occurred, as plonky2's `IoError` carries no details.
+pub(crate) fn io_error(context: &'static str) -> impl FnOnce(IoError) -> ZkTreeError {
+    move |_| ZkTreeError::Serialization(format!("Failed to {}", context))
+}
+
+pub(crate) fn write_version(buffer: &mut Vec<u8>) -> Result<(), ZkTreeError> {
+    buffer
+        .write_u8(SERIALIZATION_VERSION)
+        .map_err(io_error("write the serialization version"))
+}
+
+pub(crate) fn read_version(buffer: &mut Buffer) -> Result<(), ZkTreeError> {
+    let version = buffer
+        .read_u8()
+        .map_err(io_error("read the serialization version"))?;
+    if version != SERIALIZATION_VERSION {
+        return Err(ZkTreeError::UnsupportedSerializationVersion {
+            version,
+            expected: SERIALIZATION_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// Writes `bytes` to `buffer`, prefixed with their length.
+pub(crate) fn write_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) -> Result<(), ZkTreeError> {
+    buffer
+        .write_usize(bytes.len())
+        .map_err(io_error("write the length of a byte string"))?;
+    buffer
+        .write_all(bytes)
+        .map_err(io_error("write a byte string"))
+}
+
+/// Reads bytes written by [`write_bytes`] from `buffer`.
+pub(crate) fn read_bytes(buffer: &mut Buffer) -> Result<Vec<u8>, ZkTreeError> {
+    let len = buffer
+        .read_usize()
+        .map_err(io_error("read the length of a byte string"))?;
+    if len > buffer.unread_bytes().len() {
+        return Err(ZkTreeError::Serialization(
+            "Serialized data is truncated".to_string(),
+        ));
+    }
+    let mut bytes = vec![0; len];
+    buffer
+        .read_exact(&mut bytes)
+        .map_err(io_error("read a byte string"))?;
+    Ok(bytes)
+}
+
+pub(crate) fn write_hash<F: RichField>(
+    buffer: &mut Vec<u8>,
+    hash: HashOut<F>,
+) -> Result<(), ZkTreeError> {
+    buffer
+        .write_field_vec(&hash.elements)
+        .map_err(io_error("write a hash"))
+}
+
+pub(crate) fn read_hash<F: RichField>(buffer: &mut Buffer) -> Result<HashOut<F>, ZkTreeError> {
+    let elements = buffer
+        .read_field_vec(NUM_HASH_OUT_ELTS)
+        .map_err(io_error("read a hash"))?;
+    Ok(HashOut::from_vec(elements))
+}
+
+/// Checks that the input and circuit hashes exposed as the first public inputs of a proof are
+/// `input_hash` and `circuit_hash`.
+pub(crate) fn check_public_input_hashes<F: RichField>(
+    public_inputs: &[F],
+    input_hash: HashOut<F>,
+    circuit_hash: HashOut<F>,
+) -> Result<(), ZkTreeError> {
+    if public_inputs.len() < 2 * NUM_HASH_OUT_ELTS {
+        return Err(ZkTreeError::TooFewPublicInputs {
+            expected: 2 * NUM_HASH_OUT_ELTS,
+            actual: public_inputs.len(),
+        });
+    }
+    let public_input_hash = HashOut::from_partial(&public_inputs[..NUM_HASH_OUT_ELTS]);
+    if public_input_hash != input_hash {
+        return Err(ZkTreeError::InputHashMismatch {
+            expected: hash_elements(input_hash),
+            actual: hash_elements(public_input_hash),
+        });
+    }
+    let public_circuit_hash =
+        HashOut::from_partial(&public_inputs[NUM_HASH_OUT_ELTS..2 * NUM_HASH_OUT_ELTS]);
+    if public_circuit_hash != circuit_hash {
+        return Err(ZkTreeError::CircuitHashMismatch {
+            expected: hash_elements(circuit_hash),
+            actual: hash_elements(public_circuit_hash),
+        });
+    }
+    Ok(())
+}
+
+/// Checks that every byte of `buffer` has been read.
+pub(crate) fn check_fully_read(buffer: &Buffer) -> Result<(), ZkTreeError> {
+    if !buffer.unread_bytes().is_empty() {
+        return Err(ZkTreeError::Serialization(
+            "Serialized data has trailing bytes".to_string(),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! A second `GenericConfig` for tests, whose hasher differs from `PoseidonHash`. Running the test
+//! suite against both `PoseidonGoldilocksConfig` and `DoublePoseidonGoldilocksConfig` checks that
+//! every native hash is computed with the configured hasher, as a mismatch with the in-circuit
+//! hashes would make the proofs unsatisfiable.
+use plonky2::{
+    field::{
+        extension::{quadratic::QuadraticExtension, Extendable},
+        goldilocks_field::GoldilocksField,
+    },
+    hash::{
+        hash_types::{HashOut, RichField},
+        hashing::{compress, hash_n_to_hash_no_pad, PlonkyPermutation},
+        poseidon::{PoseidonHash, PoseidonPermutation},
+    },
+    iop::target::{BoolTarget, Target},
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        config::{AlgebraicHasher, GenericConfig, Hasher},
+    },
+};
+
+/// Permutation applying the Poseidon permutation twice.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub(crate) struct DoublePoseidonPermutation<F: RichField>(PoseidonPermutation<F>);
+
+impl<F: RichField> AsRef<[F]> for DoublePoseidonPermutation<F> {
+    fn as_ref(&self) -> &[F] {
+        self.0.as_ref()
+    }
+}
+
+impl<F: RichField> PlonkyPermutation<F> for DoublePoseidonPermutation<F> {
+    const RATE: usize = PoseidonPermutation::<F>::RATE;
+    const WIDTH: usize = PoseidonPermutation::<F>::WIDTH;
+
+    fn new<I: IntoIterator<Item = F>>(elts: I) -> Self {
+        Self(PoseidonPermutation::new(elts))
+    }
+
+    fn set_elt(&mut self, elt: F, idx: usize) {
+        self.0.set_elt(elt, idx)
+    }
+
+    fn set_from_iter<I: IntoIterator<Item = F>>(&mut self, elts: I, start_idx: usize) {
+        self.0.set_from_iter(elts, start_idx)
+    }
+
+    fn set_from_slice(&mut self, elts: &[F], start_idx: usize) {
+        self.0.set_from_slice(elts, start_idx)
+    }
+
+    fn permute(&mut self) {
+        self.0.permute();
+        self.0.permute();
+    }
+
+    fn squeeze(&self) -> &[F] {
+        self.0.squeeze()
+    }
+}
+
+/// Sponge hash over `DoublePoseidonPermutation`. Its in-circuit permutation chains two Poseidon
+/// gates, so that native and in-circuit hashes agree.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct DoublePoseidonHash;
+
+impl<F: RichField> Hasher<F> for DoublePoseidonHash {
+    const HASH_SIZE: usize = 4 * 8;
+    type Hash = HashOut<F>;
+    type Permutation = DoublePoseidonPermutation<F>;
+
+    fn hash_no_pad(input: &[F]) -> Self::Hash {
+        hash_n_to_hash_no_pad::<F, Self::Permutation>(input)
+    }
+
+    fn two_to_one(left: Self::Hash, right: Self::Hash) -> Self::Hash {
+        compress::<F, Self::Permutation>(left, right)
+    }
+}
+
+impl<F: RichField> AlgebraicHasher<F> for DoublePoseidonHash {
+    type AlgebraicPermutation = PoseidonPermutation<Target>;
+
+    fn permute_swapped<const D: usize>(
+        inputs: Self::AlgebraicPermutation,
+        swap: BoolTarget,
+        builder: &mut CircuitBuilder<F, D>,
+    ) -> Self::AlgebraicPermutation
+    where
+        F: RichField + Extendable<D>,
+    {
+        let outputs = PoseidonHash::permute_swapped(inputs, swap, builder);
+        let no_swap = builder._false();
+        PoseidonHash::permute_swapped(outputs, no_swap, builder)
+    }
+}
+
+/// Configuration using `DoublePoseidonHash` over the Goldilocks field.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct DoublePoseidonGoldilocksConfig;
+
+impl GenericConfig<2> for DoublePoseidonGoldilocksConfig {
+    type F = GoldilocksField;
+    type FE = QuadraticExtension<Self::F>;
+    type Hasher = DoublePoseidonHash;
+    type InnerHasher = DoublePoseidonHash;
+}
@@ -1,6 +1,27 @@
 #![allow(dead_code)]
+use std::sync::Arc;
+
 use crate::{
-    components::user_proof::UserProof, proof_data::ProofData, traits::proof::Proof, zktree::ZkTree,
+    circuit_commitment::{circuit_commitment, node_circuit_hash},
+    components::{
+        allow_list::CircuitDigestAllowList,
+        cyclic_node_circuit::{CyclicNodeChildren, CyclicNodeCircuit},
+        cyclic_node_proof::CyclicNodeProof,
+        leaf_circuit_cache::LeafCircuitCache,
+        padded_user_circuit::CommonLeafShape,
+        shrink_circuit::high_rate_shrink_config,
+        user_proof::UserProof,
+    },
+    error::ZkTreeError,
+    inclusion_proof::InclusionProof,
+    input_commitment::{leaf_input_hash, padding_input_hash},
+    proof_data::ProofData,
+    root_verifier_bundle::RootVerifierBundle,
+    test_utils::{DoublePoseidonGoldilocksConfig, DoublePoseidonHash},
+    traits::proof::Proof,
+    utils::install_in_thread_pool,
+    zktree::ZkTree,
+    zktree_builder::ZkTreeBuilder,
 };
 use plonky2::{
     field::{
@@ -8,18 +29,33 @@ use plonky2::{
         ops::Square,
         types::{Field, Sample},
     },
+    gates::noop::NoopGate,
+    hash::{
+        hash_types::{HashOut, NUM_HASH_OUT_ELTS},
+        keccak::KeccakHash,
+        poseidon::PoseidonHash,
+    },
     iop::witness::{PartialWitness, WitnessWrite},
     plonk::{
-        circuit_builder::CircuitBuilder, circuit_data::CircuitConfig,
-        config::PoseidonGoldilocksConfig,
+        circuit_builder::CircuitBuilder,
+        circuit_data::CircuitConfig,
+        config::{
+            AlgebraicHasher, GenericConfig, Hasher, KeccakGoldilocksConfig,
+            PoseidonGoldilocksConfig,
+        },
     },
+    util::serialization::DefaultGateSerializer,
 };
+use rayon::ThreadPoolBuilder;
 
 const D: usize = 2;
 type C = PoseidonGoldilocksConfig;
 type F = GoldilocksField;
 
-fn circuit_1() -> (F, ProofData<F, C, D>) {
+fn circuit_1<C>() -> (F, ProofData<F, C, D>)
+where
+    C: GenericConfig<D, F = F>,
+{
     let mut circuit_builder =
         CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_zk_config());
     let mut partial_witness = PartialWitness::<F>::new();
@@ -47,13 +83,24 @@ fn circuit_1() -> (F, ProofData<F, C, D>) {
         .expect("Failed to generate proof for first circuit");
 
     let proof_data = ProofData {
-        circuit_data,
+        circuit_data: Arc::new(circuit_data),
         proof_with_pis,
     };
     (c, proof_data)
 }
 
+/// Returns a proof of `circuit_1` whose public input is tampered with, so that it does not verify.
 fn malformed_circuit_1() -> (F, ProofData<F, C, D>) {
+    let (c, mut proof_data) = circuit_1::<C>();
+    let forged_c = c + F::ONE;
+    proof_data.proof_with_pis.public_inputs[0] = forged_c;
+    (forged_c, proof_data)
+}
+
+fn circuit_2<C>() -> (F, ProofData<F, C, D>)
+where
+    C: GenericConfig<D, F = F>,
+{
     let mut circuit_builder =
         CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_zk_config());
     let mut partial_witness = PartialWitness::<F>::new();
@@ -64,12 +111,12 @@ fn malformed_circuit_1() -> (F, ProofData<F, C, D>) {
 
     circuit_builder.register_public_input(c_target);
 
-    let sum_target = circuit_builder.add(a_target, b_target);
-    circuit_builder.connect(c_target, sum_target);
+    let mul_target = circuit_builder.mul(a_target, b_target);
+    circuit_builder.connect(c_target, mul_target);
 
     let a = F::rand();
     let b = F::rand();
-    let c = a + b + F::ONE;
+    let c = a * b;
 
     partial_witness.set_target(a_target, a);
     partial_witness.set_target(b_target, b);
@@ -81,13 +128,16 @@ fn malformed_circuit_1() -> (F, ProofData<F, C, D>) {
         .expect("Failed to generate proof for first circuit");
 
     let proof_data = ProofData {
-        circuit_data,
+        circuit_data: Arc::new(circuit_data),
         proof_with_pis,
     };
     (c, proof_data)
 }
 
-fn circuit_2() -> (F, ProofData<F, C, D>) {
+fn circuit_3<C>() -> (F, ProofData<F, C, D>)
+where
+    C: GenericConfig<D, F = F>,
+{
     let mut circuit_builder =
         CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_zk_config());
     let mut partial_witness = PartialWitness::<F>::new();
@@ -98,12 +148,14 @@ fn circuit_2() -> (F, ProofData<F, C, D>) {
 
     circuit_builder.register_public_input(c_target);
 
-    let mul_target = circuit_builder.mul(a_target, b_target);
-    circuit_builder.connect(c_target, mul_target);
+    let a_sqr_target = circuit_builder.square(a_target);
+    let b_sqr_target = circuit_builder.square(b_target);
+    let out_target = circuit_builder.add(a_sqr_target, b_sqr_target);
+    circuit_builder.connect(c_target, out_target);
 
     let a = F::rand();
     let b = F::rand();
-    let c = a * b;
+    let c = a.square() + b.square();
 
     partial_witness.set_target(a_target, a);
     partial_witness.set_target(b_target, b);
@@ -115,35 +167,33 @@ fn circuit_2() -> (F, ProofData<F, C, D>) {
         .expect("Failed to generate proof for first circuit");
 
     let proof_data = ProofData {
-        circuit_data,
+        circuit_data: Arc::new(circuit_data),
         proof_with_pis,
     };
     (c, proof_data)
 }
 
-fn circuit_3() -> (F, ProofData<F, C, D>) {
+fn circuit_4<C>() -> (F, ProofData<F, C, D>)
+where
+    C: GenericConfig<D, F = F>,
+{
     let mut circuit_builder =
         CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_zk_config());
     let mut partial_witness = PartialWitness::<F>::new();
 
     let a_target = circuit_builder.add_virtual_target();
     let b_target = circuit_builder.add_virtual_target();
-    let c_target = circuit_builder.add_virtual_target();
 
-    circuit_builder.register_public_input(c_target);
+    circuit_builder.register_public_input(b_target);
 
     let a_sqr_target = circuit_builder.square(a_target);
-    let b_sqr_target = circuit_builder.square(b_target);
-    let out_target = circuit_builder.add(a_sqr_target, b_sqr_target);
-    circuit_builder.connect(c_target, out_target);
+    circuit_builder.connect(b_target, a_sqr_target);
 
     let a = F::rand();
-    let b = F::rand();
-    let c = a.square() + b.square();
+    let b = a.square();
 
     partial_witness.set_target(a_target, a);
     partial_witness.set_target(b_target, b);
-    partial_witness.set_target(c_target, c);
 
     let circuit_data = circuit_builder.build::<C>();
     let proof_with_pis = circuit_data
@@ -151,13 +201,41 @@ fn circuit_3() -> (F, ProofData<F, C, D>) {
         .expect("Failed to generate proof for first circuit");
 
     let proof_data = ProofData {
-        circuit_data,
+        circuit_data: Arc::new(circuit_data),
         proof_with_pis,
     };
-    (c, proof_data)
+    (b, proof_data)
+}
+
+/// Returns `n` user proofs, cycling through `circuit_1` to `circuit_4`.
+fn user_proofs<C>(n: usize) -> Vec<UserProof<C, F, D>>
+where
+    C: GenericConfig<D, F = F>,
+    C::Hasher: AlgebraicHasher<F>,
+{
+    let circuits = [
+        circuit_1::<C>,
+        circuit_2::<C>,
+        circuit_3::<C>,
+        circuit_4::<C>,
+    ];
+    (0..n)
+        .map(|index| {
+            let (a, proof_data) = circuits[index % circuits.len()]();
+            UserProof::new(
+                vec![vec![a]],
+                proof_data.circuit_data.verifier_only.circuit_digest,
+                proof_data,
+            )
+        })
+        .collect()
 }
 
-fn circuit_4() -> (F, ProofData<F, C, D>) {
+/// Same as `circuit_4`, with a degree of `2^10`.
+fn large_circuit_4<C>() -> (F, ProofData<F, C, D>)
+where
+    C: GenericConfig<D, F = F>,
+{
     let mut circuit_builder =
         CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_zk_config());
     let mut partial_witness = PartialWitness::<F>::new();
@@ -170,6 +248,11 @@ fn circuit_4() -> (F, ProofData<F, C, D>) {
     let a_sqr_target = circuit_builder.square(a_target);
     circuit_builder.connect(b_target, a_sqr_target);
 
+    // more gates than the degree of the other user circuits, which is raised by zero-knowledge
+    while circuit_builder.num_gates() <= 1 << 14 {
+        circuit_builder.add_gate(NoopGate, vec![]);
+    }
+
     let a = F::rand();
     let b = a.square();
 
@@ -179,10 +262,10 @@ fn circuit_4() -> (F, ProofData<F, C, D>) {
     let circuit_data = circuit_builder.build::<C>();
     let proof_with_pis = circuit_data
         .prove(partial_witness)
-        .expect("Failed to generate proof for first circuit");
+        .expect("Failed to generate proof for large circuit");
 
     let proof_data = ProofData {
-        circuit_data,
+        circuit_data: Arc::new(circuit_data),
         proof_with_pis,
     };
     (b, proof_data)
@@ -190,10 +273,10 @@ fn circuit_4() -> (F, ProofData<F, C, D>) {
 
 #[test]
 fn test_zktree() {
-    let (a1, proof_data1) = circuit_1();
-    let (a2, proof_data2) = circuit_2();
-    let (a3, proof_data3) = circuit_3();
-    let (a4, proof_data4) = circuit_4();
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+    let (a3, proof_data3) = circuit_3::<C>();
+    let (a4, proof_data4) = circuit_4::<C>();
 
     assert!(proof_data1
         .circuit_data
@@ -245,12 +328,15 @@ fn test_zktree() {
         .is_ok());
 }
 
-#[test]
-fn test_zktree_verification() {
-    let (a1, proof_data1) = circuit_1();
-    let (a2, proof_data2) = circuit_2();
-    let (a3, proof_data3) = circuit_3();
-    let (a4, proof_data4) = circuit_4();
+fn zktree_verification<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+    let (a3, proof_data3) = circuit_3::<C>();
+    let (a4, proof_data4) = circuit_4::<C>();
 
     let user_proof1 = UserProof::new(
         vec![vec![a1]],
@@ -276,29 +362,45 @@ fn test_zktree_verification() {
     let zktree = ZkTree::new(vec![user_proof1, user_proof2, user_proof3, user_proof4])
         .expect("Failed to generate ZkTree from user proofs");
     assert_eq!(zktree.get_node_proofs().len(), 3);
+
+    // nodes at the same height share the same compiled circuit
+    let node_proofs = zktree.get_node_proofs();
+    assert!(Arc::ptr_eq(
+        &node_proofs[0].proof().circuit_data,
+        &node_proofs[1].proof().circuit_data
+    ));
     zktree.verify().expect("Failed to verify zkTree");
 }
 
+#[test]
+fn test_zktree_verification() {
+    zktree_verification::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+#[test]
+fn test_zktree_verification_with_double_poseidon_config() {
+    zktree_verification::<DoublePoseidonGoldilocksConfig, DoublePoseidonHash>();
+}
+
 #[test]
 fn text_zktree_verification_large_proofs() {
     let user_proofs = (0..2_i32.pow(4))
         .map(|i| {
             let (a, proof_data) = if i % 4 == 0 {
-                circuit_1()
+                circuit_1::<C>()
             } else if i % 4 == 1 {
-                circuit_2()
+                circuit_2::<C>()
             } else if i % 4 == 2 {
-                circuit_3()
+                circuit_3::<C>()
             } else {
-                circuit_4()
+                circuit_4::<C>()
             };
 
-            let user_proof = UserProof::new(
+            UserProof::new(
                 vec![vec![a]],
                 proof_data.circuit_data.verifier_only.circuit_digest,
                 proof_data,
-            );
-            user_proof
+            )
         })
         .collect::<Vec<_>>();
 
@@ -306,30 +408,442 @@ fn text_zktree_verification_large_proofs() {
     assert!(zktree.verify().is_ok())
 }
 
+fn zktree_verification_non_power_of_two_proofs<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+    let (a3, proof_data3) = circuit_3::<C>();
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    let user_proof2 = UserProof::new(
+        vec![vec![a2]],
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data2,
+    );
+    let user_proof3 = UserProof::new(
+        vec![vec![a3]],
+        proof_data3.circuit_data.verifier_only.circuit_digest,
+        proof_data3,
+    );
+
+    let zktree = ZkTree::new(vec![user_proof1, user_proof2, user_proof3])
+        .expect("Failed to generate ZkTree from user proofs");
+    assert_eq!(zktree.get_user_proofs().len(), 3);
+    assert_eq!(zktree.get_leaf_proofs().len(), 4);
+    assert_eq!(zktree.get_node_proofs().len(), 3);
+    zktree.verify().expect("Failed to verify zkTree");
+}
+
 #[test]
-#[should_panic]
-fn test_zktree_fails_if_user_proof_is_malformed() {
-    let (a1, proof_data1) = malformed_circuit_1();
-    let (a2, proof_data2) = circuit_2();
-    let (a3, proof_data3) = circuit_3();
-    let (a4, proof_data4) = circuit_4();
+fn test_zktree_verification_non_power_of_two_proofs() {
+    zktree_verification_non_power_of_two_proofs::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
 
-    assert!(proof_data1
-        .circuit_data
-        .verify(proof_data1.proof_with_pis.clone())
-        .is_err());
-    assert!(proof_data2
-        .circuit_data
-        .verify(proof_data2.proof_with_pis.clone())
-        .is_ok());
-    assert!(proof_data3
-        .circuit_data
-        .verify(proof_data3.proof_with_pis.clone())
-        .is_ok());
-    assert!(proof_data4
-        .circuit_data
-        .verify(proof_data4.proof_with_pis.clone())
-        .is_ok());
+#[test]
+fn test_zktree_verification_non_power_of_two_proofs_with_double_poseidon_config() {
+    zktree_verification_non_power_of_two_proofs::<DoublePoseidonGoldilocksConfig, DoublePoseidonHash>(
+    );
+}
+
+fn zktree_verification_single_proof<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let (a1, proof_data1) = circuit_1::<C>();
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+
+    let zktree =
+        ZkTree::new(vec![user_proof1]).expect("Failed to generate ZkTree from user proofs");
+    assert_eq!(zktree.get_leaf_proofs().len(), 2);
+    assert_eq!(zktree.get_node_proofs().len(), 1);
+    zktree.verify().expect("Failed to verify zkTree");
+}
+
+#[test]
+fn test_zktree_verification_single_proof() {
+    zktree_verification_single_proof::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+#[test]
+fn test_zktree_verification_single_proof_with_double_poseidon_config() {
+    zktree_verification_single_proof::<DoublePoseidonGoldilocksConfig, DoublePoseidonHash>();
+}
+
+#[test]
+fn test_zktree_verification_with_arity() {
+    for (arity, num_user_proofs, num_leaves, num_node_proofs) in [(3, 4, 9, 4), (4, 3, 4, 1)] {
+        let user_proofs = (0..num_user_proofs)
+            .map(|i| {
+                let (a, proof_data) = if i % 2 == 0 {
+                    circuit_1::<C>()
+                } else {
+                    circuit_2::<C>()
+                };
+                UserProof::new(
+                    vec![vec![a]],
+                    proof_data.circuit_data.verifier_only.circuit_digest,
+                    proof_data,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let zktree = ZkTree::new_with_arity(user_proofs, arity, &LeafCircuitCache::new())
+            .expect("Failed to generate ZkTree from user proofs");
+        assert_eq!(zktree.arity(), arity);
+        assert_eq!(zktree.get_leaf_proofs().len(), num_leaves);
+        assert_eq!(zktree.get_node_proofs().len(), num_node_proofs);
+        zktree.verify().expect("Failed to verify zkTree");
+
+        let root_input_hash = zktree.root().input_hash();
+        for (index, user_proof) in zktree.get_user_proofs().into_iter().enumerate() {
+            let inclusion_proof = zktree
+                .inclusion_proof(index)
+                .expect("Failed to generate inclusion proof");
+            assert_eq!(
+                inclusion_proof.siblings().len(),
+                (arity - 1) * num_leaves.ilog(arity) as usize
+            );
+            inclusion_proof
+                .verify(&user_proof.user_public_inputs().concat(), root_input_hash)
+                .expect("Failed to verify inclusion proof");
+        }
+    }
+}
+
+#[test]
+fn test_zktree_fails_with_invalid_arity() {
+    let (a1, proof_data1) = circuit_1::<C>();
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    assert!(matches!(
+        ZkTree::new_with_arity(vec![user_proof1], 1, &LeafCircuitCache::new()),
+        Err(ZkTreeError::InvalidArity { arity: 1 })
+    ));
+}
+
+#[test]
+fn test_zktree_leaf_circuit_cache_is_shared_across_trees() {
+    let leaf_circuit_cache = LeafCircuitCache::new();
+
+    let zktrees = (0..2)
+        .map(|_| {
+            let (a1, proof_data1) = circuit_1::<C>();
+            let (a2, proof_data2) = circuit_1::<C>();
+
+            let user_proof1 = UserProof::new(
+                vec![vec![a1]],
+                proof_data1.circuit_data.verifier_only.circuit_digest,
+                proof_data1,
+            );
+            let user_proof2 = UserProof::new(
+                vec![vec![a2]],
+                proof_data2.circuit_data.verifier_only.circuit_digest,
+                proof_data2,
+            );
+
+            ZkTree::new_with_leaf_circuit_cache(vec![user_proof1, user_proof2], &leaf_circuit_cache)
+                .expect("Failed to generate ZkTree from user proofs")
+        })
+        .collect::<Vec<_>>();
+
+    // the leaf circuit is compiled once and reused for every leaf of both trees
+    assert_eq!(leaf_circuit_cache.len(), 1);
+    let leaf_proofs = zktrees
+        .iter()
+        .flat_map(|zktree| zktree.get_leaf_proofs())
+        .collect::<Vec<_>>();
+    assert!(leaf_proofs.iter().all(|leaf_proof| Arc::ptr_eq(
+        &leaf_proof.proof().circuit_data,
+        &leaf_proofs[0].proof().circuit_data
+    )));
+
+    for zktree in zktrees {
+        zktree.verify().expect("Failed to verify zkTree");
+    }
+}
+
+#[test]
+fn test_zktree_leaf_circuit_cache_is_shared_across_user_circuits_of_the_same_shape() {
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+
+    // different user circuits, of the same shape
+    assert_ne!(
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data2.circuit_data.verifier_only.circuit_digest
+    );
+    assert_eq!(
+        proof_data1.circuit_data.common,
+        proof_data2.circuit_data.common
+    );
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    let user_proof2 = UserProof::new(
+        vec![vec![a2]],
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data2,
+    );
+
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let zktree =
+        ZkTree::new_with_leaf_circuit_cache(vec![user_proof1, user_proof2], &leaf_circuit_cache)
+            .expect("Failed to generate ZkTree from user proofs");
+
+    // both user proofs are verified by a single leaf circuit
+    assert_eq!(leaf_circuit_cache.len(), 1);
+    let leaf_proofs = zktree.get_leaf_proofs();
+    assert!(Arc::ptr_eq(
+        &leaf_proofs[0].proof().circuit_data,
+        &leaf_proofs[1].proof().circuit_data
+    ));
+    zktree.verify().expect("Failed to verify zkTree");
+}
+
+#[test]
+fn test_zktree_in_thread_pool() {
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    let user_proof2 = UserProof::new(
+        vec![vec![a2]],
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data2,
+    );
+
+    let thread_pool = ThreadPoolBuilder::new()
+        .num_threads(3)
+        .thread_name(|index| format!("zktree-test-pool-{index}"))
+        .build()
+        .expect("Failed to build thread pool");
+
+    // the work is run by the threads of the given pool, not by the caller nor the global pool
+    let (num_threads, thread_name) = install_in_thread_pool(Some(&thread_pool), || {
+        (
+            rayon::current_num_threads(),
+            std::thread::current().name().map(str::to_owned),
+        )
+    });
+    assert_eq!(num_threads, 3);
+    assert!(thread_name.is_some_and(|name| name.starts_with("zktree-test-pool-")));
+    let (num_threads, thread_name) = install_in_thread_pool(None, || {
+        (
+            rayon::current_num_threads(),
+            std::thread::current().name().map(str::to_owned),
+        )
+    });
+    assert_eq!(num_threads, rayon::current_num_threads());
+    assert_eq!(
+        thread_name,
+        std::thread::current().name().map(str::to_owned)
+    );
+
+    let zktree = ZkTree::new_in_thread_pool(
+        vec![user_proof1, user_proof2],
+        &LeafCircuitCache::new(),
+        &thread_pool,
+    )
+    .expect("Failed to generate ZkTree from user proofs");
+    zktree.verify().expect("Failed to verify zkTree");
+}
+
+fn zktree_inclusion_proofs<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let user_proofs = user_proofs::<C>(3);
+
+    let zktree = ZkTree::new(user_proofs).expect("Failed to generate ZkTree from user proofs");
+    let root_input_hash = zktree.root().input_hash();
+
+    for (index, user_proof) in zktree.get_user_proofs().into_iter().enumerate() {
+        let inclusion_proof = zktree
+            .inclusion_proof(index)
+            .expect("Failed to generate inclusion proof");
+        assert_eq!(inclusion_proof.siblings().len(), 2);
+
+        let user_public_inputs = user_proof.user_public_inputs().concat();
+        inclusion_proof
+            .verify(&user_public_inputs, root_input_hash)
+            .expect("Failed to verify inclusion proof");
+        assert!(inclusion_proof
+            .verify(&[user_public_inputs[0] + F::ONE], root_input_hash)
+            .is_err());
+
+        let bytes = inclusion_proof
+            .to_bytes()
+            .expect("Failed to serialize inclusion proof");
+        let deserialized_inclusion_proof = InclusionProof::<F, H>::from_bytes(&bytes)
+            .expect("Failed to deserialize inclusion proof");
+        assert_eq!(deserialized_inclusion_proof, inclusion_proof);
+
+        // the version and the leaf index are read, but not the arity
+        assert!(matches!(
+            InclusionProof::<F, H>::from_bytes(&bytes[..1 + 8 + 4]),
+            Err(ZkTreeError::Serialization(message)) if message == "Failed to read the arity"
+        ));
+    }
+
+    // padding leaves do not have inclusion proofs
+    assert!(matches!(
+        zktree.inclusion_proof(3),
+        Err(ZkTreeError::InvalidUserProofIndex {
+            index: 3,
+            num_user_proofs: 3
+        })
+    ));
+
+    // a path to the padding leaf does not verify for empty or zero user public inputs
+    let sibling_inclusion_proof = zktree
+        .inclusion_proof(2)
+        .expect("Failed to generate inclusion proof");
+    let mut padding_siblings = sibling_inclusion_proof.siblings().to_vec();
+    padding_siblings[0] =
+        leaf_input_hash::<F, H>(&zktree.get_user_proofs()[2].user_public_inputs().concat());
+    let padding_inclusion_proof = InclusionProof::<F, H>::new(3, 2, padding_siblings)
+        .expect("Failed to build padding inclusion proof");
+    assert_eq!(
+        padding_inclusion_proof.compute_root_input_hash(padding_input_hash::<F, H>()),
+        root_input_hash
+    );
+    for user_public_inputs in [vec![], vec![F::ZERO], vec![F::ZERO; 4]] {
+        assert!(padding_inclusion_proof
+            .verify(&user_public_inputs, root_input_hash)
+            .is_err());
+    }
+}
+
+#[test]
+fn test_zktree_inclusion_proofs() {
+    zktree_inclusion_proofs::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+#[test]
+fn test_zktree_inclusion_proofs_with_double_poseidon_config() {
+    zktree_inclusion_proofs::<DoublePoseidonGoldilocksConfig, DoublePoseidonHash>();
+}
+
+fn zktree_root_verifier_bundle<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    let user_proof2 = UserProof::new(
+        vec![vec![a2]],
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data2,
+    );
+
+    let zktree = ZkTree::new(vec![user_proof1, user_proof2])
+        .expect("Failed to generate ZkTree from user proofs");
+    // the light client pins the root circuit and the hashes it expects
+    let input_hash = zktree.root().input_hash();
+    let circuit_hash = zktree.root().circuit_hash();
+    let verifier_digest = zktree.root().circuit_verifier_digest();
+
+    let root_verifier_bundle = zktree.root_verifier_bundle();
+    root_verifier_bundle
+        .verify(input_hash, circuit_hash, verifier_digest)
+        .expect("Failed to verify root verifier bundle");
+
+    let bytes = root_verifier_bundle
+        .to_bytes(&DefaultGateSerializer)
+        .expect("Failed to serialize root verifier bundle");
+    let deserialized_root_verifier_bundle =
+        RootVerifierBundle::<F, C, D>::from_bytes(&bytes, &DefaultGateSerializer)
+            .expect("Failed to deserialize root verifier bundle");
+    deserialized_root_verifier_bundle
+        .verify(input_hash, circuit_hash, verifier_digest)
+        .expect("Failed to verify deserialized root verifier bundle");
+
+    // a bundle is rejected if it does not match the expectations of the caller, whatever hashes
+    // it claims
+    assert!(matches!(
+        deserialized_root_verifier_bundle.verify(HashOut::ZERO, circuit_hash, verifier_digest),
+        Err(ZkTreeError::InputHashMismatch { .. })
+    ));
+    assert!(matches!(
+        deserialized_root_verifier_bundle.verify(input_hash, HashOut::ZERO, verifier_digest),
+        Err(ZkTreeError::CircuitHashMismatch { .. })
+    ));
+    assert!(matches!(
+        deserialized_root_verifier_bundle.verify(input_hash, circuit_hash, HashOut::ZERO),
+        Err(ZkTreeError::VerifierCircuitDigestMismatch)
+    ));
+
+    // nor if the hashes it claims are not the ones exposed by the root proof, the input hash and
+    // circuit hash being serialized last
+    let mut tampered_bytes = bytes.clone();
+    let input_hash_offset = tampered_bytes.len() - 2 * NUM_HASH_OUT_ELTS * 8;
+    tampered_bytes[input_hash_offset] ^= 1;
+    let tampered_root_verifier_bundle =
+        RootVerifierBundle::<F, C, D>::from_bytes(&tampered_bytes, &DefaultGateSerializer)
+            .expect("Failed to deserialize root verifier bundle");
+    assert_ne!(tampered_root_verifier_bundle.input_hash(), input_hash);
+    assert!(matches!(
+        tampered_root_verifier_bundle.verify(input_hash, circuit_hash, verifier_digest),
+        Err(ZkTreeError::InputHashMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_zktree_root_verifier_bundle() {
+    zktree_root_verifier_bundle::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+#[test]
+fn test_zktree_root_verifier_bundle_with_double_poseidon_config() {
+    zktree_root_verifier_bundle::<DoublePoseidonGoldilocksConfig, DoublePoseidonHash>();
+}
+
+fn zktree_with_allow_list<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+    let (a3, proof_data3) = circuit_3::<C>();
+
+    let allow_list = CircuitDigestAllowList::<F, H>::new(vec![
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data3.circuit_data.verifier_only.circuit_digest,
+    ])
+    .expect("Failed to build allow-list");
 
     let user_proof1 = UserProof::new(
         vec![vec![a1]],
@@ -346,20 +860,1285 @@ fn test_zktree_fails_if_user_proof_is_malformed() {
         proof_data3.circuit_data.verifier_only.circuit_digest,
         proof_data3,
     );
-    let user_proof4 = UserProof::new(
-        vec![vec![a4]],
-        proof_data4.circuit_data.verifier_only.circuit_digest,
-        proof_data4,
+
+    let zktree = ZkTree::new_with_allow_list(
+        vec![user_proof1, user_proof2, user_proof3],
+        &allow_list,
+        &LeafCircuitCache::new(),
+    )
+    .expect("Failed to generate ZkTree from user proofs");
+    assert_eq!(zktree.allow_list_root(), Some(allow_list.root()));
+    zktree
+        .verify_with_allow_list(allow_list.root())
+        .expect("Failed to verify zkTree");
+    assert!(matches!(
+        zktree.verify_with_allow_list(HashOut::ZERO),
+        Err(ZkTreeError::AllowListRootMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_zktree_with_allow_list() {
+    zktree_with_allow_list::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+#[test]
+fn test_zktree_with_allow_list_with_double_poseidon_config() {
+    zktree_with_allow_list::<DoublePoseidonGoldilocksConfig, DoublePoseidonHash>();
+}
+
+#[test]
+fn test_zktree_fails_if_user_circuit_is_not_allowed() {
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+
+    let allow_list = CircuitDigestAllowList::<F, PoseidonHash>::new(vec![
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+    ])
+    .expect("Failed to build allow-list");
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    let user_proof2 = UserProof::new(
+        vec![vec![a2]],
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data2,
     );
 
-    let zktree = ZkTree::new(vec![user_proof1, user_proof2, user_proof3, user_proof4])
-        .expect("Failed to generate ZkTree from user proofs");
+    match ZkTree::new_with_allow_list(
+        vec![user_proof1, user_proof2],
+        &allow_list,
+        &LeafCircuitCache::new(),
+    ) {
+        Err(error @ ZkTreeError::CircuitNotAllowed { .. }) => assert!(error.is_user_fault()),
+        _ => panic!("User circuit is not allowed"),
+    }
+}
 
-    let root = zktree.root();
-    let root_proof_with_pis = &root.proof().proof_with_pis;
-    assert!(root
+fn zktree_append<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let user_proofs = user_proofs::<C>(5);
+
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let mut zktree =
+        ZkTree::new_with_leaf_circuit_cache(user_proofs[..3].to_vec(), &leaf_circuit_cache)
+            .expect("Failed to generate ZkTree from user proofs");
+
+    // the fourth user proof takes the place of the padding leaf
+    zktree
+        .append_with_leaf_circuit_cache(user_proofs[3].clone(), &leaf_circuit_cache)
+        .expect("Failed to append user proof");
+    assert_eq!(zktree.get_user_proofs().len(), 4);
+    assert_eq!(zktree.get_leaf_proofs().len(), 4);
+    assert_eq!(zktree.get_node_proofs().len(), 3);
+    zktree.verify().expect("Failed to verify zkTree");
+
+    // the fifth user proof does not fit, so that the ZkTree grows a level
+    zktree
+        .append_with_leaf_circuit_cache(user_proofs[4].clone(), &leaf_circuit_cache)
+        .expect("Failed to append user proof");
+    assert_eq!(zktree.get_user_proofs().len(), 5);
+    assert_eq!(zktree.get_leaf_proofs().len(), 8);
+    assert_eq!(zktree.get_node_proofs().len(), 7);
+    zktree.verify().expect("Failed to verify zkTree");
+
+    let fresh_zktree = ZkTree::new_with_leaf_circuit_cache(user_proofs, &leaf_circuit_cache)
+        .expect("Failed to generate ZkTree from user proofs");
+    assert_eq!(zktree.root().input_hash(), fresh_zktree.root().input_hash());
+    assert_eq!(
+        zktree.root().circuit_hash(),
+        fresh_zktree.root().circuit_hash()
+    );
+
+    let root_input_hash = zktree.root().input_hash();
+    for (index, user_proof) in zktree.get_user_proofs().into_iter().enumerate() {
+        zktree
+            .inclusion_proof(index)
+            .expect("Failed to generate inclusion proof")
+            .verify(&user_proof.user_public_inputs().concat(), root_input_hash)
+            .expect("Failed to verify inclusion proof");
+    }
+}
+
+#[test]
+fn test_zktree_append() {
+    zktree_append::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+fn zktree_update_leaf<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let user_proofs = user_proofs::<C>(5);
+    let (a, proof_data) = circuit_3::<C>();
+    let updated_user_proof = UserProof::new(
+        vec![vec![a]],
+        proof_data.circuit_data.verifier_only.circuit_digest,
+        proof_data,
+    );
+
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let mut zktree = ZkTree::new_with_leaf_circuit_cache(user_proofs.clone(), &leaf_circuit_cache)
+        .expect("Failed to generate ZkTree from user proofs");
+    let node_proofs_input_hashes = zktree
+        .get_node_proofs()
+        .into_iter()
+        .map(|node_proof| node_proof.input_hash())
+        .collect::<Vec<_>>();
+
+    zktree
+        .update_leaf_with_leaf_circuit_cache(1, updated_user_proof.clone(), &leaf_circuit_cache)
+        .expect("Failed to update leaf");
+    assert_eq!(zktree.get_user_proofs().len(), 5);
+    assert_eq!(zktree.get_leaf_proofs().len(), 8);
+    assert_eq!(zktree.get_node_proofs().len(), 7);
+    zktree.verify().expect("Failed to verify zkTree");
+
+    // only the ancestors of the second leaf, at indices 0, 4 and 6, are proven again
+    let updated_node_proofs_input_hashes = zktree
+        .get_node_proofs()
+        .into_iter()
+        .map(|node_proof| node_proof.input_hash())
+        .collect::<Vec<_>>();
+    for (index, (input_hash, updated_input_hash)) in node_proofs_input_hashes
+        .iter()
+        .zip(&updated_node_proofs_input_hashes)
+        .enumerate()
+    {
+        assert_eq!(
+            input_hash == updated_input_hash,
+            ![0, 4, 6].contains(&index)
+        );
+    }
+
+    let mut updated_user_proofs = user_proofs;
+    updated_user_proofs[1] = updated_user_proof;
+    let fresh_zktree =
+        ZkTree::new_with_leaf_circuit_cache(updated_user_proofs, &leaf_circuit_cache)
+            .expect("Failed to generate ZkTree from user proofs");
+    assert_eq!(
+        updated_node_proofs_input_hashes,
+        fresh_zktree
+            .get_node_proofs()
+            .into_iter()
+            .map(|node_proof| node_proof.input_hash())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        zktree.root().circuit_hash(),
+        fresh_zktree.root().circuit_hash()
+    );
+
+    let root_input_hash = zktree.root().input_hash();
+    for (index, user_proof) in zktree.get_user_proofs().into_iter().enumerate() {
+        zktree
+            .inclusion_proof(index)
+            .expect("Failed to generate inclusion proof")
+            .verify(&user_proof.user_public_inputs().concat(), root_input_hash)
+            .expect("Failed to verify inclusion proof");
+    }
+
+    // padding leaves cannot be updated
+    let (a, proof_data) = circuit_1::<C>();
+    let user_proof = UserProof::new(
+        vec![vec![a]],
+        proof_data.circuit_data.verifier_only.circuit_digest,
+        proof_data,
+    );
+    assert!(zktree
+        .update_leaf_with_leaf_circuit_cache(5, user_proof, &leaf_circuit_cache)
+        .is_err());
+}
+
+#[test]
+fn test_zktree_update_leaf() {
+    zktree_update_leaf::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+fn zktree_merge<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let user_proofs = user_proofs::<C>(4);
+
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let left = ZkTree::new_with_leaf_circuit_cache(user_proofs[..2].to_vec(), &leaf_circuit_cache)
+        .expect("Failed to generate ZkTree from user proofs");
+    let right = ZkTree::new_with_leaf_circuit_cache(user_proofs[2..].to_vec(), &leaf_circuit_cache)
+        .expect("Failed to generate ZkTree from user proofs");
+
+    let zktree = ZkTree::merge(left, right).expect("Failed to merge ZkTrees");
+    assert_eq!(zktree.get_user_proofs().len(), 4);
+    assert_eq!(zktree.get_leaf_proofs().len(), 4);
+    assert_eq!(zktree.get_node_proofs().len(), 3);
+    zktree.verify().expect("Failed to verify zkTree");
+
+    let fresh_zktree = ZkTree::new_with_leaf_circuit_cache(user_proofs, &leaf_circuit_cache)
+        .expect("Failed to generate ZkTree from user proofs");
+    assert_eq!(zktree.root().input_hash(), fresh_zktree.root().input_hash());
+    assert_eq!(
+        zktree.root().circuit_hash(),
+        fresh_zktree.root().circuit_hash()
+    );
+
+    let root_input_hash = zktree.root().input_hash();
+    for (index, user_proof) in zktree.get_user_proofs().into_iter().enumerate() {
+        zktree
+            .inclusion_proof(index)
+            .expect("Failed to generate inclusion proof")
+            .verify(&user_proof.user_public_inputs().concat(), root_input_hash)
+            .expect("Failed to verify inclusion proof");
+    }
+}
+
+#[test]
+fn test_zktree_merge() {
+    zktree_merge::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+#[test]
+fn test_zktree_merge_fails_with_incompatible_trees() {
+    let user_proofs = user_proofs::<C>(4);
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let new_zktree = |user_proofs: &[UserProof<C, F, D>]| {
+        ZkTree::new_with_leaf_circuit_cache(user_proofs.to_vec(), &leaf_circuit_cache)
+            .expect("Failed to generate ZkTree from user proofs")
+    };
+
+    // the left ZkTree has a padding leaf
+    assert!(ZkTree::merge(
+        new_zktree(&user_proofs[..1]),
+        new_zktree(&user_proofs[1..3])
+    )
+    .is_err());
+    // the ZkTrees do not have the same height
+    assert!(ZkTree::merge(new_zktree(&user_proofs[..2]), new_zktree(&user_proofs[1..])).is_err());
+    // merging binary ZkTrees requires two of them
+    assert!(ZkTree::merge_k(vec![
+        new_zktree(&user_proofs[..2]),
+        new_zktree(&user_proofs[2..]),
+        new_zktree(&user_proofs[1..3]),
+    ])
+    .is_err());
+}
+
+fn zktree_builder_with_circuit_configs<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let mut user_proofs = user_proofs::<C>(6);
+    let last_user_proof = user_proofs.pop().expect("Missing user proof");
+
+    let leaf_config = CircuitConfig::standard_recursion_zk_config();
+    let node_config = CircuitConfig::standard_recursion_config();
+    let mut root_config = CircuitConfig::standard_recursion_config();
+    root_config.fri_config.rate_bits = 4;
+    root_config.fri_config.num_query_rounds = 21;
+
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let mut zktree = ZkTreeBuilder::new()
+        .leaf_config(leaf_config.clone())
+        .node_config(node_config.clone())
+        .root_config(root_config.clone())
+        .leaf_circuit_cache(&leaf_circuit_cache)
+        .build(user_proofs)
+        .expect("Failed to generate ZkTree from user proofs");
+
+    assert_eq!(zktree.circuit_configs().leaf, leaf_config);
+    assert_eq!(zktree.circuit_configs().node, node_config);
+    assert_eq!(zktree.circuit_configs().root, root_config);
+    assert!(zktree.get_leaf_proofs().iter().all(|leaf_proof| leaf_proof
         .proof()
         .circuit_data
-        .verify(root_proof_with_pis.clone())
+        .common
+        .config
+        == leaf_config));
+    let node_proofs = zktree.get_node_proofs();
+    assert!(node_proofs[..node_proofs.len() - 1]
+        .iter()
+        .all(|node_proof| node_proof.proof().circuit_data.common.config == node_config));
+    assert_eq!(
+        zktree.root().proof().circuit_data.common.config,
+        root_config
+    );
+    zktree.verify().expect("Failed to verify zkTree");
+
+    zktree
+        .append_with_leaf_circuit_cache(last_user_proof, &leaf_circuit_cache)
+        .expect("Failed to append user proof");
+    assert_eq!(
+        zktree.root().proof().circuit_data.common.config,
+        root_config
+    );
+    zktree.verify().expect("Failed to verify zkTree");
+}
+
+#[test]
+fn test_zktree_builder_with_circuit_configs() {
+    zktree_builder_with_circuit_configs::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+fn zktree_append_with_root_config<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let user_proofs = user_proofs::<C>(3);
+
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let builder = || {
+        ZkTreeBuilder::new()
+            .root_config(CircuitConfig::standard_recursion_config())
+            .leaf_circuit_cache(&leaf_circuit_cache)
+    };
+    let mut zktree = builder()
+        .build(user_proofs[..2].to_vec())
+        .expect("Failed to generate ZkTree from user proofs");
+
+    // the former root is proven again with the node config when the ZkTree grows
+    zktree
+        .append_with_leaf_circuit_cache(user_proofs[2].clone(), &leaf_circuit_cache)
+        .expect("Failed to append user proof");
+    zktree.verify().expect("Failed to verify zkTree");
+
+    let fresh_zktree = builder()
+        .build(user_proofs)
+        .expect("Failed to generate ZkTree from user proofs");
+    assert_eq!(
+        zktree
+            .get_node_proofs()
+            .iter()
+            .map(|node_proof| node_proof.circuit_verifier_digest())
+            .collect::<Vec<_>>(),
+        fresh_zktree
+            .get_node_proofs()
+            .iter()
+            .map(|node_proof| node_proof.circuit_verifier_digest())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(zktree.root().input_hash(), fresh_zktree.root().input_hash());
+    assert_eq!(
+        zktree.root().circuit_hash(),
+        fresh_zktree.root().circuit_hash()
+    );
+}
+
+#[test]
+fn test_zktree_append_with_root_config() {
+    zktree_append_with_root_config::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+fn zktree_merge_with_root_config<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let user_proofs = user_proofs::<C>(4);
+
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let build = |user_proofs: &[UserProof<C, F, D>]| {
+        ZkTreeBuilder::new()
+            .root_config(CircuitConfig::standard_recursion_config())
+            .leaf_circuit_cache(&leaf_circuit_cache)
+            .build(user_proofs.to_vec())
+            .expect("Failed to generate ZkTree from user proofs")
+    };
+
+    // the merged roots are proven again with the node config
+    let zktree = ZkTree::merge(build(&user_proofs[..2]), build(&user_proofs[2..]))
+        .expect("Failed to merge ZkTrees");
+    zktree.verify().expect("Failed to verify zkTree");
+
+    let fresh_zktree = build(&user_proofs);
+    assert_eq!(
+        zktree
+            .get_node_proofs()
+            .iter()
+            .map(|node_proof| node_proof.circuit_verifier_digest())
+            .collect::<Vec<_>>(),
+        fresh_zktree
+            .get_node_proofs()
+            .iter()
+            .map(|node_proof| node_proof.circuit_verifier_digest())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(zktree.root().input_hash(), fresh_zktree.root().input_hash());
+    assert_eq!(
+        zktree.root().circuit_hash(),
+        fresh_zktree.root().circuit_hash()
+    );
+}
+
+#[test]
+fn test_zktree_merge_with_root_config() {
+    zktree_merge_with_root_config::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+fn zktree_with_shrink_layers<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let mut user_proofs = user_proofs::<C>(4);
+    let last_user_proof = user_proofs.pop().expect("Missing user proof");
+
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let mut zktree = ZkTreeBuilder::new()
+        .node_config(CircuitConfig::standard_recursion_config())
+        .root_config(CircuitConfig::standard_recursion_config())
+        .shrink_configs(vec![high_rate_shrink_config(), high_rate_shrink_config()])
+        .leaf_circuit_cache(&leaf_circuit_cache)
+        .build(user_proofs)
+        .expect("Failed to generate ZkTree from user proofs");
+
+    assert_eq!(zktree.get_shrink_proofs().len(), 2);
+    let root = zktree.root();
+    let final_proof = zktree.final_proof();
+    assert_eq!(final_proof.input_hash(), root.input_hash());
+    assert_eq!(final_proof.circuit_hash(), root.circuit_hash());
+    assert_eq!(
+        final_proof.proof().proof_with_pis.public_inputs,
+        root.proof().proof_with_pis.public_inputs
+    );
+    assert!(
+        final_proof.proof().proof_with_pis.to_bytes().len()
+            < root.proof().proof_with_pis.to_bytes().len()
+    );
+    zktree.verify().expect("Failed to verify zkTree");
+    zktree
+        .root_verifier_bundle()
+        .verify(
+            zktree.root().input_hash(),
+            zktree.root().circuit_hash(),
+            zktree.final_proof().circuit_verifier_digest(),
+        )
+        .expect("Failed to verify root verifier bundle");
+
+    // the shrink layers wrap the updated root, with the same compiled shrink circuits
+    let final_verifier_digest = zktree.final_proof().circuit_verifier_digest();
+    zktree
+        .append_with_leaf_circuit_cache(last_user_proof.clone(), &leaf_circuit_cache)
+        .expect("Failed to append user proof");
+    assert_eq!(
+        zktree.final_proof().input_hash(),
+        zktree.root().input_hash()
+    );
+    assert_eq!(
+        zktree.final_proof().circuit_verifier_digest(),
+        final_verifier_digest
+    );
+    zktree.verify().expect("Failed to verify zkTree");
+    zktree
+        .root_verifier_bundle()
+        .verify(
+            zktree.root().input_hash(),
+            zktree.root().circuit_hash(),
+            zktree.final_proof().circuit_verifier_digest(),
+        )
+        .expect("Failed to verify root verifier bundle");
+
+    // growing changes the root circuit, so the shrink circuits are compiled again
+    zktree
+        .append_with_leaf_circuit_cache(last_user_proof, &leaf_circuit_cache)
+        .expect("Failed to append user proof");
+    assert_ne!(
+        zktree.final_proof().circuit_verifier_digest(),
+        final_verifier_digest
+    );
+    assert_eq!(
+        zktree.final_proof().circuit_hash(),
+        zktree.root().circuit_hash()
+    );
+    zktree.verify().expect("Failed to verify zkTree");
+}
+
+#[test]
+fn test_zktree_with_shrink_layers() {
+    zktree_with_shrink_layers::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+fn zktree_wrapped_with_keccak_config<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    let user_proof2 = UserProof::new(
+        vec![vec![a2]],
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data2,
+    );
+
+    let zktree = ZkTree::new(vec![user_proof1, user_proof2])
+        .expect("Failed to generate ZkTree from user proofs");
+    let wrapped_proof = zktree
+        .wrap_final_proof::<KeccakGoldilocksConfig>(CircuitConfig::standard_recursion_config())
+        .expect("Failed to wrap root proof");
+
+    let root = zktree.root();
+    assert_eq!(wrapped_proof.input_hash(), root.input_hash());
+    assert_eq!(wrapped_proof.circuit_hash(), root.circuit_hash());
+    assert_eq!(
+        wrapped_proof.proof().proof_with_pis.public_inputs,
+        root.proof().proof_with_pis.public_inputs
+    );
+    let verifier_digest = wrapped_proof
+        .proof()
+        .circuit_data
+        .verifier_only
+        .circuit_digest;
+    wrapped_proof
+        .verify(root.input_hash(), root.circuit_hash(), verifier_digest)
+        .expect("Failed to verify wrapped proof");
+
+    let bytes = wrapped_proof
+        .verifier_bundle()
+        .to_bytes(&DefaultGateSerializer)
+        .expect("Failed to serialize wrapped verifier bundle");
+    let deserialized_verifier_bundle =
+        RootVerifierBundle::<F, KeccakGoldilocksConfig, D>::from_bytes(
+            &bytes,
+            &DefaultGateSerializer,
+        )
+        .expect("Failed to deserialize wrapped verifier bundle");
+    deserialized_verifier_bundle
+        .verify(root.input_hash(), root.circuit_hash(), verifier_digest)
+        .expect("Failed to verify deserialized wrapped verifier bundle");
+
+    // a bundle is rejected if it does not match the expectations of the caller
+    assert!(matches!(
+        deserialized_verifier_bundle.verify(HashOut::ZERO, root.circuit_hash(), verifier_digest),
+        Err(ZkTreeError::InputHashMismatch { .. })
+    ));
+    assert!(matches!(
+        wrapped_proof.verify(
+            root.input_hash(),
+            root.circuit_hash(),
+            KeccakHash::<25>::hash_no_pad(&[F::ZERO])
+        ),
+        Err(ZkTreeError::VerifierCircuitDigestMismatch)
+    ));
+}
+
+#[test]
+fn test_zktree_wrapped_with_keccak_config() {
+    zktree_wrapped_with_keccak_config::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+fn zktree_with_common_leaf_shape<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = large_circuit_4::<C>();
+    let (a3, proof_data3) = circuit_3::<C>();
+    let small_degree_bits = proof_data1.circuit_data.common.degree_bits();
+    let large_degree_bits = proof_data2.circuit_data.common.degree_bits();
+    assert!(small_degree_bits < large_degree_bits);
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    let user_proof2 = UserProof::new(
+        vec![vec![a2]],
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data2,
+    );
+    let user_proof3 = UserProof::new(
+        vec![vec![a3]],
+        proof_data3.circuit_data.verifier_only.circuit_digest,
+        proof_data3,
+    );
+
+    // leaves of user circuits of different degrees cannot be paired
+    assert!(ZkTree::new(vec![user_proof1.clone(), user_proof2.clone()]).is_err());
+
+    let common_leaf_shape = CommonLeafShape::new(large_degree_bits, 13);
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let mut zktree = ZkTreeBuilder::new()
+        .common_leaf_shape(common_leaf_shape.clone())
+        .leaf_circuit_cache(&leaf_circuit_cache)
+        .build(vec![user_proof1.clone(), user_proof2.clone()])
+        .expect("Failed to generate ZkTree from user proofs of different degrees");
+    zktree.verify().expect("Failed to verify zkTree");
+    assert_eq!(
+        zktree.circuit_configs().common_leaf_shape,
+        Some(common_leaf_shape)
+    );
+
+    // the padded user proofs keep the user public inputs
+    let user_proofs = zktree.get_user_proofs();
+    assert_eq!(user_proofs[0].user_public_inputs(), vec![&[a1][..]]);
+    assert_eq!(user_proofs[1].user_public_inputs(), vec![&[a2][..]]);
+    assert_eq!(
+        user_proofs[0].proof().circuit_data.common,
+        user_proofs[1].proof().circuit_data.common
+    );
+    assert_eq!(user_proof1.input_hash(), user_proofs[0].input_hash());
+
+    // appended user proofs are padded as well
+    zktree
+        .append_with_leaf_circuit_cache(user_proof3, &leaf_circuit_cache)
+        .expect("Failed to append user proof");
+    zktree.verify().expect("Failed to verify zkTree");
+
+    // user circuits larger than the maximum degree are rejected
+    match ZkTreeBuilder::<C, F, H, D>::new()
+        .common_leaf_shape(CommonLeafShape::new(large_degree_bits - 1, 13))
+        .build(vec![user_proof1, user_proof2])
+    {
+        Err(ZkTreeError::InvalidUserProofs(errors)) => {
+            assert!(errors.iter().all(ZkTreeError::is_user_fault));
+            assert!(matches!(
+                errors.as_slice(),
+                [ZkTreeError::UserCircuitTooLarge {
+                    leaf_index: Some(1),
+                    degree_bits,
+                    max_degree_bits,
+                }] if *degree_bits == large_degree_bits && *max_degree_bits == large_degree_bits - 1
+            ));
+        }
+        _ => panic!("Too large user circuit is not rejected"),
+    }
+}
+
+#[test]
+fn test_zktree_with_common_leaf_shape() {
+    zktree_with_common_leaf_shape::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+#[test]
+fn test_zktree_append_fails_with_another_allow_list() {
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+
+    let allow_list = CircuitDigestAllowList::<F, PoseidonHash>::new(vec![
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+    ])
+    .expect("Failed to build allow-list");
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    let user_proof2 = UserProof::new(
+        vec![vec![a2]],
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data2,
+    );
+
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let mut zktree =
+        ZkTree::new_with_allow_list(vec![user_proof1], &allow_list, &leaf_circuit_cache)
+            .expect("Failed to generate ZkTree from user proofs");
+    assert!(zktree
+        .append_with_leaf_circuit_cache(user_proof2.clone(), &leaf_circuit_cache)
         .is_err());
+    zktree
+        .append_with_allow_list(user_proof2, &allow_list, &leaf_circuit_cache)
+        .expect("Failed to append user proof");
+    zktree
+        .verify_with_allow_list(allow_list.root())
+        .expect("Failed to verify zkTree");
+}
+
+#[test]
+fn test_zktree_fails_with_public_inputs_length_mismatch() {
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    // the second user circuit has a single public input
+    let user_proof2 = UserProof::new(
+        vec![vec![a2, a2]],
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data2,
+    );
+
+    match ZkTree::new(vec![user_proof1, user_proof2]) {
+        Err(ZkTreeError::InvalidUserProofs(errors)) => {
+            assert!(errors.iter().all(ZkTreeError::is_user_fault));
+            assert!(matches!(
+                errors.as_slice(),
+                [ZkTreeError::PublicInputsLengthMismatch {
+                    leaf_index: Some(1),
+                    expected: 1,
+                    actual: 2,
+                }]
+            ));
+        }
+        _ => panic!("Public inputs length mismatch is not rejected"),
+    }
+}
+
+#[test]
+fn test_zktree_fails_without_user_proofs() {
+    assert!(matches!(
+        ZkTree::<C, F, PoseidonHash, D>::new(vec![]),
+        Err(ZkTreeError::NoUserProofs)
+    ));
+}
+
+#[test]
+fn test_zktree_fails_if_user_proof_is_malformed() {
+    let (a1, proof_data1) = malformed_circuit_1();
+    let (a2, proof_data2) = circuit_2::<C>();
+    let (a3, proof_data3) = circuit_3::<C>();
+    let (a4, proof_data4) = circuit_4::<C>();
+
+    assert!(proof_data1
+        .circuit_data
+        .verify(proof_data1.proof_with_pis.clone())
+        .is_err());
+    assert!(proof_data2
+        .circuit_data
+        .verify(proof_data2.proof_with_pis.clone())
+        .is_ok());
+    assert!(proof_data3
+        .circuit_data
+        .verify(proof_data3.proof_with_pis.clone())
+        .is_ok());
+    assert!(proof_data4
+        .circuit_data
+        .verify(proof_data4.proof_with_pis.clone())
+        .is_ok());
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    let user_proof2 = UserProof::new(
+        vec![vec![a2]],
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data2,
+    );
+    let user_proof3 = UserProof::new(
+        vec![vec![a3]],
+        proof_data3.circuit_data.verifier_only.circuit_digest,
+        proof_data3,
+    );
+    let user_proof4 = UserProof::new(
+        vec![vec![a4]],
+        proof_data4.circuit_data.verifier_only.circuit_digest,
+        proof_data4,
+    );
+
+    match ZkTree::new(vec![user_proof1, user_proof2, user_proof3, user_proof4]) {
+        Err(ZkTreeError::InvalidUserProofs(errors)) => assert!(matches!(
+            errors.as_slice(),
+            [ZkTreeError::UserProofVerification {
+                leaf_index: Some(0),
+                ..
+            }]
+        )),
+        _ => panic!("Malformed user proof is not rejected"),
+    }
+}
+
+#[test]
+fn test_zktree_reports_all_invalid_user_proofs() {
+    let (a1, proof_data1) = circuit_1::<C>();
+    let (a2, proof_data2) = circuit_2::<C>();
+    let (a3, proof_data3) = circuit_3::<C>();
+    let (a4, proof_data4) = circuit_4::<C>();
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    // the user circuit hash is not the verifier digest of the second user circuit
+    let forged_circuit_hash = PoseidonHash::hash_or_noop(&F::rand_array::<4>());
+    let user_proof2 = UserProof::new(vec![vec![a2]], forged_circuit_hash, proof_data2);
+    let user_proof3 = UserProof::new(
+        vec![vec![a3]],
+        proof_data3.circuit_data.verifier_only.circuit_digest,
+        proof_data3,
+    );
+    // the user public inputs are not the public inputs of the fourth user proof
+    let user_proof4 = UserProof::new(
+        vec![vec![a4 + F::ONE]],
+        proof_data4.circuit_data.verifier_only.circuit_digest,
+        proof_data4,
+    );
+
+    match ZkTree::new(vec![user_proof1, user_proof2, user_proof3, user_proof4]) {
+        Err(ZkTreeError::InvalidUserProofs(errors)) => {
+            assert!(matches!(
+                errors.as_slice(),
+                [
+                    ZkTreeError::UserCircuitHashMismatch {
+                        leaf_index: Some(1),
+                        ..
+                    },
+                    ZkTreeError::PublicInputsMismatch {
+                        leaf_index: Some(3)
+                    },
+                ]
+            ));
+            assert!(errors.iter().all(ZkTreeError::is_user_fault));
+        }
+        _ => panic!("Invalid user proofs are not all rejected"),
+    }
+}
+
+#[test]
+fn test_zktree_skipping_invalid_user_proofs() {
+    let (a1, proof_data1) = malformed_circuit_1();
+    let (a2, proof_data2) = circuit_2::<C>();
+    let (a3, proof_data3) = circuit_3::<C>();
+    let (a4, proof_data4) = circuit_4::<C>();
+    let (a5, proof_data5) = circuit_1::<C>();
+
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+    let user_proof2 = UserProof::new(
+        vec![vec![a2]],
+        proof_data2.circuit_data.verifier_only.circuit_digest,
+        proof_data2,
+    );
+    let forged_circuit_hash = PoseidonHash::hash_or_noop(&F::rand_array::<4>());
+    let user_proof3 = UserProof::new(vec![vec![a3]], forged_circuit_hash, proof_data3);
+    let user_proof4 = UserProof::new(
+        vec![vec![a4]],
+        proof_data4.circuit_data.verifier_only.circuit_digest,
+        proof_data4,
+    );
+    let user_proof5 = UserProof::new(
+        vec![vec![a5]],
+        proof_data5.circuit_data.verifier_only.circuit_digest,
+        proof_data5,
+    );
+
+    let filtered_zktree = ZkTree::new_skipping_invalid(
+        vec![
+            user_proof1,
+            user_proof2,
+            user_proof3,
+            user_proof4,
+            user_proof5,
+        ],
+        &LeafCircuitCache::new(),
+    )
+    .expect("Failed to generate ZkTree from valid user proofs");
+
+    assert_eq!(filtered_zktree.included_indices(), &[1, 3, 4]);
+    assert_eq!(filtered_zktree.submitted_index(2), Some(4));
+    assert_eq!(filtered_zktree.submitted_index(3), None);
+    assert_eq!(filtered_zktree.rejected_indices(), vec![0, 2]);
+    assert!(matches!(
+        filtered_zktree.rejected(),
+        [
+            ZkTreeError::UserProofVerification { .. },
+            ZkTreeError::UserCircuitHashMismatch { .. },
+        ]
+    ));
+
+    let zktree = filtered_zktree.into_zktree();
+    assert_eq!(zktree.get_user_proofs().len(), 3);
+    assert_eq!(zktree.get_leaf_proofs().len(), 4);
+    assert_eq!(
+        zktree.get_user_proofs()[2].user_public_inputs(),
+        vec![&[a5][..]]
+    );
+    zktree.verify().expect("Failed to verify zkTree");
+}
+
+#[test]
+fn test_zktree_skipping_invalid_fails_without_valid_user_proofs() {
+    let (a1, proof_data1) = malformed_circuit_1();
+    let user_proof1 = UserProof::new(
+        vec![vec![a1]],
+        proof_data1.circuit_data.verifier_only.circuit_digest,
+        proof_data1,
+    );
+
+    match ZkTree::<C, F, PoseidonHash, D>::new_skipping_invalid(
+        vec![user_proof1],
+        &LeafCircuitCache::new(),
+    ) {
+        Err(ZkTreeError::InvalidUserProofs(errors)) => assert_eq!(errors.len(), 1),
+        _ => panic!("ZkTree without valid user proofs is not rejected"),
+    }
+}
+
+fn zktree_with_cyclic_node_circuit<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let user_proofs = user_proofs::<C>(5);
+
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let new_zktree = |user_proofs: &[UserProof<C, F, D>]| {
+        ZkTreeBuilder::new()
+            .cyclic_node_config(CircuitConfig::standard_recursion_config())
+            .shrink_configs(vec![high_rate_shrink_config()])
+            .leaf_circuit_cache(&leaf_circuit_cache)
+            .build(user_proofs.to_vec())
+            .expect("Failed to generate ZkTree from user proofs")
+    };
+    let mut zktree = new_zktree(&user_proofs[..3]);
+    zktree.verify().expect("Failed to verify zkTree");
+    assert_eq!(zktree.get_cyclic_node_proofs().len(), 3);
+    assert!(zktree.get_node_proofs().is_empty());
+
+    // every node is proven with the same cyclic node circuit, whose digest the circuit hash folds
+    // in at every height
+    let verifier_circuit_digest = zktree
+        .cyclic_root()
+        .expect("Failed to retrieve cyclic root")
+        .circuit_verifier_digest();
+    assert!(zktree
+        .get_cyclic_node_proofs()
+        .iter()
+        .all(|node_proof| node_proof.circuit_verifier_digest() == verifier_circuit_digest));
+    let mut circuit_hashes = zktree
+        .get_leaf_proofs()
+        .iter()
+        .map(|leaf_proof| leaf_proof.circuit_hash())
+        .collect::<Vec<_>>();
+    while circuit_hashes.len() > 1 {
+        circuit_hashes = circuit_hashes
+            .chunks(zktree.arity())
+            .map(|children_circuit_hashes| {
+                node_circuit_hash::<F, H>(children_circuit_hashes, verifier_circuit_digest)
+            })
+            .collect();
+    }
+    assert_eq!(
+        zktree
+            .cyclic_root()
+            .expect("Failed to retrieve cyclic root")
+            .circuit_hash(),
+        circuit_hashes[0]
+    );
+    assert_eq!(zktree.allow_list_root(), None);
+    let leaf_verifier_circuit_digest = zktree.get_leaf_proofs()[0].circuit_verifier_digest();
+    zktree
+        .verify_with_circuit_digests(leaf_verifier_circuit_digest, &[verifier_circuit_digest; 2])
+        .expect("Failed to verify zkTree against the cyclic node circuit digest");
+    assert!(matches!(
+        zktree.verify_with_circuit_digests(
+            leaf_verifier_circuit_digest,
+            &[leaf_verifier_circuit_digest, verifier_circuit_digest]
+        ),
+        Err(ZkTreeError::CircuitHashMismatch { .. })
+    ));
+
+    // the final proof exposes the public inputs of the root without its verifier data
+    let final_proof = zktree.final_proof();
+    assert_eq!(
+        final_proof.proof().proof_with_pis.public_inputs,
+        zktree
+            .cyclic_root()
+            .expect("Failed to retrieve cyclic root")
+            .node_public_inputs()
+    );
+    zktree
+        .root_verifier_bundle()
+        .verify(
+            zktree
+                .cyclic_root()
+                .expect("Failed to retrieve cyclic root")
+                .input_hash(),
+            zktree
+                .cyclic_root()
+                .expect("Failed to retrieve cyclic root")
+                .circuit_hash(),
+            final_proof.circuit_verifier_digest(),
+        )
+        .expect("Failed to verify root verifier bundle");
+
+    // a root exposing other verifier data than its circuit is rejected
+    let mut forged_proof_data = zktree
+        .cyclic_root()
+        .expect("Failed to retrieve cyclic root")
+        .proof()
+        .clone();
+    *forged_proof_data
+        .proof_with_pis
+        .public_inputs
+        .last_mut()
+        .expect("Failed to retrieve root public inputs") += F::ONE;
+    let forged_root = CyclicNodeProof::<C, F, H, D>::new(
+        forged_proof_data,
+        zktree
+            .cyclic_root()
+            .expect("Failed to retrieve cyclic root")
+            .input_hash(),
+        zktree
+            .cyclic_root()
+            .expect("Failed to retrieve cyclic root")
+            .circuit_hash(),
+    );
+    assert!(matches!(
+        forged_root.verify(),
+        Err(ZkTreeError::CyclicVerifierDataMismatch)
+    ));
+
+    // appending fills the padding leaf, then grows the ZkTree with the same circuit
+    for user_proof in &user_proofs[3..] {
+        zktree
+            .append_with_leaf_circuit_cache(user_proof.clone(), &leaf_circuit_cache)
+            .expect("Failed to append user proof");
+        zktree.verify().expect("Failed to verify zkTree");
+    }
+    assert_eq!(zktree.get_leaf_proofs().len(), 8);
+    assert_eq!(zktree.get_cyclic_node_proofs().len(), 7);
+    assert_eq!(
+        zktree
+            .cyclic_root()
+            .expect("Failed to retrieve cyclic root")
+            .circuit_verifier_digest(),
+        verifier_circuit_digest
+    );
+    zktree
+        .update_leaf_with_leaf_circuit_cache(0, user_proofs[4].clone(), &leaf_circuit_cache)
+        .expect("Failed to update leaf");
+    zktree.verify().expect("Failed to verify zkTree");
+
+    let root_input_hash = zktree
+        .cyclic_root()
+        .expect("Failed to retrieve cyclic root")
+        .input_hash();
+    let root_circuit_hash = zktree
+        .cyclic_root()
+        .expect("Failed to retrieve cyclic root")
+        .circuit_hash();
+    for (index, user_proof) in zktree.get_user_proofs().into_iter().enumerate() {
+        zktree
+            .inclusion_proof(index)
+            .expect("Failed to generate inclusion proof")
+            .verify(&user_proof.user_public_inputs().concat(), root_input_hash)
+            .expect("Failed to verify inclusion proof");
+    }
+    // the trees hold the prover data of their circuits, keep only one of them alive at once
+    drop(zktree);
+
+    let updated_user_proofs = std::iter::once(user_proofs[4].clone())
+        .chain(user_proofs[1..].iter().cloned())
+        .collect::<Vec<_>>();
+    let fresh_zktree = new_zktree(&updated_user_proofs);
+    assert_eq!(
+        fresh_zktree
+            .cyclic_root()
+            .expect("Failed to retrieve cyclic root")
+            .input_hash(),
+        root_input_hash
+    );
+    assert_eq!(
+        fresh_zktree
+            .cyclic_root()
+            .expect("Failed to retrieve cyclic root")
+            .circuit_hash(),
+        root_circuit_hash
+    );
+    drop(fresh_zktree);
+
+    // merging keeps the former roots and proves the new root with the same circuit
+    let merged_zktree = ZkTree::merge(
+        new_zktree(&user_proofs[..2]),
+        new_zktree(&user_proofs[2..4]),
+    )
+    .expect("Failed to merge ZkTrees");
+    merged_zktree.verify().expect("Failed to verify zkTree");
+    let merged_root = merged_zktree
+        .cyclic_root()
+        .expect("Failed to retrieve cyclic root");
+    let (root_input_hash, root_circuit_hash) =
+        (merged_root.input_hash(), merged_root.circuit_hash());
+    drop(merged_zktree);
+    let fresh_zktree = new_zktree(&user_proofs[..4]);
+    assert_eq!(
+        fresh_zktree
+            .cyclic_root()
+            .expect("Failed to retrieve cyclic root")
+            .input_hash(),
+        root_input_hash
+    );
+    assert_eq!(
+        fresh_zktree
+            .cyclic_root()
+            .expect("Failed to retrieve cyclic root")
+            .circuit_hash(),
+        root_circuit_hash
+    );
+    drop(fresh_zktree);
+
+    // the node circuits of a ZkTree cannot be mixed with a cyclic node circuit
+    assert!(matches!(
+        ZkTree::merge(
+            new_zktree(&user_proofs[..2]),
+            ZkTree::new_with_leaf_circuit_cache(user_proofs[2..4].to_vec(), &leaf_circuit_cache)
+                .expect("Failed to generate ZkTree from user proofs"),
+        ),
+        Err(ZkTreeError::IncompatibleZkTree { index: 1 })
+    ));
+
+    // cyclic recursion does not support zero-knowledge configs
+    assert!(matches!(
+        ZkTreeBuilder::new()
+            .cyclic_node_config(CircuitConfig::standard_recursion_zk_config())
+            .build(user_proofs),
+        Err(ZkTreeError::ZeroKnowledgeCyclicConfig)
+    ));
+}
+
+#[test]
+fn test_zktree_with_cyclic_node_circuit() {
+    zktree_with_cyclic_node_circuit::<PoseidonGoldilocksConfig, PoseidonHash>();
+}
+
+#[test]
+fn test_cyclic_node_circuit_fails_without_common_data_fixed_point() {
+    let zktree = ZkTree::new(user_proofs::<C>(2)).expect("Failed to generate ZkTree");
+    let leaf_proofs = zktree.get_leaf_proofs();
+
+    // a single round cannot reach the fixed point, starting from the common data of an empty
+    // circuit
+    let cyclic_node_circuit = CyclicNodeCircuit::new(
+        leaf_proofs[0].proof(),
+        CyclicNodeChildren::Leaves(leaf_proofs.clone()),
+    )
+    .with_max_common_data_rounds(1);
+    assert!(matches!(
+        cyclic_node_circuit.build_compiled_circuit(),
+        Err(ZkTreeError::CyclicCommonDataFixedPoint { rounds: 1 })
+    ));
+}
+
+fn zktree_circuit_commitment<C, H>()
+where
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let user_proofs = user_proofs::<C>(3);
+
+    let leaf_circuit_cache = LeafCircuitCache::new();
+    let zktree = ZkTree::new_with_leaf_circuit_cache(user_proofs.clone(), &leaf_circuit_cache)
+        .expect("Failed to generate ZkTree from user proofs");
+    zktree.verify().expect("Failed to verify zkTree");
+
+    // the circuit digests are pinned from another ZkTree built with the same circuits
+    let pinned_zktree = ZkTree::new_with_leaf_circuit_cache(user_proofs, &leaf_circuit_cache)
+        .expect("Failed to generate ZkTree from user proofs");
+    let leaf_verifier_circuit_digest = pinned_zktree.get_leaf_proofs()[0].circuit_verifier_digest();
+    let node_proofs = pinned_zktree.get_node_proofs();
+    let node_verifier_circuit_digests = [
+        node_proofs[0].circuit_verifier_digest(),
+        pinned_zktree.root().circuit_verifier_digest(),
+    ];
+    zktree
+        .verify_with_circuit_digests(leaf_verifier_circuit_digest, &node_verifier_circuit_digests)
+        .expect("Failed to verify zkTree against the pinned circuit digests");
+
+    // a wrong leaf or node circuit digest is rejected
+    assert!(matches!(
+        zktree.verify_with_circuit_digests(
+            node_verifier_circuit_digests[0],
+            &node_verifier_circuit_digests
+        ),
+        Err(ZkTreeError::CircuitHashMismatch { .. })
+    ));
+    assert!(matches!(
+        zktree.verify_with_circuit_digests(
+            leaf_verifier_circuit_digest,
+            &[
+                node_verifier_circuit_digests[1],
+                node_verifier_circuit_digests[1]
+            ]
+        ),
+        Err(ZkTreeError::CircuitHashMismatch { .. })
+    ));
+    // as is a root that is not a proof of the pinned root circuit
+    assert!(matches!(
+        zktree.verify_with_circuit_digests(
+            leaf_verifier_circuit_digest,
+            &[
+                node_verifier_circuit_digests[0],
+                node_verifier_circuit_digests[0]
+            ]
+        ),
+        Err(ZkTreeError::VerifierCircuitDigestMismatch)
+    ));
+
+    let mut user_circuit_hashes = zktree
+        .get_user_proofs()
+        .iter()
+        .map(|user_proof| user_proof.circuit_hash())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        circuit_commitment::<F, H>(
+            &user_circuit_hashes,
+            leaf_verifier_circuit_digest,
+            &node_verifier_circuit_digests,
+        )
+        .expect("Failed to compute circuit commitment"),
+        zktree.root().circuit_hash()
+    );
+
+    // the circuit commitment binds the order of the user circuits
+    user_circuit_hashes.swap(0, 1);
+    assert_ne!(
+        circuit_commitment::<F, H>(
+            &user_circuit_hashes,
+            leaf_verifier_circuit_digest,
+            &node_verifier_circuit_digests,
+        )
+        .expect("Failed to compute circuit commitment"),
+        zktree.root().circuit_hash()
+    );
+
+    // as well as the node circuits
+    assert_ne!(
+        circuit_commitment::<F, H>(
+            &zktree
+                .get_user_proofs()
+                .iter()
+                .map(|user_proof| user_proof.circuit_hash())
+                .collect::<Vec<_>>(),
+            leaf_verifier_circuit_digest,
+            &[
+                node_verifier_circuit_digests[1],
+                node_verifier_circuit_digests[0]
+            ],
+        )
+        .expect("Failed to compute circuit commitment"),
+        zktree.root().circuit_hash()
+    );
+}
+
+#[test]
+fn test_zktree_circuit_commitment() {
+    zktree_circuit_commitment::<PoseidonGoldilocksConfig, PoseidonHash>();
 }
@@ -1,3 +1,4 @@
+use crate::compiled_circuit::CompiledCircuit;
 use plonky2::{
     field::extension::Extendable,
     hash::hash_types::RichField,
@@ -12,7 +13,7 @@ use plonky2::{
 ///
 /// * `C`: Represents the configuration for the circuit, must satisfy `GenericConfig`.
 /// * `F`: The field type that must implement `RichField` for cryptographic operations and
-///        `Extendable<D>` for field extensions.
+///   `Extendable<D>` for field extensions.
 /// * `D`: A compile-time constant that defines the dimension of the field extension.
 ///
 /// # Associated Types
@@ -54,4 +55,17 @@ where
     ///
     /// Consumes a mutable reference to self, which may alter the compiler's state.
     fn compile_and_build(&mut self) -> (CircuitData<F, C, D>, Self::Targets, Self::OutTargets);
+
+    /// Compiles and builds the circuit into a `CompiledCircuit`, which can then be shared to
+    /// generate proofs for every instance of the same circuit without rebuilding it.
+    ///
+    /// # Returns
+    ///
+    /// A `CompiledCircuit` holding the circuit data and the input and output targets.
+    fn build_compiled_circuit(
+        &mut self,
+    ) -> CompiledCircuit<F, C, Self::Targets, Self::OutTargets, D> {
+        let (circuit_data, targets, out_targets) = self.compile_and_build();
+        CompiledCircuit::new(circuit_data, targets, out_targets)
+    }
 }
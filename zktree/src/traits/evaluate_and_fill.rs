@@ -1,10 +1,10 @@
-use anyhow::Error;
 use plonky2::{
     field::extension::Extendable, hash::hash_types::RichField, iop::witness::PartialWitness,
     plonk::config::GenericConfig,
 };
 
 use super::circuit_compiler::CircuitCompiler;
+use crate::error::ZkTreeError;
 
 /// The `EvaluateFillCircuit` trait extends the `CircuitCompiler` trait to provide functionality
 /// for evaluating a compiled circuit and filling its witness. It is used to both execute the
@@ -30,7 +30,7 @@ use super::circuit_compiler::CircuitCompiler;
 ///
 /// # Returns
 ///
-/// * `Result<PartialWitness<F>, Error>`: A result that, on success, contains the `PartialWitness<F>`
+/// * `Result<PartialWitness<F>, ZkTreeError>`: A result that, on success, contains the `PartialWitness<F>`
 ///   populated with the circuit's data, or an error if the process fails.
 pub trait EvaluateFillCircuit<C, F, const D: usize>: CircuitCompiler<C, F, D>
 where
@@ -59,11 +59,11 @@ where
     ///
     /// # Returns
     ///
-    /// A `Result` that, on success, contains the populated `PartialWitness<F>`, or an `Error` if
-    /// filling the witness fails.
+    /// A `Result` that, on success, contains the populated `PartialWitness<F>`, or a `ZkTreeError`
+    /// if filling the witness fails.
     fn fill(
         &self,
         targets: Self::Targets,
         out_targets: Self::OutTargets,
-    ) -> Result<PartialWitness<F>, Error>;
+    ) -> Result<PartialWitness<F>, ZkTreeError>;
 }
@@ -2,3 +2,4 @@ pub mod circuit_compiler;
 pub mod evaluate_and_fill;
 pub mod proof;
 pub mod provable;
+pub mod serializable;
@@ -14,7 +14,7 @@ use crate::proof_data::ProofData;
 /// # Type Parameters
 ///
 /// * `F`: The field type used in the circuit, implementing `RichField` for cryptographic operations
-///        and `Extendable<D>` for field extensions.
+///   and `Extendable<D>` for field extensions.
 /// * `C`: The circuit configuration, satisfying `GenericConfig`.
 /// * `D`: A compile-time constant that defines the dimension of the field extension.
 ///
@@ -29,6 +29,8 @@ use crate::proof_data::ProofData;
 /// * `circuit_hash`: Returns a hash representing the circuit itself.
 ///
 /// * `proof`: Accesses the `ProofData` which contains all the necessary information for proof verification.
+///
+/// * `node_public_inputs`: Returns the public inputs the proof exposes as a zkTree node.
 pub trait Proof<C, F, const D: usize>
 where
     F: RichField + Extendable<D>,
@@ -71,4 +73,17 @@ where
     ///
     /// A reference to the `ProofData<F, C, D>` which contains the proof information.
     fn proof(&self) -> &ProofData<F, C, D>;
+    /// Returns the public inputs the proof exposes as a zkTree node, i.e. its input hash, its
+    /// circuit hash and its allow-list root, if any. These are all of its public inputs, unless
+    /// the proof is cyclic and also exposes the verifier data of its own circuit after them.
+    ///
+    /// # Returns
+    ///
+    /// A slice of the public inputs of the proof, without any trailing verifier data.
+    fn node_public_inputs<'a>(&'a self) -> &'a [F]
+    where
+        C: 'a,
+    {
+        &self.proof().proof_with_pis.public_inputs
+    }
 }
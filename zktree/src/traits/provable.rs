@@ -1,8 +1,9 @@
 use crate::{
+    compiled_circuit::CompiledCircuit,
+    error::ZkTreeError,
     proof_data::ProofData,
     traits::{circuit_compiler::CircuitCompiler, evaluate_and_fill::EvaluateFillCircuit},
 };
-use anyhow::Error;
 use plonky2::{
     field::extension::Extendable, hash::hash_types::RichField, plonk::config::GenericConfig,
 };
@@ -25,11 +26,16 @@ use plonky2::{
 ///
 /// # Required Methods
 ///
-/// * `proof`: Consumes the implementor to produce a `ProofData` or an error. This method is the
-///   final step in the proof generation process, outputting the data needed for verification.
+/// * `proof_with_compiled_circuit`: Consumes the implementor to produce a `ProofData` or an error,
+///   using an already compiled circuit. This allows a circuit to be built once and reused for
+///   every proof that shares it.
 ///
 /// # Provided Methods
 ///
+/// * `proof`: Consumes the implementor to produce a `ProofData` or an error. This method compiles
+///   and builds the circuit before proving, and is the final step in the proof generation process,
+///   outputting the data needed for verification.
+///
 /// * `prove_and_verify`: A convenience method that both generates a proof and immediately verifies it.
 ///   It leverages `proof` to generate the proof data and then verifies it using the `verify` method
 ///   from the `ProofData`'s associated circuit data. This is a complete lifecycle for a zk-SNARK proof
@@ -37,21 +43,39 @@ use plonky2::{
 ///
 /// # Returns
 ///
-/// * `Result<(), Error>`: Returns `Ok(())` if the proof is successfully generated and verified, or
-///   an `Error` if any step in the process fails.
+/// * `Result<(), ZkTreeError>`: Returns `Ok(())` if the proof is successfully generated and verified, or
+///   a `ZkTreeError` if any step in the process fails.
 pub trait Provable<F: RichField + Extendable<D>, C: GenericConfig<D, F = F>, const D: usize>:
     CircuitCompiler<C, F, D> + EvaluateFillCircuit<C, F, D>
 where
     Self: Sized,
 {
+    /// Generates the proof data for the circuit, using a circuit previously compiled and built by
+    /// `compile_and_build`. Only the witness is filled and proven, the circuit is not rebuilt.
+    ///
+    /// # Arguments
+    ///
+    /// * `compiled_circuit`: The compiled circuit to generate the proof with.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that contains `ProofData<F, C, D>` on success, or a `ZkTreeError` if proof generation fails.
+    fn proof_with_compiled_circuit(
+        self,
+        compiled_circuit: &CompiledCircuit<F, C, Self::Targets, Self::OutTargets, D>,
+    ) -> Result<ProofData<F, C, D>, ZkTreeError>;
+
     /// Generates the proof data for the circuit. This method consumes the implementor and is
     /// intended to be called once all the necessary steps to compile and evaluate the circuit have
     /// been completed.
     ///
     /// # Returns
     ///
-    /// A `Result` that contains `ProofData<F, C, D>` on success, or an `Error` if proof generation fails.
-    fn proof(self) -> Result<ProofData<F, C, D>, Error>;
+    /// A `Result` that contains `ProofData<F, C, D>` on success, or a `ZkTreeError` if proof generation fails.
+    fn proof(mut self) -> Result<ProofData<F, C, D>, ZkTreeError> {
+        let compiled_circuit = self.build_compiled_circuit();
+        self.proof_with_compiled_circuit(&compiled_circuit)
+    }
 
     /// Generates and verifies a proof for the circuit. It is a convenience method that wraps the
     /// process of proof generation and verification into a single call. It first calls `proof` to
@@ -59,10 +83,11 @@ where
     ///
     /// # Returns
     ///
-    /// A `Result` that is `Ok(())` if the proof is successfully generated and verified, or an
-    /// `Error` if there is a failure in either proof generation or verification.
-    fn prove_and_verify(self) -> Result<(), Error> {
+    /// A `Result` that is `Ok(())` if the proof is successfully generated and verified, or a
+    /// `ZkTreeError` if there is a failure in either proof generation or verification.
+    fn prove_and_verify(self) -> Result<(), ZkTreeError> {
         let proof_data = self.proof()?;
-        proof_data.circuit_data.verify(proof_data.proof_with_pis)
+        proof_data.circuit_data.verify(proof_data.proof_with_pis)?;
+        Ok(())
     }
 }
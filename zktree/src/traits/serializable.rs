@@ -0,0 +1,107 @@
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    util::serialization::{Buffer, GateSerializer, WitnessGeneratorSerializer},
+};
+
+use crate::{
+    error::ZkTreeError,
+    serialization::{check_fully_read, read_version, write_version},
+};
+
+/// The `Serializable` trait provides a versioned binary encoding for zkTree proofs. The circuit
+/// data of a proof is encoded with plonky2's gate and generator serializers, which must support
+/// every gate and generator of the circuit (plonky2's `DefaultGateSerializer` and
+/// `DefaultGeneratorSerializer` do for the circuits of this crate).
+///
+/// # Type Parameters
+///
+/// * `F`: The field type, implementing `RichField` and `Extendable<D>`.
+/// * `D`: A compile-time constant that defines the dimension of the field extension.
+///
+/// # Required Methods
+///
+/// * `write`: Writes the unversioned encoding of `self` to a buffer.
+///
+/// * `read`: Reads a value written by `write` from a buffer.
+///
+/// # Provided Methods
+///
+/// * `to_bytes`: Serializes `self`, prefixed with the
+///   [`SERIALIZATION_VERSION`](crate::serialization::SERIALIZATION_VERSION).
+///
+/// * `from_bytes`: Deserializes a value serialized by `to_bytes`, rejecting other versions.
+pub trait Serializable<F, const D: usize>
+where
+    F: RichField + Extendable<D>,
+    Self: Sized,
+{
+    /// Writes the encoding of `self` to `buffer`, without the serialization version.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer`: The buffer to write to.
+    /// * `gate_serializer`: The serializer for the gates of the circuits.
+    /// * `generator_serializer`: The serializer for the witness generators of the circuits.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that is `Ok(())` on success, or a `ZkTreeError` if serialization fails.
+    fn write(
+        &self,
+        buffer: &mut Vec<u8>,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<(), ZkTreeError>;
+
+    /// Reads a value written by [`Serializable::write`] from `buffer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer`: The buffer to read from.
+    /// * `gate_serializer`: The serializer for the gates of the circuits.
+    /// * `generator_serializer`: The serializer for the witness generators of the circuits.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the deserialized value, or a `ZkTreeError` if deserialization fails.
+    fn read(
+        buffer: &mut Buffer,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<Self, ZkTreeError>;
+
+    /// Serializes `self` into versioned bytes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the serialized bytes, or a `ZkTreeError` if serialization fails.
+    fn to_bytes(
+        &self,
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<Vec<u8>, ZkTreeError> {
+        let mut buffer = Vec::new();
+        write_version(&mut buffer)?;
+        self.write(&mut buffer, gate_serializer, generator_serializer)?;
+        Ok(buffer)
+    }
+
+    /// Deserializes a value from bytes produced by [`Serializable::to_bytes`].
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the deserialized value, or a `ZkTreeError` if the version is not
+    /// supported or the bytes are malformed.
+    fn from_bytes(
+        bytes: &[u8],
+        gate_serializer: &dyn GateSerializer<F, D>,
+        generator_serializer: &dyn WitnessGeneratorSerializer<F, D>,
+    ) -> Result<Self, ZkTreeError> {
+        let mut buffer = Buffer::new(bytes);
+        read_version(&mut buffer)?;
+        let value = Self::read(&mut buffer, gate_serializer, generator_serializer)?;
+        check_fully_read(&buffer)?;
+        Ok(value)
+    }
+}
@@ -1,41 +1,414 @@
-use anyhow::Error;
+use std::collections::HashMap;
+
 use plonky2::{
     field::extension::Extendable,
     hash::hash_types::RichField,
-    plonk::config::{AlgebraicHasher, GenericConfig},
+    plonk::{
+        circuit_data::CircuitConfig,
+        config::{AlgebraicHasher, GenericConfig},
+    },
+};
+use rayon::prelude::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+    ParallelSlice,
+};
+use rayon::ThreadPool;
+
+use crate::{
+    components::{
+        allow_list::CircuitDigestAllowList,
+        cyclic_node_circuit::CompiledCyclicNodeCircuit,
+        cyclic_node_proof::CyclicNodeProof,
+        leaf_circuit_cache::LeafCircuitCache,
+        leaf_proof::LeafProof,
+        node_circuit::{
+            check_arity, check_children_public_inputs, CompiledNodeCircuit, NodeCircuit,
+        },
+        node_proof::NodeProof,
+        padded_user_circuit::{CommonLeafShape, PaddedUserCircuit},
+        shrink_circuit::{CompiledShrinkCircuit, ShrinkCircuit},
+        user_proof::UserProof,
+    },
+    error::{hash_elements, ZkTreeError},
+    traits::{circuit_compiler::CircuitCompiler, proof::Proof},
 };
-use rayon::prelude::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 
-use crate::components::{leaf_proof::LeafProof, node_proof::NodeProof};
+/// Validates `user_proofs` natively in parallel, see [`UserProof::validate`], and returns the
+/// errors of the invalid ones, each attributed to the index of its user proof, in increasing
+/// index order.
+pub(crate) fn invalid_user_proofs<C, F, const D: usize>(
+    user_proofs: &[UserProof<C, F, D>],
+) -> Vec<ZkTreeError>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    C::Hasher: AlgebraicHasher<F>,
+{
+    user_proofs
+        .par_iter()
+        .enumerate()
+        .filter_map(|(leaf_index, user_proof)| {
+            user_proof
+                .validate()
+                .err()
+                .map(|error| error.at_leaf(leaf_index))
+        })
+        .collect()
+}
 
-pub(crate) fn generate_node_proofs_from_leaves<C, F, H, const D: usize>(
-    leaf_proofs: &Vec<LeafProof<C, F, H, D>>,
-) -> Result<Vec<NodeProof<C, F, H, D>>, Error>
+/// Validates `user_proofs` natively in parallel, see [`UserProof::validate`], so that invalid user
+/// proofs are reported before any leaf is proven.
+///
+/// # Returns
+///
+/// A `Result` that is `Ok(())` if all the user proofs are valid, or a
+/// `ZkTreeError::InvalidUserProofs` listing the errors of all the invalid ones otherwise.
+pub(crate) fn validate_user_proofs<C, F, const D: usize>(
+    user_proofs: &[UserProof<C, F, D>],
+) -> Result<(), ZkTreeError>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    C::Hasher: AlgebraicHasher<F>,
+{
+    let errors = invalid_user_proofs(user_proofs);
+    if !errors.is_empty() {
+        return Err(ZkTreeError::InvalidUserProofs(errors));
+    }
+    Ok(())
+}
+
+/// Brings `user_proofs` to `common_leaf_shape` in parallel, see [`UserProof::pad`]. The
+/// `PaddedUserCircuit` is compiled once per user circuit.
+///
+/// # Returns
+///
+/// A `Result` containing the padded user proofs, in the same order, or a
+/// `ZkTreeError::InvalidUserProofs` listing the user proofs whose circuit is too large for the
+/// shape, or the error of the first user proof that could not be padded.
+pub(crate) fn pad_user_proofs<C, F, H, const D: usize>(
+    user_proofs: Vec<UserProof<C, F, D>>,
+    common_leaf_shape: &CommonLeafShape,
+) -> Result<Vec<UserProof<C, F, D>>, ZkTreeError>
 where
     F: RichField + Extendable<D>,
     C: GenericConfig<D, F = F, Hasher = H>,
     H: AlgebraicHasher<F> + Send + Sync,
 {
-    (0..leaf_proofs.len())
+    let errors = user_proofs
+        .par_iter()
+        .enumerate()
+        .filter_map(|(leaf_index, user_proof)| {
+            common_leaf_shape
+                .check_user_proof(user_proof)
+                .err()
+                .map(|error| error.at_leaf(leaf_index))
+        })
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(ZkTreeError::InvalidUserProofs(errors));
+    }
+
+    let mut user_circuits = HashMap::new();
+    for user_proof in &user_proofs {
+        user_circuits
+            .entry(hash_elements(user_proof.circuit_hash()))
+            .or_insert(user_proof);
+    }
+    let compiled_padded_user_circuits = user_circuits
         .into_par_iter()
-        .step_by(2)
-        .map(|i| NodeProof::new_from_children(&leaf_proofs[i], &leaf_proofs[i + 1]))
-        .collect::<Result<Vec<_>, _>>()
+        .map(|(user_circuit_hash, user_proof)| {
+            let compiled_padded_user_circuit =
+                PaddedUserCircuit::<C, F, H, D>::new(user_proof, common_leaf_shape)
+                    .build_compiled_circuit();
+            (user_circuit_hash, compiled_padded_user_circuit)
+        })
+        .collect::<HashMap<_, _>>();
+
+    user_proofs
+        .par_iter()
+        .enumerate()
+        .map(|(leaf_index, user_proof)| {
+            user_proof
+                .pad_with_compiled_circuit(
+                    common_leaf_shape,
+                    &compiled_padded_user_circuits[&hash_elements(user_proof.circuit_hash())],
+                )
+                .map_err(|error| error.at_leaf(leaf_index))
+        })
+        .collect()
 }
 
-pub(crate) fn generate_node_proofs_from_nodes<C, F, H, const D: usize>(
-    node_proofs: &[NodeProof<C, F, H, D>],
-    start_child_index: usize,
-    node_proofs_len: usize,
-) -> Result<Vec<NodeProof<C, F, H, D>>, Error>
+/// Generates the leaf proofs for `user_proofs` in parallel, followed by the padding leaves needed
+/// to reach `num_leaves` leaves. Leaf circuits are built with `leaf_config` and retrieved from
+/// `leaf_circuit_cache`, and check membership of the user circuits in `allow_list`, if any. The
+/// user proofs are expected to have been validated beforehand, see [`validate_user_proofs`].
+pub(crate) fn generate_leaf_proofs<C, F, H, const D: usize>(
+    user_proofs: &[UserProof<C, F, D>],
+    padding_user_proof: &UserProof<C, F, D>,
+    num_leaves: usize,
+    leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+    allow_list: Option<&CircuitDigestAllowList<F, H>>,
+    leaf_config: &CircuitConfig,
+) -> Result<Vec<LeafProof<C, F, H, D>>, ZkTreeError>
 where
     F: RichField + Extendable<D>,
     C: GenericConfig<D, F = F, Hasher = H>,
     H: AlgebraicHasher<F> + Send + Sync,
 {
-    (start_child_index..node_proofs_len)
-        .into_par_iter()
-        .step_by(2)
-        .map(|i| NodeProof::new_from_children(&node_proofs[i], &node_proofs[i + 1]))
-        .collect::<Result<Vec<_>, _>>()
+    // compile each distinct leaf circuit once, before the leaves are proven in parallel
+    let mut leaf_user_proofs = user_proofs.iter().collect::<Vec<_>>();
+    if user_proofs.len() < num_leaves {
+        leaf_user_proofs.push(padding_user_proof);
+    }
+    let mut compiled_leaf_circuits = leaf_circuit_cache.get_or_compile_all_with_config(
+        &leaf_user_proofs,
+        allow_list,
+        leaf_config,
+    );
+    let padding_compiled_leaf_circuit = if user_proofs.len() < num_leaves {
+        compiled_leaf_circuits.pop()
+    } else {
+        None
+    };
+
+    let mut leaf_proofs = user_proofs
+        .par_iter()
+        .zip(compiled_leaf_circuits.par_iter())
+        .enumerate()
+        .map(|(leaf_index, (user_proof, compiled_leaf_circuit))| {
+            LeafProof::new_from_user_proof_with_compiled_circuit(
+                user_proof,
+                allow_list,
+                compiled_leaf_circuit,
+            )
+            .map_err(|error| error.at_leaf(leaf_index))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if let Some(compiled_leaf_circuit) = padding_compiled_leaf_circuit {
+        let padding_leaf_proofs = (leaf_proofs.len()..num_leaves)
+            .into_par_iter()
+            .map(|_| {
+                LeafProof::new_padding_with_compiled_circuit(
+                    padding_user_proof,
+                    allow_list,
+                    &compiled_leaf_circuit,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        leaf_proofs.extend(padding_leaf_proofs);
+    }
+
+    Ok(leaf_proofs)
+}
+
+/// Compiles the `NodeCircuit` aggregating `children` with `config`, which is shared by every node
+/// whose children have the same `CommonCircuitData` as `children`. The public inputs of the
+/// children are checked beforehand, as the circuit could not be satisfied otherwise.
+pub(crate) fn compile_node_circuit<C, F, H, P, const D: usize>(
+    children: &[&P],
+    config: &CircuitConfig,
+) -> Result<CompiledNodeCircuit<F, C, D>, ZkTreeError>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F>,
+    P: Proof<C, F, D>,
+{
+    check_children_public_inputs(children)?;
+    Ok(
+        NodeCircuit::<C, F, H, P, D>::new_from_children(children.to_vec())
+            .with_config(config.clone())
+            .build_compiled_circuit(),
+    )
+}
+
+/// Compiles the `ShrinkCircuit` wrapping `proof` with `config`, which is shared by every proof of
+/// the same circuit as `proof`.
+pub(crate) fn compile_shrink_circuit<C, F, H, P, const D: usize>(
+    proof: &P,
+    config: &CircuitConfig,
+) -> CompiledShrinkCircuit<F, C, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F>,
+    P: Proof<C, F, D> + ?Sized,
+{
+    ShrinkCircuit::<C, F, H, P, D>::new(proof)
+        .with_config(config.clone())
+        .build_compiled_circuit()
+}
+
+/// Generates the node proofs of the level above `children`, grouping `arity` consecutive children
+/// under each node. The `NodeCircuit` is compiled once for the whole level, as all the children at
+/// a given height share the same `CommonCircuitData`, and is returned together with the node
+/// proofs so that nodes of this level can later be proven again. The circuit is built with
+/// `config`, and errors are attributed to the nodes of `level`.
+#[allow(clippy::type_complexity)]
+pub(crate) fn generate_node_proofs<C, F, H, P, const D: usize>(
+    children: &[P],
+    level: usize,
+    arity: usize,
+    config: &CircuitConfig,
+) -> Result<(Vec<NodeProof<C, F, H, D>>, CompiledNodeCircuit<F, C, D>), ZkTreeError>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F> + Send + Sync,
+    P: Proof<C, F, D> + Sync,
+{
+    check_arity(arity)?;
+    if children.is_empty() || children.len() % arity != 0 {
+        return Err(ZkTreeError::ChildrenNotMultipleOfArity {
+            num_children: children.len(),
+            arity,
+        });
+    }
+
+    let child_common_data = &children[0].proof().circuit_data.common;
+    if let Some(i) = children
+        .iter()
+        .position(|child| child.proof().circuit_data.common != *child_common_data)
+    {
+        return Err(ZkTreeError::CommonDataMismatch { position: i });
+    }
+
+    let compiled_node_circuit = compile_node_circuit::<C, F, H, P, D>(
+        &children[..arity].iter().collect::<Vec<_>>(),
+        config,
+    )?;
+
+    let node_proofs = children
+        .par_chunks(arity)
+        .enumerate()
+        .map(|(node_index, node_children)| {
+            NodeProof::new_from_k_children_with_compiled_circuit(
+                &node_children.iter().collect::<Vec<_>>(),
+                &compiled_node_circuit,
+            )
+            .map_err(|error| error.at_node(level, node_index))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((node_proofs, compiled_node_circuit))
+}
+
+/// Generates the node proofs of every level above `leaf_proofs` up to a single root with the same
+/// compiled `CyclicNodeCircuit`, grouping `arity` consecutive proofs under each node. The number
+/// of leaf proofs must be a power of the arity of the compiled circuit.
+///
+/// # Returns
+///
+/// A `Result` containing the node proofs, levels concatenated from the one above the leaves to
+/// the root, or a `ZkTreeError` attributed to the failing node otherwise.
+pub(crate) fn generate_cyclic_node_proofs<C, F, H, const D: usize>(
+    leaf_proofs: &[LeafProof<C, F, H, D>],
+    compiled_cyclic_node_circuit: &CompiledCyclicNodeCircuit<F, C, D>,
+) -> Result<Vec<CyclicNodeProof<C, F, H, D>>, ZkTreeError>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let arity = compiled_cyclic_node_circuit.arity();
+    if leaf_proofs.is_empty() || leaf_proofs.len() % arity != 0 {
+        return Err(ZkTreeError::ChildrenNotMultipleOfArity {
+            num_children: leaf_proofs.len(),
+            arity,
+        });
+    }
+
+    let mut node_proofs = leaf_proofs
+        .par_chunks(arity)
+        .enumerate()
+        .map(|(node_index, node_children)| {
+            CyclicNodeProof::new_from_leaves_with_compiled_circuit(
+                &node_children.iter().collect::<Vec<_>>(),
+                compiled_cyclic_node_circuit,
+            )
+            .map_err(|error| error.at_node(0, node_index))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut level = 0;
+    let mut level_start_index = 0;
+    while node_proofs.len() - level_start_index > 1 {
+        level += 1;
+        let level_end_index = node_proofs.len();
+        let children = &node_proofs[level_start_index..level_end_index];
+        if children.len() % arity != 0 {
+            return Err(ZkTreeError::ChildrenNotMultipleOfArity {
+                num_children: children.len(),
+                arity,
+            });
+        }
+        let level_node_proofs = children
+            .par_chunks(arity)
+            .enumerate()
+            .map(|(node_index, node_children)| {
+                CyclicNodeProof::new_from_nodes_with_compiled_circuit(
+                    &node_children.iter().collect::<Vec<_>>(),
+                    compiled_cyclic_node_circuit,
+                )
+                .map_err(|error| error.at_node(level, node_index))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        node_proofs.extend(level_node_proofs);
+        level_start_index = level_end_index;
+    }
+    Ok(node_proofs)
+}
+
+/// Returns the children of the parent node of the proof at `position` in `children`, from left to
+/// right, with `proof` in place of the proof at `position`.
+pub(crate) fn parent_node_children<'a, P>(
+    children: &'a [P],
+    position: usize,
+    proof: &'a P,
+    arity: usize,
+) -> Vec<&'a P> {
+    let first_child_position = position - position % arity;
+    (first_child_position..first_child_position + arity)
+        .map(|child_position| {
+            if child_position == position {
+                proof
+            } else {
+                &children[child_position]
+            }
+        })
+        .collect()
+}
+
+/// Proves again the parent node of the proof at `position` in `children`, with `proof` in place
+/// of the proof at `position`. Errors are attributed to the parent node within `level`.
+pub(crate) fn reprove_parent_node<C, F, H, P, const D: usize>(
+    children: &[P],
+    level: usize,
+    position: usize,
+    proof: &P,
+    arity: usize,
+    compiled_node_circuit: &CompiledNodeCircuit<F, C, D>,
+) -> Result<NodeProof<C, F, H, D>, ZkTreeError>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F>,
+    P: Proof<C, F, D>,
+{
+    let node_children = parent_node_children(children, position, proof, arity);
+    NodeProof::new_from_k_children_with_compiled_circuit(&node_children, compiled_node_circuit)
+        .map_err(|error| error.at_node(level, position / arity))
+}
+
+/// Runs `op` on `thread_pool` if any, so that all the rayon parallelism it spawns (including
+/// plonky2's internal parallelism) is confined to that pool, or on the current thread otherwise.
+pub(crate) fn install_in_thread_pool<R, OP>(thread_pool: Option<&ThreadPool>, op: OP) -> R
+where
+    R: Send,
+    OP: FnOnce() -> R + Send,
+{
+    match thread_pool {
+        Some(thread_pool) => thread_pool.install(op),
+        None => op(),
+    }
 }
@@ -1,18 +1,363 @@
-use std::marker::PhantomData;
+use std::{collections::HashSet, marker::PhantomData, ops::Range};
 
-use anyhow::{anyhow, Error};
 use plonky2::{
     field::extension::Extendable,
-    hash::{hash_types::RichField, merkle_tree::MerkleTree},
-    plonk::config::{AlgebraicHasher, GenericConfig},
+    hash::hash_types::{HashOut, RichField, NUM_HASH_OUT_ELTS},
+    plonk::{
+        circuit_data::CircuitConfig,
+        config::{AlgebraicHasher, GenericConfig},
+    },
 };
+use rayon::ThreadPool;
 
 use crate::{
-    components::{leaf_proof::LeafProof, node_proof::NodeProof, user_proof::UserProof},
+    circuit_commitment::circuit_commitment_with_arity,
+    components::{
+        allow_list::{allow_list_root_from_public_inputs, CircuitDigestAllowList},
+        cyclic_node_circuit::CompiledCyclicNodeCircuit,
+        cyclic_node_proof::CyclicNodeProof,
+        leaf_circuit_cache::LeafCircuitCache,
+        leaf_proof::LeafProof,
+        node_circuit::{check_arity, CompiledNodeCircuit},
+        node_proof::NodeProof,
+        shrink_circuit::CompiledShrinkCircuit,
+        shrink_proof::ShrinkProof,
+        user_proof::UserProof,
+        wrapped_proof::WrappedProof,
+    },
+    error::{hash_elements, ZkTreeError},
+    inclusion_proof::InclusionProof,
+    input_commitment::{input_commitment_with_arity, leaf_input_hash, padding_input_hash},
+    root_verifier_bundle::RootVerifierBundle,
     traits::proof::Proof,
-    utils::{generate_node_proofs_from_leaves, generate_node_proofs_from_nodes},
+    utils::{
+        compile_node_circuit, compile_shrink_circuit, generate_cyclic_node_proofs,
+        generate_leaf_proofs, generate_node_proofs, install_in_thread_pool, invalid_user_proofs,
+        pad_user_proofs, parent_node_children, reprove_parent_node, validate_user_proofs,
+    },
+    zktree_builder::CircuitConfigs,
 };
 
+/// Number of children of every node of a `ZkTree` built without an explicit arity.
+pub const DEFAULT_ARITY: usize = 2;
+
+/// Number of leaves of a `ZkTree` of the given arity aggregating `num_user_proofs` user proofs,
+/// including padding leaves, i.e. the smallest power of `arity` greater than one that is at least
+/// `num_user_proofs`.
+fn num_leaves(num_user_proofs: usize, arity: usize) -> usize {
+    let mut num_leaves = arity;
+    while num_leaves < num_user_proofs {
+        num_leaves *= arity;
+    }
+    num_leaves
+}
+
+/// Returns the input hashes of the siblings of the proof at `position` in `level`, i.e. of the
+/// other children of its parent node, from left to right.
+fn siblings_input_hashes<C, F, P, const D: usize>(
+    level: &[P],
+    position: usize,
+    arity: usize,
+) -> Vec<HashOut<F>>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F>,
+    P: Proof<C, F, D>,
+{
+    let first_sibling_position = position - position % arity;
+    (first_sibling_position..first_sibling_position + arity)
+        .filter(|sibling_position| *sibling_position != position)
+        .map(|sibling_position| level[sibling_position].input_hash())
+        .collect()
+}
+
+/// Lays out the node proofs of `arity` `ZkTree`s merged under `root`, see [`ZkTree::merge_k`]:
+/// every level of the merged `ZkTree` is the concatenation of the corresponding levels of the
+/// merged ones, laid out in `levels`, the former roots being the level below `root`.
+///
+/// # Arguments
+///
+/// * `levels`: The range of each level of nodes of the merged `ZkTree`s.
+/// * `zktrees_node_proofs`: The node proofs of each merged `ZkTree`, without its former root.
+/// * `former_roots`: The roots of the merged `ZkTree`s, possibly proven again.
+/// * `root`: The new root.
+fn merge_levels<P>(
+    levels: &[Range<usize>],
+    zktrees_node_proofs: Vec<Vec<P>>,
+    former_roots: Vec<P>,
+    root: P,
+) -> Vec<P> {
+    let former_root_level = levels.len() - 1;
+    let mut levels_node_proofs = std::iter::repeat_with(Vec::new)
+        .take(levels.len())
+        .collect::<Vec<_>>();
+    for (node_proofs, former_root) in zktrees_node_proofs.into_iter().zip(former_roots) {
+        let mut node_proofs = node_proofs.into_iter();
+        for (level, level_node_proofs) in levels[..former_root_level]
+            .iter()
+            .zip(levels_node_proofs.iter_mut())
+        {
+            level_node_proofs.extend(node_proofs.by_ref().take(level.len()));
+        }
+        levels_node_proofs[former_root_level].push(former_root);
+    }
+    let mut node_proofs = levels_node_proofs.into_iter().flatten().collect::<Vec<_>>();
+    node_proofs.push(root);
+    node_proofs
+}
+
+/// Lays out the node proofs of a `ZkTree` grown under `root`, see [`ZkTree::append`]: every level
+/// of `node_proofs`, laid out in `levels`, is followed by `arity - 1` times as many copies of the
+/// padding node proof of this level.
+fn grow_levels<P: Clone>(
+    levels: &[Range<usize>],
+    node_proofs: Vec<P>,
+    padding_node_proofs: Vec<P>,
+    root: P,
+    arity: usize,
+) -> Vec<P> {
+    let mut grown_node_proofs = Vec::with_capacity(node_proofs.len() * arity + 1);
+    let mut node_proofs = node_proofs.into_iter();
+    for (level, padding_node_proof) in levels.iter().zip(padding_node_proofs) {
+        let num_padding_node_proofs = level.len() * (arity - 1);
+        grown_node_proofs.extend(node_proofs.by_ref().take(level.len()));
+        grown_node_proofs
+            .extend(std::iter::repeat(padding_node_proof).take(num_padding_node_proofs));
+    }
+    grown_node_proofs.push(root);
+    grown_node_proofs
+}
+
+/// Replaces the nodes on the path from the leaf at `leaf_index` to the root in `node_proofs`, laid
+/// out in `levels`, with `path`, from the level above the leaves to the root.
+fn replace_path<P>(
+    node_proofs: &mut [P],
+    levels: &[Range<usize>],
+    leaf_index: usize,
+    path: Vec<P>,
+    arity: usize,
+) {
+    let mut position = leaf_index;
+    for (level, node_proof) in levels.iter().zip(path) {
+        position /= arity;
+        node_proofs[level.start + position] = node_proof;
+    }
+}
+
+/// Proves again the root of a `ZkTree` with a `NodeCircuit` built with `config`, e.g. a
+/// `NodeCircuit` built with the node `CircuitConfig` when the root stops being the root.
+///
+/// # Arguments
+///
+/// * `leaf_proofs`: The leaf proofs of the `ZkTree`.
+/// * `node_proofs`: The node proofs of the `ZkTree`, laid out in `levels`.
+/// * `levels`: The range of each level of nodes in `node_proofs`.
+/// * `arity`: The number of children of every node.
+/// * `config`: The `CircuitConfig` of the `NodeCircuit`.
+///
+/// # Returns
+///
+/// A `Result` containing the root proof together with its compiled `NodeCircuit`, or a
+/// `ZkTreeError` if the proof generation fails.
+#[allow(clippy::type_complexity)]
+fn prove_root_with_config<C, F, H, const D: usize>(
+    leaf_proofs: &[LeafProof<C, F, H, D>],
+    node_proofs: &[NodeProof<C, F, H, D>],
+    levels: &[Range<usize>],
+    arity: usize,
+    config: &CircuitConfig,
+) -> Result<(NodeProof<C, F, H, D>, CompiledNodeCircuit<F, C, D>), ZkTreeError>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    let (mut level_node_proofs, compiled_node_circuit) = match levels.len() {
+        1 => generate_node_proofs(leaf_proofs, 0, arity, config)?,
+        num_levels => generate_node_proofs(
+            &node_proofs[levels[num_levels - 2].clone()],
+            num_levels - 1,
+            arity,
+            config,
+        )?,
+    };
+    let root = level_node_proofs
+        .pop()
+        .expect("Failed to retrieve former root");
+    Ok((root, compiled_node_circuit))
+}
+
+/// Proves again the root of a `ZkTree` with `compiled_node_circuit`, see
+/// [`prove_root_with_config`].
+fn prove_root_with_compiled_circuit<C, F, H, const D: usize>(
+    leaf_proofs: &[LeafProof<C, F, H, D>],
+    node_proofs: &[NodeProof<C, F, H, D>],
+    levels: &[Range<usize>],
+    compiled_node_circuit: &CompiledNodeCircuit<F, C, D>,
+) -> Result<NodeProof<C, F, H, D>, ZkTreeError>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F>,
+{
+    match levels.len() {
+        1 => NodeProof::new_from_k_children_with_compiled_circuit(
+            &leaf_proofs.iter().collect::<Vec<_>>(),
+            compiled_node_circuit,
+        ),
+        num_levels => NodeProof::new_from_k_children_with_compiled_circuit(
+            &node_proofs[levels[num_levels - 2].clone()]
+                .iter()
+                .collect::<Vec<_>>(),
+            compiled_node_circuit,
+        ),
+    }
+}
+
+/// The node proofs of a `ZkTree`, levels concatenated from the one above the leaves to the root,
+/// together with the compiled circuits they are proven with, kept to prove nodes again when the
+/// `ZkTree` is updated.
+///
+/// # Type Parameters
+///
+/// * `C`: The configuration of the circuits, satisfying `GenericConfig`.
+/// * `F`: The field type that must implement `RichField` and `Extendable<D>`.
+/// * `H`: The hasher type that implements `AlgebraicHasher<F>`, the hasher of `C`.
+/// * `D`: The dimension of the field extension, specified as a compile-time constant.
+enum NodeLayer<C, F, H, const D: usize>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F>,
+{
+    /// Nodes proven with a `NodeCircuit` compiled per level, the root one being built with the
+    /// root `CircuitConfig`.
+    Node {
+        node_proofs: Vec<NodeProof<C, F, H, D>>,
+        compiled_node_circuits: Vec<CompiledNodeCircuit<F, C, D>>,
+    },
+    /// Nodes proven with the same `CyclicNodeCircuit` at every height, see
+    /// [`CircuitConfigs::cyclic_node`].
+    Cyclic {
+        node_proofs: Vec<CyclicNodeProof<C, F, H, D>>,
+        compiled_cyclic_node_circuit: CompiledCyclicNodeCircuit<F, C, D>,
+    },
+}
+
+impl<C, F, H, const D: usize> NodeLayer<C, F, H, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F>,
+{
+    /// Returns the node proofs, levels concatenated from the one above the leaves to the root.
+    fn node_proofs(&self) -> Vec<&dyn Proof<C, F, D>> {
+        match self {
+            Self::Node { node_proofs, .. } => node_proofs
+                .iter()
+                .map(|node_proof| node_proof as &dyn Proof<C, F, D>)
+                .collect(),
+            Self::Cyclic { node_proofs, .. } => node_proofs
+                .iter()
+                .map(|node_proof| node_proof as &dyn Proof<C, F, D>)
+                .collect(),
+        }
+    }
+
+    /// Returns the root proof.
+    fn root(&self) -> &dyn Proof<C, F, D> {
+        match self {
+            Self::Node { node_proofs, .. } => node_proofs.last().expect("Failed to retrieve root"),
+            Self::Cyclic { node_proofs, .. } => {
+                node_proofs.last().expect("Failed to retrieve root")
+            }
+        }
+    }
+
+    /// Verifies the root proof. The verifier data a cyclic root exposes must also be the one of
+    /// its `CyclicNodeCircuit`, see [`CyclicNodeProof::verify`].
+    fn verify_root(&self) -> Result<(), ZkTreeError> {
+        match self {
+            Self::Node { node_proofs, .. } => {
+                let root = node_proofs.last().expect("Failed to retrieve root");
+                root.proof()
+                    .circuit_data
+                    .verify(root.proof().proof_with_pis.clone())?;
+                Ok(())
+            }
+            Self::Cyclic { node_proofs, .. } => node_proofs
+                .last()
+                .expect("Failed to retrieve root")
+                .verify(),
+        }
+    }
+
+    /// Returns whether the nodes of both layers are proven with the same circuits, so that the
+    /// `ZkTree`s they belong to can be merged.
+    fn has_same_circuits(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Node {
+                    compiled_node_circuits,
+                    ..
+                },
+                Self::Node {
+                    compiled_node_circuits: other_compiled_node_circuits,
+                    ..
+                },
+            ) => compiled_node_circuits
+                .iter()
+                .zip(other_compiled_node_circuits)
+                .all(|(compiled_node_circuit, other_compiled_node_circuit)| {
+                    compiled_node_circuit.circuit_data().verifier_only
+                        == other_compiled_node_circuit.circuit_data().verifier_only
+                }),
+            (
+                Self::Cyclic {
+                    compiled_cyclic_node_circuit,
+                    ..
+                },
+                Self::Cyclic {
+                    compiled_cyclic_node_circuit: other_compiled_cyclic_node_circuit,
+                    ..
+                },
+            ) => {
+                compiled_cyclic_node_circuit.circuit_data().verifier_only
+                    == other_compiled_cyclic_node_circuit
+                        .circuit_data()
+                        .verifier_only
+            }
+            _ => false,
+        }
+    }
+}
+
+/// `ZkTree` aggregates user proofs into a single root proof. Every user proof is verified by a
+/// leaf proof, and every node proof verifies `arity` consecutive proofs of the level below, up to
+/// the root.
+///
+/// # Type Parameters
+///
+/// * `C`: The configuration of the circuits, satisfying `GenericConfig`.
+/// * `F`: The field type that must implement `RichField` and `Extendable<D>`.
+/// * `H`: The hasher type that implements `AlgebraicHasher<F>`, the hasher of `C`.
+/// * `D`: The dimension of the field extension, specified as a compile-time constant.
+///
+/// # Fields
+///
+/// * `user_proofs`: The aggregated user proofs.
+/// * `leaf_proofs`: The leaf proofs, one per user proof followed by padding leaves.
+/// * `nodes`: The node proofs, levels concatenated from the one above the leaves to the root,
+///   together with the compiled node circuits kept to prove nodes again when the `ZkTree` is
+///   updated.
+/// * `shrink_proofs`: The proofs of the shrink layers wrapping the root proof, the last one being
+///   the final proof of the `ZkTree`.
+/// * `compiled_shrink_circuits`: The compiled `ShrinkCircuit` of each shrink layer, kept to shrink
+///   the root again when the `ZkTree` is updated without changing the root circuit.
+/// * `circuit_configs`: The `CircuitConfig` of the leaf, node, root, cyclic node and shrink
+///   circuits.
+/// * `arity`: The number of children of every node.
+/// * `_phantom_data`: `PhantomData` used to mark the usage of the hasher type `H`.
 pub struct ZkTree<C, F, H, const D: usize>
 where
     F: RichField + Extendable<D>,
@@ -21,51 +366,941 @@ where
 {
     user_proofs: Vec<UserProof<C, F, D>>,
     leaf_proofs: Vec<LeafProof<C, F, H, D>>,
-    node_proofs: Vec<NodeProof<C, F, H, D>>,
+    nodes: NodeLayer<C, F, H, D>,
+    shrink_proofs: Vec<ShrinkProof<C, F, H, D>>,
+    compiled_shrink_circuits: Vec<CompiledShrinkCircuit<F, C, D>>,
+    circuit_configs: CircuitConfigs,
+    arity: usize,
     _phantom_data: PhantomData<H>,
 }
 
-impl<C, F, H, const D: usize> ZkTree<C, F, H, D>
+/// `FilteredZkTree` is the result of [`ZkTree::new_skipping_invalid`], i.e. a `ZkTree` aggregating
+/// the valid user proofs among the submitted ones, together with the rejected user proofs.
+///
+/// # Type Parameters
+///
+/// * `C`: The configuration of the circuits, satisfying `GenericConfig`.
+/// * `F`: The field type that must implement `RichField` and `Extendable<D>`.
+/// * `H`: The hasher type that implements `AlgebraicHasher<F>`, the hasher of `C`.
+/// * `D`: The dimension of the field extension, specified as a compile-time constant.
+///
+/// # Fields
+///
+/// * `zktree`: The `ZkTree` aggregating the valid user proofs.
+/// * `included_indices`: The index among the submitted user proofs of the user proof of each
+///   leaf of `zktree`, in increasing order.
+/// * `rejected`: The errors of the invalid user proofs, in increasing order of the index among the
+///   submitted user proofs they are attributed to.
+pub struct FilteredZkTree<C, F, H, const D: usize>
 where
     F: RichField + Extendable<D>,
     C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F>,
+{
+    zktree: ZkTree<C, F, H, D>,
+    included_indices: Vec<usize>,
+    rejected: Vec<ZkTreeError>,
+}
+
+impl<C, F, H, const D: usize> FilteredZkTree<C, F, H, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F>,
+{
+    /// Returns the `ZkTree` aggregating the valid user proofs.
+    pub fn zktree(&self) -> &ZkTree<C, F, H, D> {
+        &self.zktree
+    }
+
+    /// Consumes the `FilteredZkTree`, returning the `ZkTree` aggregating the valid user proofs.
+    pub fn into_zktree(self) -> ZkTree<C, F, H, D> {
+        self.zktree
+    }
+
+    /// Returns the indices among the submitted user proofs of the aggregated user proofs, in
+    /// leaf order.
+    pub fn included_indices(&self) -> &[usize] {
+        &self.included_indices
+    }
+
+    /// Returns the index among the submitted user proofs of the user proof of the leaf at
+    /// `leaf_index`, or `None` if the leaf is a padding leaf.
+    pub fn submitted_index(&self, leaf_index: usize) -> Option<usize> {
+        self.included_indices.get(leaf_index).copied()
+    }
+
+    /// Returns the errors of the rejected user proofs, see [`ZkTreeError::leaf_index`] for the
+    /// index among the submitted user proofs each of them is attributed to.
+    pub fn rejected(&self) -> &[ZkTreeError] {
+        &self.rejected
+    }
+
+    /// Returns the indices among the submitted user proofs of the rejected user proofs.
+    pub fn rejected_indices(&self) -> Vec<usize> {
+        self.rejected
+            .iter()
+            .filter_map(ZkTreeError::leaf_index)
+            .collect()
+    }
+}
+
+impl<C, F, H, const D: usize> ZkTree<C, F, H, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
     H: AlgebraicHasher<F> + Send + Sync,
 {
-    pub fn new(user_proofs: Vec<UserProof<C, F, D>>) -> Result<Self, Error> {
-        debug_assert!(user_proofs.len().is_power_of_two() && user_proofs.len() > 1);
-        let zktree_height = user_proofs.len().ilog2();
-
-        let mut leaf_proofs: Vec<LeafProof<C, F, H, D>> = Vec::with_capacity(user_proofs.len());
-        for user_proof in &user_proofs {
-            leaf_proofs.push(LeafProof::new_from_user_proof(user_proof)?);
-        }
-
-        let mut node_proofs = Vec::with_capacity((1 << (zktree_height + 1)) - 1);
-        let mut start_child_index = 0;
-        let mut node_proofs_len = 0;
-
-        for height in 0..zktree_height {
-            if height == 0 {
-                node_proofs.extend(generate_node_proofs_from_leaves(&leaf_proofs)?);
-                node_proofs_len = node_proofs.len();
-            } else {
-                node_proofs.extend(generate_node_proofs_from_nodes(
-                    &node_proofs,
-                    start_child_index,
-                    node_proofs_len,
-                )?);
-                start_child_index = node_proofs_len;
-                node_proofs_len += 1 << (zktree_height - height - 1);
+    /// Builds a new binary `ZkTree` aggregating the given user proofs. If the number of user proofs
+    /// is not a power of two (or is one), the leaves are padded with padding leaves up to the next
+    /// power of two, see [`LeafProof::new_padding`]. The user proofs are first validated natively,
+    /// and all the invalid ones are reported together, see [`ZkTreeError::InvalidUserProofs`].
+    pub fn new(user_proofs: Vec<UserProof<C, F, D>>) -> Result<Self, ZkTreeError> {
+        Self::new_with_leaf_circuit_cache(user_proofs, &LeafCircuitCache::new())
+    }
+
+    /// Builds a new `ZkTree` aggregating the given user proofs, running all the leaf and node
+    /// proving (including plonky2's internal parallelism) on `thread_pool`. This allows capping
+    /// the CPU used by a given aggregation, e.g. with a pool built by
+    /// `rayon::ThreadPoolBuilder::new().num_threads(n).build()`.
+    pub fn new_in_thread_pool(
+        user_proofs: Vec<UserProof<C, F, D>>,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+        thread_pool: &ThreadPool,
+    ) -> Result<Self, ZkTreeError> {
+        install_in_thread_pool(Some(thread_pool), || {
+            Self::new_with_leaf_circuit_cache(user_proofs, leaf_circuit_cache)
+        })
+    }
+
+    /// Builds a new `ZkTree` aggregating the given user proofs, retrieving the compiled leaf
+    /// circuits from `leaf_circuit_cache`. User proofs sharing a user circuit are proven with the
+    /// same leaf circuit, and the cache can be reused across `ZkTree` constructions.
+    pub fn new_with_leaf_circuit_cache(
+        user_proofs: Vec<UserProof<C, F, D>>,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+    ) -> Result<Self, ZkTreeError> {
+        Self::build(
+            user_proofs,
+            DEFAULT_ARITY,
+            leaf_circuit_cache,
+            None,
+            &CircuitConfigs::default(),
+        )
+    }
+
+    /// Builds a new `ZkTree` aggregating the given user proofs, whose nodes each verify `arity`
+    /// child proofs at once. A larger arity yields a shallower tree, hence less recursion layers,
+    /// at the cost of larger node circuits. The leaves are padded with padding leaves up to the
+    /// next power of `arity`.
+    pub fn new_with_arity(
+        user_proofs: Vec<UserProof<C, F, D>>,
+        arity: usize,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+    ) -> Result<Self, ZkTreeError> {
+        Self::build(
+            user_proofs,
+            arity,
+            leaf_circuit_cache,
+            None,
+            &CircuitConfigs::default(),
+        )
+    }
+
+    /// Builds a new `ZkTree` aggregating the given user proofs, only accepting proofs of the user
+    /// circuits in `allow_list`. Every leaf proves that its user circuit digest is a member of the
+    /// allow-list, and the allow-list root is exposed by the root proof, see
+    /// [`ZkTree::verify_with_allow_list`].
+    pub fn new_with_allow_list(
+        user_proofs: Vec<UserProof<C, F, D>>,
+        allow_list: &CircuitDigestAllowList<F, H>,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+    ) -> Result<Self, ZkTreeError> {
+        Self::build(
+            user_proofs,
+            DEFAULT_ARITY,
+            leaf_circuit_cache,
+            Some(allow_list),
+            &CircuitConfigs::default(),
+        )
+    }
+
+    /// Builds a new binary `ZkTree` aggregating the valid user proofs among the given ones, instead
+    /// of failing if some of them are invalid. The invalid user proofs are dropped before proving
+    /// and reported, along with the indices of the aggregated ones, in the returned
+    /// [`FilteredZkTree`].
+    pub fn new_skipping_invalid(
+        user_proofs: Vec<UserProof<C, F, D>>,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+    ) -> Result<FilteredZkTree<C, F, H, D>, ZkTreeError> {
+        let rejected = invalid_user_proofs(&user_proofs);
+        let rejected_indices = rejected
+            .iter()
+            .filter_map(ZkTreeError::leaf_index)
+            .collect::<HashSet<_>>();
+        let (included_indices, user_proofs): (Vec<_>, Vec<_>) = user_proofs
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| !rejected_indices.contains(index))
+            .unzip();
+        if user_proofs.is_empty() && !rejected.is_empty() {
+            return Err(ZkTreeError::InvalidUserProofs(rejected));
+        }
+
+        let zktree = Self::build_from_valid_user_proofs(
+            user_proofs,
+            DEFAULT_ARITY,
+            leaf_circuit_cache,
+            None,
+            &CircuitConfigs::default(),
+        )?;
+        Ok(FilteredZkTree {
+            zktree,
+            included_indices,
+            rejected,
+        })
+    }
+
+    /// Builds a new `ZkTree` aggregating the given user proofs with all the construction options,
+    /// see [`ZkTreeBuilder`](crate::zktree_builder::ZkTreeBuilder).
+    pub(crate) fn build(
+        user_proofs: Vec<UserProof<C, F, D>>,
+        arity: usize,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+        allow_list: Option<&CircuitDigestAllowList<F, H>>,
+        circuit_configs: &CircuitConfigs,
+    ) -> Result<Self, ZkTreeError> {
+        check_arity(arity)?;
+        validate_user_proofs(&user_proofs)?;
+        let user_proofs = match &circuit_configs.common_leaf_shape {
+            Some(common_leaf_shape) => pad_user_proofs(user_proofs, common_leaf_shape)?,
+            None => user_proofs,
+        };
+        Self::build_from_valid_user_proofs(
+            user_proofs,
+            arity,
+            leaf_circuit_cache,
+            allow_list,
+            circuit_configs,
+        )
+    }
+
+    fn build_from_valid_user_proofs(
+        user_proofs: Vec<UserProof<C, F, D>>,
+        arity: usize,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+        allow_list: Option<&CircuitDigestAllowList<F, H>>,
+        circuit_configs: &CircuitConfigs,
+    ) -> Result<Self, ZkTreeError> {
+        check_arity(arity)?;
+        let last_user_proof = user_proofs.last().ok_or(ZkTreeError::NoUserProofs)?;
+        let num_leaves = num_leaves(user_proofs.len(), arity);
+
+        let leaf_proofs = generate_leaf_proofs(
+            &user_proofs,
+            last_user_proof,
+            num_leaves,
+            leaf_circuit_cache,
+            allow_list,
+            &circuit_configs.leaf,
+        )?;
+
+        let nodes = match &circuit_configs.cyclic_node {
+            Some(config) => {
+                let compiled_cyclic_node_circuit =
+                    CompiledCyclicNodeCircuit::new(&leaf_proofs[0], arity, config.clone())?;
+                let node_proofs =
+                    generate_cyclic_node_proofs(&leaf_proofs, &compiled_cyclic_node_circuit)?;
+                NodeLayer::Cyclic {
+                    node_proofs,
+                    compiled_cyclic_node_circuit,
+                }
+            }
+            None => {
+                let mut node_proofs = Vec::with_capacity((num_leaves - 1) / (arity - 1));
+                let (level_node_proofs, compiled_node_circuit) = generate_node_proofs(
+                    &leaf_proofs,
+                    0,
+                    arity,
+                    circuit_configs.level_config(num_leaves / arity),
+                )?;
+                node_proofs.extend(level_node_proofs);
+                let mut compiled_node_circuits = vec![compiled_node_circuit];
+
+                let mut level_start_index = 0;
+                while node_proofs.len() - level_start_index > 1 {
+                    let level_end_index = node_proofs.len();
+                    let children = &node_proofs[level_start_index..level_end_index];
+                    let (level_node_proofs, compiled_node_circuit) = generate_node_proofs(
+                        children,
+                        compiled_node_circuits.len(),
+                        arity,
+                        circuit_configs.level_config(children.len() / arity),
+                    )?;
+                    node_proofs.extend(level_node_proofs);
+                    compiled_node_circuits.push(compiled_node_circuit);
+                    level_start_index = level_end_index;
+                }
+                NodeLayer::Node {
+                    node_proofs,
+                    compiled_node_circuits,
+                }
+            }
+        };
+
+        let mut zktree = Self {
+            user_proofs,
+            leaf_proofs,
+            nodes,
+            shrink_proofs: vec![],
+            compiled_shrink_circuits: vec![],
+            circuit_configs: circuit_configs.clone(),
+            arity,
+            _phantom_data: PhantomData,
+        };
+        zktree.shrink()?;
+        Ok(zktree)
+    }
+
+    /// Merges two `ZkTree`s of the same height into a binary `ZkTree` whose root verifies both
+    /// roots, so that only one `NodeCircuit` is proven. The merged `ZkTree` aggregates the user
+    /// proofs of `left` followed by those of `right`, as if it had been built from all of them at
+    /// once. See [`ZkTree::merge_k`] for `ZkTree`s of higher arity.
+    pub fn merge(left: Self, right: Self) -> Result<Self, ZkTreeError> {
+        Self::merge_k(vec![left, right])
+    }
+
+    /// Merges `arity` `ZkTree`s of the same arity, height, circuit configs and circuits under a new
+    /// root, in order. The merged roots are proven again with the node `CircuitConfig` if it differs
+    /// from the root one, so that the merged `ZkTree` is the one that would be built from scratch.
+    /// `ZkTree`s built with a `CyclicNodeCircuit` keep their roots as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `zktrees`: The `ZkTree`s to merge, whose number must be their arity. Every `ZkTree` but
+    ///   the last one must have no padding leaf, so that the user proofs of the merged `ZkTree`
+    ///   are followed by its padding leaves.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the merged `ZkTree`, or a `ZkTreeError` if the `ZkTree`s cannot be
+    /// merged or the new root cannot be proven.
+    pub fn merge_k(zktrees: Vec<Self>) -> Result<Self, ZkTreeError> {
+        let first_zktree = zktrees.first().ok_or(ZkTreeError::NoZkTrees)?;
+        let arity = first_zktree.arity;
+        let num_leaves = first_zktree.leaf_proofs.len();
+        if zktrees.len() != arity {
+            return Err(ZkTreeError::InvalidNumberOfZkTrees {
+                num_zktrees: zktrees.len(),
+                arity,
+            });
+        }
+        for (index, zktree) in zktrees.iter().enumerate() {
+            // the merged ZkTree keeps proving nodes with the compiled circuits of the first one
+            if zktree.arity != arity
+                || zktree.leaf_proofs.len() != num_leaves
+                || zktree.circuit_configs != first_zktree.circuit_configs
+                || zktree.leaf_proofs[0].proof().circuit_data.common
+                    != first_zktree.leaf_proofs[0].proof().circuit_data.common
+                || !zktree.nodes.has_same_circuits(&first_zktree.nodes)
+            {
+                return Err(ZkTreeError::IncompatibleZkTree { index });
+            }
+            if index + 1 < zktrees.len() && zktree.user_proofs.len() != num_leaves {
+                return Err(ZkTreeError::PaddedZkTree { index });
+            }
+            if zktree.allow_list_root() != first_zktree.allow_list_root() {
+                return Err(ZkTreeError::AllowListRootMismatch {
+                    expected: first_zktree.allow_list_root().map(hash_elements),
+                    actual: zktree.allow_list_root().map(hash_elements),
+                });
             }
         }
 
-        Ok(Self {
+        let levels = first_zktree.node_levels();
+        let circuit_configs = first_zktree.circuit_configs.clone();
+        let mut user_proofs = Vec::with_capacity(num_leaves * arity);
+        let mut leaf_proofs = Vec::with_capacity(num_leaves * arity);
+        let mut zktrees_nodes = Vec::with_capacity(arity);
+        for zktree in zktrees {
+            user_proofs.extend(zktree.user_proofs);
+            leaf_proofs.extend(zktree.leaf_proofs);
+            zktrees_nodes.push(zktree.nodes);
+        }
+        let nodes = match circuit_configs.cyclic_node {
+            Some(_) => Self::merge_cyclic_nodes(zktrees_nodes, &levels)?,
+            None => Self::merge_nodes(
+                zktrees_nodes,
+                &leaf_proofs,
+                &levels,
+                &circuit_configs,
+                arity,
+            )?,
+        };
+
+        let mut zktree = Self {
             user_proofs,
             leaf_proofs,
-            node_proofs,
+            nodes,
+            shrink_proofs: vec![],
+            compiled_shrink_circuits: vec![],
+            circuit_configs,
+            arity,
             _phantom_data: PhantomData,
+        };
+        zktree.shrink()?;
+        Ok(zktree)
+    }
+
+    /// Merges the `NodeCircuit` node layers of `ZkTree`s under a new root, see
+    /// [`ZkTree::merge_k`]. The former roots are proven again with the node `CircuitConfig` if it
+    /// differs from the root one.
+    ///
+    /// # Arguments
+    ///
+    /// * `zktrees_nodes`: The node layers of the merged `ZkTree`s, in order.
+    /// * `leaf_proofs`: The leaf proofs of the merged `ZkTree`s, concatenated in order.
+    /// * `levels`: The range of each level of nodes of the merged `ZkTree`s.
+    /// * `circuit_configs`: The `CircuitConfig`s shared by the merged `ZkTree`s.
+    /// * `arity`: The number of children of every node.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the node layer of the merged `ZkTree`, or a `ZkTreeError` if the new
+    /// root cannot be proven.
+    fn merge_nodes(
+        zktrees_nodes: Vec<NodeLayer<C, F, H, D>>,
+        leaf_proofs: &[LeafProof<C, F, H, D>],
+        levels: &[Range<usize>],
+        circuit_configs: &CircuitConfigs,
+        arity: usize,
+    ) -> Result<NodeLayer<C, F, H, D>, ZkTreeError> {
+        let former_root_level = levels.len() - 1;
+        let num_leaves = leaf_proofs.len() / arity;
+        let mut zktrees_node_proofs = Vec::with_capacity(arity);
+        let mut compiled_node_circuits = Vec::new();
+        for (index, nodes) in zktrees_nodes.into_iter().enumerate() {
+            match nodes {
+                NodeLayer::Node {
+                    node_proofs,
+                    compiled_node_circuits: zktree_compiled_node_circuits,
+                } => {
+                    if index == 0 {
+                        compiled_node_circuits = zktree_compiled_node_circuits;
+                    }
+                    zktrees_node_proofs.push(node_proofs);
+                }
+                NodeLayer::Cyclic { .. } => return Err(ZkTreeError::IncompatibleZkTree { index }),
+            }
+        }
+
+        let former_roots = if circuit_configs.root != circuit_configs.node {
+            let (former_root, compiled_node_circuit) = prove_root_with_config(
+                &leaf_proofs[..num_leaves],
+                &zktrees_node_proofs[0],
+                levels,
+                arity,
+                &circuit_configs.node,
+            )?;
+            let mut former_roots = vec![former_root];
+            for (index, node_proofs) in zktrees_node_proofs.iter().enumerate().skip(1) {
+                former_roots.push(
+                    prove_root_with_compiled_circuit(
+                        &leaf_proofs[index * num_leaves..(index + 1) * num_leaves],
+                        node_proofs,
+                        levels,
+                        &compiled_node_circuit,
+                    )
+                    .map_err(|error| error.at_node(former_root_level, index))?,
+                );
+            }
+            compiled_node_circuits[former_root_level] = compiled_node_circuit;
+            for node_proofs in zktrees_node_proofs.iter_mut() {
+                node_proofs.pop();
+            }
+            former_roots
+        } else {
+            zktrees_node_proofs
+                .iter_mut()
+                .map(|node_proofs| node_proofs.pop().expect("Failed to retrieve former root"))
+                .collect()
+        };
+
+        let roots = former_roots.iter().collect::<Vec<_>>();
+        let compiled_root_circuit = compile_node_circuit::<C, F, H, NodeProof<C, F, H, D>, D>(
+            &roots,
+            &circuit_configs.root,
+        )?;
+        let root =
+            NodeProof::new_from_k_children_with_compiled_circuit(&roots, &compiled_root_circuit)
+                .map_err(|error| error.at_node(levels.len(), 0))?;
+        compiled_node_circuits.push(compiled_root_circuit);
+
+        Ok(NodeLayer::Node {
+            node_proofs: merge_levels(levels, zktrees_node_proofs, former_roots, root),
+            compiled_node_circuits,
+        })
+    }
+
+    /// Merges the `CyclicNodeCircuit` node layers of `ZkTree`s under a new root, see
+    /// [`ZkTree::merge_k`]. As the same circuit proves every node at any height, the former roots
+    /// are kept as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `zktrees_nodes`: The node layers of the merged `ZkTree`s, in order.
+    /// * `levels`: The range of each level of nodes of the merged `ZkTree`s.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the node layer of the merged `ZkTree`, or a `ZkTreeError` if the new
+    /// root cannot be proven.
+    fn merge_cyclic_nodes(
+        zktrees_nodes: Vec<NodeLayer<C, F, H, D>>,
+        levels: &[Range<usize>],
+    ) -> Result<NodeLayer<C, F, H, D>, ZkTreeError> {
+        let mut zktrees_node_proofs = Vec::with_capacity(zktrees_nodes.len());
+        let mut compiled_cyclic_node_circuits = Vec::with_capacity(zktrees_nodes.len());
+        for (index, nodes) in zktrees_nodes.into_iter().enumerate() {
+            match nodes {
+                NodeLayer::Cyclic {
+                    node_proofs,
+                    compiled_cyclic_node_circuit,
+                } => {
+                    zktrees_node_proofs.push(node_proofs);
+                    compiled_cyclic_node_circuits.push(compiled_cyclic_node_circuit);
+                }
+                NodeLayer::Node { .. } => return Err(ZkTreeError::IncompatibleZkTree { index }),
+            }
+        }
+        let compiled_cyclic_node_circuit = compiled_cyclic_node_circuits
+            .into_iter()
+            .next()
+            .ok_or(ZkTreeError::NoZkTrees)?;
+
+        let former_roots = zktrees_node_proofs
+            .iter_mut()
+            .map(|node_proofs| node_proofs.pop().expect("Failed to retrieve former root"))
+            .collect::<Vec<_>>();
+        let root = CyclicNodeProof::new_from_nodes_with_compiled_circuit(
+            &former_roots.iter().collect::<Vec<_>>(),
+            &compiled_cyclic_node_circuit,
+        )
+        .map_err(|error| error.at_node(levels.len(), 0))?;
+
+        Ok(NodeLayer::Cyclic {
+            node_proofs: merge_levels(levels, zktrees_node_proofs, former_roots, root),
+            compiled_cyclic_node_circuit,
         })
     }
+
+    /// Appends a user proof to the `ZkTree`. The user proof takes the place of the first padding
+    /// leaf, and only the nodes on the path from its leaf to the root are proven again. If the
+    /// `ZkTree` has no padding leaf left, its capacity is first multiplied by the arity, adding a
+    /// new root level above the current root and padding subtrees.
+    pub fn append(&mut self, user_proof: UserProof<C, F, D>) -> Result<(), ZkTreeError> {
+        self.append_with_leaf_circuit_cache(user_proof, &LeafCircuitCache::new())
+    }
+
+    /// Appends a user proof to the `ZkTree` as [`ZkTree::append`] does, retrieving the compiled
+    /// leaf circuit from `leaf_circuit_cache`, e.g. the cache the `ZkTree` was built with.
+    pub fn append_with_leaf_circuit_cache(
+        &mut self,
+        user_proof: UserProof<C, F, D>,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+    ) -> Result<(), ZkTreeError> {
+        self.append_with(user_proof, leaf_circuit_cache, None)
+    }
+
+    /// Appends a user proof to a `ZkTree` built with an allow-list of user circuits, see
+    /// [`ZkTree::new_with_allow_list`]. `allow_list` must be the allow-list the `ZkTree` was built
+    /// with.
+    pub fn append_with_allow_list(
+        &mut self,
+        user_proof: UserProof<C, F, D>,
+        allow_list: &CircuitDigestAllowList<F, H>,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+    ) -> Result<(), ZkTreeError> {
+        self.append_with(user_proof, leaf_circuit_cache, Some(allow_list))
+    }
+
+    fn append_with(
+        &mut self,
+        user_proof: UserProof<C, F, D>,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+        allow_list: Option<&CircuitDigestAllowList<F, H>>,
+    ) -> Result<(), ZkTreeError> {
+        let leaf_index = self.user_proofs.len();
+        user_proof
+            .validate()
+            .map_err(|error| error.at_leaf(leaf_index))?;
+        let user_proof = self
+            .pad_user_proof(user_proof)
+            .map_err(|error| error.at_leaf(leaf_index))?;
+        let compiled_leaf_circuit = leaf_circuit_cache.get_or_compile_with_config(
+            &user_proof,
+            allow_list,
+            &self.circuit_configs.leaf,
+        );
+        let leaf_proof = LeafProof::new_from_user_proof_with_compiled_circuit(
+            &user_proof,
+            allow_list,
+            &compiled_leaf_circuit,
+        )
+        .map_err(|error| error.at_leaf(leaf_index))?;
+        self.check_leaf_proof(leaf_index, &leaf_proof)?;
+
+        if self.user_proofs.len() == self.leaf_proofs.len() {
+            let padding_leaf_proof = LeafProof::new_padding_with_compiled_circuit(
+                &user_proof,
+                allow_list,
+                &compiled_leaf_circuit,
+            )?;
+            self.grow(padding_leaf_proof)?;
+        }
+
+        self.replace_leaf_proof(leaf_index, leaf_proof)?;
+        self.user_proofs.push(user_proof);
+        self.shrink()
+    }
+
+    /// Replaces the user proof at `index` with `user_proof`, e.g. a corrected proof resubmitted by
+    /// the same user. Only the leaf of the user proof and the nodes on the path from this leaf to
+    /// the root are proven again, and the resulting `ZkTree` is the one `ZkTree::new` would build
+    /// from the updated user proofs.
+    pub fn update_leaf(
+        &mut self,
+        index: usize,
+        user_proof: UserProof<C, F, D>,
+    ) -> Result<(), ZkTreeError> {
+        self.update_leaf_with_leaf_circuit_cache(index, user_proof, &LeafCircuitCache::new())
+    }
+
+    /// Replaces the user proof at `index` as [`ZkTree::update_leaf`] does, retrieving the compiled
+    /// leaf circuit from `leaf_circuit_cache`, e.g. the cache the `ZkTree` was built with.
+    pub fn update_leaf_with_leaf_circuit_cache(
+        &mut self,
+        index: usize,
+        user_proof: UserProof<C, F, D>,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+    ) -> Result<(), ZkTreeError> {
+        self.update_leaf_with(index, user_proof, leaf_circuit_cache, None)
+    }
+
+    /// Replaces the user proof at `index` in a `ZkTree` built with an allow-list of user circuits,
+    /// see [`ZkTree::new_with_allow_list`]. `allow_list` must be the allow-list the `ZkTree` was
+    /// built with.
+    pub fn update_leaf_with_allow_list(
+        &mut self,
+        index: usize,
+        user_proof: UserProof<C, F, D>,
+        allow_list: &CircuitDigestAllowList<F, H>,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+    ) -> Result<(), ZkTreeError> {
+        self.update_leaf_with(index, user_proof, leaf_circuit_cache, Some(allow_list))
+    }
+
+    fn update_leaf_with(
+        &mut self,
+        index: usize,
+        user_proof: UserProof<C, F, D>,
+        leaf_circuit_cache: &LeafCircuitCache<C, F, D>,
+        allow_list: Option<&CircuitDigestAllowList<F, H>>,
+    ) -> Result<(), ZkTreeError> {
+        if index >= self.user_proofs.len() {
+            return Err(ZkTreeError::InvalidUserProofIndex {
+                index,
+                num_user_proofs: self.user_proofs.len(),
+            });
+        }
+
+        user_proof
+            .validate()
+            .map_err(|error| error.at_leaf(index))?;
+        let user_proof = self
+            .pad_user_proof(user_proof)
+            .map_err(|error| error.at_leaf(index))?;
+        let compiled_leaf_circuit = leaf_circuit_cache.get_or_compile_with_config(
+            &user_proof,
+            allow_list,
+            &self.circuit_configs.leaf,
+        );
+        let leaf_proof = LeafProof::new_from_user_proof_with_compiled_circuit(
+            &user_proof,
+            allow_list,
+            &compiled_leaf_circuit,
+        )
+        .map_err(|error| error.at_leaf(index))?;
+        self.check_leaf_proof(index, &leaf_proof)?;
+
+        self.replace_leaf_proof(index, leaf_proof)?;
+        self.user_proofs[index] = user_proof;
+        self.shrink()
+    }
+
+    /// Brings `user_proof` to the common leaf shape of the `ZkTree`, if it was built with one.
+    fn pad_user_proof(
+        &self,
+        user_proof: UserProof<C, F, D>,
+    ) -> Result<UserProof<C, F, D>, ZkTreeError> {
+        match &self.circuit_configs.common_leaf_shape {
+            Some(common_leaf_shape) => user_proof.pad(common_leaf_shape),
+            None => Ok(user_proof),
+        }
+    }
+
+    /// Checks that `leaf_proof` can take the place of the leaf at `leaf_index`, i.e. that it was
+    /// generated by a leaf circuit with the same `CommonCircuitData` and allow-list as the leaves.
+    fn check_leaf_proof(
+        &self,
+        leaf_index: usize,
+        leaf_proof: &LeafProof<C, F, H, D>,
+    ) -> Result<(), ZkTreeError> {
+        if leaf_proof.proof().circuit_data.common != self.leaf_proofs[0].proof().circuit_data.common
+        {
+            return Err(ZkTreeError::CommonDataMismatch {
+                position: leaf_index,
+            });
+        }
+        let allow_list_root =
+            allow_list_root_from_public_inputs(&leaf_proof.proof().proof_with_pis.public_inputs);
+        if allow_list_root != self.allow_list_root() {
+            return Err(ZkTreeError::AllowListRootMismatch {
+                expected: self.allow_list_root().map(hash_elements),
+                actual: allow_list_root.map(hash_elements),
+            });
+        }
+        Ok(())
+    }
+
+    /// Multiplies the capacity of the `ZkTree` by its arity. The current tree becomes the leftmost
+    /// subtree of a new root, whose other children are subtrees of padding leaves. Padding subtrees
+    /// are proven once per level and shared, so that growing only requires a proof per level. The
+    /// former root is proven again with the node `CircuitConfig` if it differs from the root one,
+    /// so that the `ZkTree` is the one that would be built from scratch.
+    fn grow(&mut self, padding_leaf_proof: LeafProof<C, F, H, D>) -> Result<(), ZkTreeError> {
+        let arity = self.arity;
+        let levels = self.node_levels();
+        let former_root_level = levels.len() - 1;
+
+        match &mut self.nodes {
+            NodeLayer::Node {
+                node_proofs,
+                compiled_node_circuits,
+            } => {
+                let former_root = if self.circuit_configs.root != self.circuit_configs.node {
+                    Some(prove_root_with_config(
+                        &self.leaf_proofs,
+                        node_proofs,
+                        &levels,
+                        arity,
+                        &self.circuit_configs.node,
+                    )?)
+                } else {
+                    None
+                };
+                let compiled_node_circuit = |level: usize| match &former_root {
+                    Some((_, compiled_node_circuit)) if level == former_root_level => {
+                        compiled_node_circuit
+                    }
+                    _ => &compiled_node_circuits[level],
+                };
+
+                let mut padding_node_proofs: Vec<NodeProof<C, F, H, D>> =
+                    Vec::with_capacity(levels.len());
+                padding_node_proofs.push(NodeProof::new_from_k_children_with_compiled_circuit(
+                    &vec![&padding_leaf_proof; arity],
+                    compiled_node_circuit(0),
+                )?);
+                for level in 1..levels.len() {
+                    let padding_node_proof = NodeProof::new_from_k_children_with_compiled_circuit(
+                        &vec![&padding_node_proofs[level - 1]; arity],
+                        compiled_node_circuit(level),
+                    )?;
+                    padding_node_proofs.push(padding_node_proof);
+                }
+
+                let root_children = std::iter::once(match &former_root {
+                    Some((former_root, _)) => former_root,
+                    None => node_proofs.last().expect("Failed to retrieve root"),
+                })
+                .chain(std::iter::repeat(&padding_node_proofs[former_root_level]))
+                .take(arity)
+                .collect::<Vec<_>>();
+                let compiled_root_circuit =
+                    compile_node_circuit::<C, F, H, NodeProof<C, F, H, D>, D>(
+                        &root_children,
+                        &self.circuit_configs.root,
+                    )?;
+                let root = NodeProof::new_from_k_children_with_compiled_circuit(
+                    &root_children,
+                    &compiled_root_circuit,
+                )
+                .map_err(|error| error.at_node(levels.len(), 0))?;
+
+                if let Some((former_root, compiled_node_circuit)) = former_root {
+                    node_proofs[levels[former_root_level].start] = former_root;
+                    compiled_node_circuits[former_root_level] = compiled_node_circuit;
+                }
+                *node_proofs = grow_levels(
+                    &levels,
+                    std::mem::take(node_proofs),
+                    padding_node_proofs,
+                    root,
+                    arity,
+                );
+                compiled_node_circuits.push(compiled_root_circuit);
+                // the shrink circuits were compiled for the former root circuit
+                self.compiled_shrink_circuits.clear();
+            }
+            NodeLayer::Cyclic {
+                node_proofs,
+                compiled_cyclic_node_circuit,
+            } => {
+                let mut padding_node_proofs = Vec::with_capacity(levels.len());
+                padding_node_proofs.push(CyclicNodeProof::new_from_leaves_with_compiled_circuit(
+                    &vec![&padding_leaf_proof; arity],
+                    compiled_cyclic_node_circuit,
+                )?);
+                for level in 1..levels.len() {
+                    let padding_node_proof = CyclicNodeProof::new_from_nodes_with_compiled_circuit(
+                        &vec![&padding_node_proofs[level - 1]; arity],
+                        compiled_cyclic_node_circuit,
+                    )?;
+                    padding_node_proofs.push(padding_node_proof);
+                }
+
+                let root_children =
+                    std::iter::once(node_proofs.last().expect("Failed to retrieve root"))
+                        .chain(std::iter::repeat(&padding_node_proofs[former_root_level]))
+                        .take(arity)
+                        .collect::<Vec<_>>();
+                let root = CyclicNodeProof::new_from_nodes_with_compiled_circuit(
+                    &root_children,
+                    compiled_cyclic_node_circuit,
+                )
+                .map_err(|error| error.at_node(levels.len(), 0))?;
+
+                // the root circuit is unchanged, and so are the shrink circuits
+                *node_proofs = grow_levels(
+                    &levels,
+                    std::mem::take(node_proofs),
+                    padding_node_proofs,
+                    root,
+                    arity,
+                );
+            }
+        }
+
+        let num_padding_leaves = self.leaf_proofs.len() * (arity - 1);
+        self.leaf_proofs
+            .extend(std::iter::repeat(padding_leaf_proof).take(num_padding_leaves));
+        Ok(())
+    }
+
+    /// Wraps the root proof into the shrink layers of the `ZkTree`, if any, replacing the previous
+    /// shrink proofs. Every shrink layer is compiled for the circuit of the proof it wraps, once
+    /// per root circuit: the compiled shrink circuits are reused until the root circuit changes.
+    fn shrink(&mut self) -> Result<(), ZkTreeError> {
+        let mut shrink_proofs: Vec<ShrinkProof<C, F, H, D>> =
+            Vec::with_capacity(self.circuit_configs.shrink.len());
+        for (layer, config) in self.circuit_configs.shrink.iter().enumerate() {
+            if layer == self.compiled_shrink_circuits.len() {
+                let compiled_shrink_circuit = match shrink_proofs.last() {
+                    Some(shrink_proof) => compile_shrink_circuit(shrink_proof, config),
+                    None => compile_shrink_circuit(self.nodes.root(), config),
+                };
+                self.compiled_shrink_circuits.push(compiled_shrink_circuit);
+            }
+            let compiled_shrink_circuit = &self.compiled_shrink_circuits[layer];
+            let shrink_proof = match shrink_proofs.last() {
+                Some(shrink_proof) => ShrinkProof::new_from_proof_with_compiled_circuit(
+                    shrink_proof,
+                    compiled_shrink_circuit,
+                )?,
+                None => ShrinkProof::new_from_proof_with_compiled_circuit(
+                    self.nodes.root(),
+                    compiled_shrink_circuit,
+                )?,
+            };
+            shrink_proofs.push(shrink_proof);
+        }
+        self.shrink_proofs = shrink_proofs;
+        Ok(())
+    }
+
+    /// Replaces the leaf at `leaf_index` with `leaf_proof`, and proves again the nodes on the path
+    /// from the leaf to the root. The `ZkTree` is only updated once every node on the path has
+    /// been proven.
+    fn replace_leaf_proof(
+        &mut self,
+        leaf_index: usize,
+        leaf_proof: LeafProof<C, F, H, D>,
+    ) -> Result<(), ZkTreeError> {
+        let arity = self.arity;
+        let levels = self.node_levels();
+        match &mut self.nodes {
+            NodeLayer::Node {
+                node_proofs,
+                compiled_node_circuits,
+            } => {
+                let mut position = leaf_index;
+                let mut path = Vec::with_capacity(levels.len());
+                path.push(reprove_parent_node(
+                    &self.leaf_proofs,
+                    0,
+                    position,
+                    &leaf_proof,
+                    arity,
+                    &compiled_node_circuits[0],
+                )?);
+                for level in 1..levels.len() {
+                    position /= arity;
+                    let node_proof = reprove_parent_node(
+                        &node_proofs[levels[level - 1].clone()],
+                        level,
+                        position,
+                        &path[level - 1],
+                        arity,
+                        &compiled_node_circuits[level],
+                    )?;
+                    path.push(node_proof);
+                }
+                replace_path(node_proofs, &levels, leaf_index, path, arity);
+            }
+            NodeLayer::Cyclic {
+                node_proofs,
+                compiled_cyclic_node_circuit,
+            } => {
+                let mut position = leaf_index;
+                let mut path = Vec::with_capacity(levels.len());
+                path.push(
+                    CyclicNodeProof::new_from_leaves_with_compiled_circuit(
+                        &parent_node_children(&self.leaf_proofs, position, &leaf_proof, arity),
+                        compiled_cyclic_node_circuit,
+                    )
+                    .map_err(|error| error.at_node(0, position / arity))?,
+                );
+                for level in 1..levels.len() {
+                    position /= arity;
+                    let node_proof = CyclicNodeProof::new_from_nodes_with_compiled_circuit(
+                        &parent_node_children(
+                            &node_proofs[levels[level - 1].clone()],
+                            position,
+                            &path[level - 1],
+                            arity,
+                        ),
+                        compiled_cyclic_node_circuit,
+                    )
+                    .map_err(|error| error.at_node(level, position / arity))?;
+                    path.push(node_proof);
+                }
+                replace_path(node_proofs, &levels, leaf_index, path, arity);
+            }
+        }
+        self.leaf_proofs[leaf_index] = leaf_proof;
+        Ok(())
+    }
 }
 
 impl<C, F, H, const D: usize> ZkTree<C, F, H, D>
@@ -74,8 +1309,36 @@ where
     C: GenericConfig<D, F = F, Hasher = H>,
     H: AlgebraicHasher<F>,
 {
+    /// Returns the root proof of the `ZkTree`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `ZkTree` is built with a `CyclicNodeCircuit`, see
+    /// [`CircuitConfigs::cyclic_node`], whose root is returned by [`ZkTree::cyclic_root`].
     pub fn root(&self) -> &NodeProof<C, F, H, D> {
-        self.node_proofs.last().expect("Failed to retrieve root")
+        match &self.nodes {
+            NodeLayer::Node { node_proofs, .. } => {
+                node_proofs.last().expect("Failed to retrieve root")
+            }
+            NodeLayer::Cyclic { .. } => {
+                panic!("The root of a ZkTree built with a CyclicNodeCircuit is a CyclicNodeProof")
+            }
+        }
+    }
+
+    /// Returns the root proof of the `ZkTree` if it is built with a `CyclicNodeCircuit`, see
+    /// [`CircuitConfigs::cyclic_node`], or `None` otherwise.
+    pub fn cyclic_root(&self) -> Option<&CyclicNodeProof<C, F, H, D>> {
+        match &self.nodes {
+            NodeLayer::Node { .. } => None,
+            NodeLayer::Cyclic { node_proofs, .. } => node_proofs.last(),
+        }
+    }
+
+    /// Returns the root of the allow-list of user circuits the `ZkTree` was built with, or `None`
+    /// if it was built without an allow-list.
+    pub fn allow_list_root(&self) -> Option<HashOut<F>> {
+        allow_list_root_from_public_inputs(self.nodes.root().node_public_inputs())
     }
 
     pub fn get_user_proofs(&self) -> Vec<&UserProof<C, F, D>> {
@@ -86,8 +1349,129 @@ where
         self.leaf_proofs.iter().collect::<Vec<_>>()
     }
 
+    /// Returns the node proofs, levels concatenated from the one above the leaves to the root, or
+    /// no node proof if the `ZkTree` is built with a `CyclicNodeCircuit`, see
+    /// [`ZkTree::get_cyclic_node_proofs`].
     pub fn get_node_proofs(&self) -> Vec<&NodeProof<C, F, H, D>> {
-        self.node_proofs.iter().collect::<Vec<_>>()
+        match &self.nodes {
+            NodeLayer::Node { node_proofs, .. } => node_proofs.iter().collect::<Vec<_>>(),
+            NodeLayer::Cyclic { .. } => vec![],
+        }
+    }
+
+    /// Returns the cyclic node proofs, levels concatenated from the one above the leaves to the
+    /// root, if the `ZkTree` is built with a `CyclicNodeCircuit`, see
+    /// [`CircuitConfigs::cyclic_node`], or no node proof otherwise.
+    pub fn get_cyclic_node_proofs(&self) -> Vec<&CyclicNodeProof<C, F, H, D>> {
+        match &self.nodes {
+            NodeLayer::Node { .. } => vec![],
+            NodeLayer::Cyclic { node_proofs, .. } => node_proofs.iter().collect::<Vec<_>>(),
+        }
+    }
+
+    /// Returns the proofs of the shrink layers wrapping the root proof, in order.
+    pub fn get_shrink_proofs(&self) -> Vec<&ShrinkProof<C, F, H, D>> {
+        self.shrink_proofs.iter().collect::<Vec<_>>()
+    }
+
+    /// Returns the final proof of the `ZkTree`, i.e. the proof of the last shrink layer, or the
+    /// root proof if the `ZkTree` has no shrink layer. It exposes the same public inputs as the
+    /// root proof.
+    pub fn final_proof(&self) -> &dyn Proof<C, F, D> {
+        match self.shrink_proofs.last() {
+            Some(shrink_proof) => shrink_proof,
+            None => self.nodes.root(),
+        }
+    }
+
+    /// Returns the number of children of every node of the `ZkTree`.
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Returns the `CircuitConfig` of the leaf, node, root, cyclic node and shrink circuits of the
+    /// `ZkTree`.
+    pub fn circuit_configs(&self) -> &CircuitConfigs {
+        &self.circuit_configs
+    }
+
+    /// Generates an `InclusionProof` that the public inputs of the user proof at `index` are
+    /// committed in the root input hash.
+    pub fn inclusion_proof(&self, index: usize) -> Result<InclusionProof<F, H>, ZkTreeError> {
+        if index >= self.user_proofs.len() {
+            return Err(ZkTreeError::InvalidUserProofIndex {
+                index,
+                num_user_proofs: self.user_proofs.len(),
+            });
+        }
+
+        let arity = self.arity;
+        let mut siblings = siblings_input_hashes(&self.leaf_proofs, index, arity);
+        let mut position = index;
+        let levels = self.node_levels();
+        // the root has no siblings
+        for level in &levels[..levels.len() - 1] {
+            position /= arity;
+            siblings.extend(match &self.nodes {
+                NodeLayer::Node { node_proofs, .. } => {
+                    siblings_input_hashes(&node_proofs[level.clone()], position, arity)
+                }
+                NodeLayer::Cyclic { node_proofs, .. } => {
+                    siblings_input_hashes(&node_proofs[level.clone()], position, arity)
+                }
+            });
+        }
+
+        InclusionProof::new(index, arity, siblings)
+    }
+
+    /// Returns the range of each level of nodes in `node_proofs`, from the level above the leaves
+    /// to the root.
+    fn node_levels(&self) -> Vec<Range<usize>> {
+        let mut levels = Vec::new();
+        let mut level_start_index = 0;
+        let mut level_len = self.leaf_proofs.len() / self.arity;
+        while level_len > 0 {
+            levels.push(level_start_index..level_start_index + level_len);
+            level_start_index += level_len;
+            level_len /= self.arity;
+        }
+        levels
+    }
+
+    /// Exports a `RootVerifierBundle` for the final proof of the `ZkTree`, see
+    /// [`ZkTree::final_proof`], which can be verified on its own without any prover data or user
+    /// proof.
+    pub fn root_verifier_bundle(&self) -> RootVerifierBundle<F, C, D> {
+        let final_proof = self.final_proof();
+        RootVerifierBundle::new(
+            final_proof.proof().circuit_data.verifier_data(),
+            final_proof.proof().proof_with_pis.clone(),
+            final_proof.input_hash(),
+            final_proof.circuit_hash(),
+        )
+    }
+
+    /// Wraps the final proof of the `ZkTree` into a proof of the outer config `OC`, e.g.
+    /// `KeccakGoldilocksConfig` for external verifiers such as smart contracts, exposing the same
+    /// public inputs as the root proof.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The `CircuitConfig` of the `WrapperCircuit`, e.g.
+    ///   `CircuitConfig::standard_recursion_config()`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `WrappedProof`, or a `ZkTreeError` if the proof generation fails.
+    pub fn wrap_final_proof<OC>(
+        &self,
+        config: CircuitConfig,
+    ) -> Result<WrappedProof<F, OC, D>, ZkTreeError>
+    where
+        OC: GenericConfig<D, F = F>,
+    {
+        WrappedProof::new_from_proof(self.final_proof(), config)
     }
 }
 
@@ -97,19 +1481,115 @@ where
     C: GenericConfig<D, F = F, Hasher = H>,
     H: AlgebraicHasher<F>,
 {
-    pub fn verify(&self) -> Result<(), Error> {
-        let root = self.root();
-        let root_proof_with_pis = root.proof().proof_with_pis.clone();
-        root.proof().circuit_data.verify(root_proof_with_pis)?;
-        let input_tree_leaves = self
+    /// Verifies the `ZkTree` as [`ZkTree::verify_with_circuit_digests`] does, against the leaf and
+    /// node circuit digests of its own proofs. This only checks that the `ZkTree` is consistent,
+    /// not that it was built with the expected circuits, which verifiers that did not build it
+    /// should pin with [`ZkTree::verify_with_circuit_digests`].
+    pub fn verify(&self) -> Result<(), ZkTreeError> {
+        let node_proofs = self.nodes.node_proofs();
+        let node_verifier_circuit_digests = self
+            .node_levels()
+            .into_iter()
+            .map(|level| node_proofs[level.start].circuit_verifier_digest())
+            .collect::<Vec<_>>();
+        self.verify_with_circuit_digests(
+            self.leaf_proofs[0].circuit_verifier_digest(),
+            &node_verifier_circuit_digests,
+        )
+    }
+
+    /// Verifies the root proof and the shrink proofs of the `ZkTree`, and checks that the root
+    /// input hash commits to the user public inputs, and that the root circuit hash commits to the
+    /// user circuits and to the leaf and node circuits whose digests are supplied by the caller.
+    /// The root proof must also be a proof of the last of these node circuits. For a `ZkTree`
+    /// built with a `CyclicNodeCircuit`, see [`CircuitConfigs::cyclic_node`], the cyclic node
+    /// circuit proves every level, and the root constrains the digest of its own circuit at every
+    /// height.
+    ///
+    /// # Arguments
+    ///
+    /// * `leaf_verifier_circuit_digest`: The verifier circuit digest of the expected leaf circuit.
+    /// * `node_verifier_circuit_digests`: The verifier circuit digest of the expected node circuit
+    ///   of each level, from the level above the leaves to the root, i.e. the digest of the cyclic
+    ///   node circuit at every level for a `ZkTree` built with one.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that is `Ok(())` if the `ZkTree` is valid and built with the expected circuits,
+    /// or a `ZkTreeError` otherwise.
+    pub fn verify_with_circuit_digests(
+        &self,
+        leaf_verifier_circuit_digest: HashOut<F>,
+        node_verifier_circuit_digests: &[HashOut<F>],
+    ) -> Result<(), ZkTreeError> {
+        if node_verifier_circuit_digests.last()
+            != Some(&self.nodes.root().circuit_verifier_digest())
+        {
+            return Err(ZkTreeError::VerifierCircuitDigestMismatch);
+        }
+        self.nodes.verify_root()?;
+        let root_public_inputs = self.nodes.root().node_public_inputs();
+        // shrink layers must expose the public inputs of the root
+        for shrink_proof in &self.shrink_proofs {
+            let shrink_proof_with_pis = shrink_proof.proof().proof_with_pis.clone();
+            shrink_proof
+                .proof()
+                .circuit_data
+                .verify(shrink_proof_with_pis)?;
+            let shrink_public_inputs = &shrink_proof.proof().proof_with_pis.public_inputs;
+            if shrink_public_inputs != root_public_inputs {
+                return Err(ZkTreeError::ShrinkPublicInputsMismatch);
+            }
+        }
+        // padding leaves commit to an empty list of user public inputs
+        let leaf_input_hashes = self
             .user_proofs
             .iter()
-            .map(|user_proof| user_proof.user_public_inputs().concat())
+            .map(|user_proof| leaf_input_hash::<F, H>(&user_proof.user_public_inputs().concat()))
+            .chain(std::iter::repeat(padding_input_hash::<F, H>()))
+            .take(self.leaf_proofs.len())
             .collect::<Vec<_>>();
-        let input_hashes_merkle_tree = MerkleTree::<F, H>::new(input_tree_leaves, 0);
-        if input_hashes_merkle_tree.cap.0[0] != root.input_hash() {
-            return Err(anyhow!("Input hashes do not match"));
+        let root_input_hash = HashOut::from_partial(&root_public_inputs[..NUM_HASH_OUT_ELTS]);
+        let input_commitment = input_commitment_with_arity::<F, H>(&leaf_input_hashes, self.arity)?;
+        if input_commitment != root_input_hash {
+            return Err(ZkTreeError::InputHashMismatch {
+                expected: hash_elements(input_commitment),
+                actual: hash_elements(root_input_hash),
+            });
+        }
+        // the root circuit hash commits to the user circuits and to the leaf and node circuits
+        let user_circuit_hashes = self
+            .user_proofs
+            .iter()
+            .map(|user_proof| user_proof.circuit_hash())
+            .collect::<Vec<_>>();
+        let circuit_commitment = circuit_commitment_with_arity::<F, H>(
+            &user_circuit_hashes,
+            leaf_verifier_circuit_digest,
+            node_verifier_circuit_digests,
+            self.arity,
+        )?;
+        let root_circuit_hash =
+            HashOut::from_partial(&root_public_inputs[NUM_HASH_OUT_ELTS..2 * NUM_HASH_OUT_ELTS]);
+        if circuit_commitment != root_circuit_hash {
+            return Err(ZkTreeError::CircuitHashMismatch {
+                expected: hash_elements(circuit_commitment),
+                actual: hash_elements(root_circuit_hash),
+            });
         }
         Ok(())
     }
+
+    /// Verifies the `ZkTree` as [`ZkTree::verify`] does, and additionally checks that it was built
+    /// with the allow-list of user circuits whose root is `allow_list_root`.
+    pub fn verify_with_allow_list(&self, allow_list_root: HashOut<F>) -> Result<(), ZkTreeError> {
+        self.verify()?;
+        match self.allow_list_root() {
+            Some(root_allow_list_root) if root_allow_list_root == allow_list_root => Ok(()),
+            root_allow_list_root => Err(ZkTreeError::AllowListRootMismatch {
+                expected: Some(hash_elements(allow_list_root)),
+                actual: root_allow_list_root.map(hash_elements),
+            }),
+        }
+    }
 }
@@ -0,0 +1,239 @@
+use plonky2::{
+    field::extension::Extendable,
+    hash::hash_types::RichField,
+    plonk::{
+        circuit_data::CircuitConfig,
+        config::{AlgebraicHasher, GenericConfig},
+    },
+};
+use rayon::ThreadPool;
+
+use crate::{
+    components::{
+        allow_list::CircuitDigestAllowList, leaf_circuit_cache::LeafCircuitCache,
+        padded_user_circuit::CommonLeafShape, user_proof::UserProof,
+    },
+    error::ZkTreeError,
+    utils::install_in_thread_pool,
+    zktree::{ZkTree, DEFAULT_ARITY},
+};
+
+/// `CircuitConfigs` holds the `CircuitConfig` of each layer of a `ZkTree`. All the layers use the
+/// zero-knowledge recursion config by default, without any shrink layer nor common leaf shape.
+///
+/// # Fields
+///
+/// * `leaf`: The config of the leaf circuits, verifying the user proofs.
+/// * `node`: The config of the node circuits below the root.
+/// * `root`: The config of the root node circuit.
+/// * `shrink`: The configs of the `ShrinkCircuit`s successively wrapping the root proof into a
+///   smaller final proof, see
+///   [`high_rate_shrink_config`](crate::components::shrink_circuit::high_rate_shrink_config).
+/// * `common_leaf_shape`: The shape the user proofs are brought to before being aggregated, if
+///   any, so that user circuits of different degrees can be mixed in the same `ZkTree`.
+/// * `cyclic_node`: The config of the `CyclicNodeCircuit` proving every node at any height in
+///   place of the node and root circuits, if any, see
+///   [`CyclicNodeCircuit`](crate::components::cyclic_node_circuit::CyclicNodeCircuit).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CircuitConfigs {
+    pub leaf: CircuitConfig,
+    pub node: CircuitConfig,
+    pub root: CircuitConfig,
+    pub shrink: Vec<CircuitConfig>,
+    pub common_leaf_shape: Option<CommonLeafShape>,
+    pub cyclic_node: Option<CircuitConfig>,
+}
+
+impl CircuitConfigs {
+    /// Returns the config of the node circuits of a level of `num_nodes` nodes, i.e. the root
+    /// config for the root level and the node config otherwise.
+    pub(crate) fn level_config(&self, num_nodes: usize) -> &CircuitConfig {
+        if num_nodes == 1 {
+            &self.root
+        } else {
+            &self.node
+        }
+    }
+}
+
+impl Default for CircuitConfigs {
+    fn default() -> Self {
+        Self {
+            leaf: CircuitConfig::standard_recursion_zk_config(),
+            node: CircuitConfig::standard_recursion_zk_config(),
+            root: CircuitConfig::standard_recursion_zk_config(),
+            shrink: vec![],
+            common_leaf_shape: None,
+            cyclic_node: None,
+        }
+    }
+}
+
+/// `ZkTreeBuilder` builds a `ZkTree` with a custom arity, allow-list, leaf circuit cache, thread
+/// pool and `CircuitConfig` per layer. For instance, zero-knowledge is usually only needed at the
+/// leaves, which hide the user proofs, so that inner nodes can use the faster
+/// `CircuitConfig::standard_recursion_config()`.
+///
+/// # Type Parameters
+///
+/// * `'a`: Lifetime of the references to the allow-list, leaf circuit cache and thread pool.
+/// * `C`: The configuration of the circuits, satisfying `GenericConfig`.
+/// * `F`: The field type that must implement `RichField` and `Extendable<D>`.
+/// * `H`: The hasher type that implements `AlgebraicHasher<F>`, the hasher of `C`.
+/// * `D`: The dimension of the field extension, specified as a compile-time constant.
+///
+/// # Fields
+///
+/// * `arity`: The number of children of every node.
+/// * `circuit_configs`: The `CircuitConfig` of each layer.
+/// * `allow_list`: An optional allow-list of user circuits, see
+///   [`ZkTree::new_with_allow_list`].
+/// * `leaf_circuit_cache`: An optional cache of compiled leaf circuits, a new one being used
+///   otherwise.
+/// * `thread_pool`: An optional thread pool running all the proving, see
+///   [`ZkTree::new_in_thread_pool`].
+pub struct ZkTreeBuilder<'a, C, F, H, const D: usize>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H>,
+    H: AlgebraicHasher<F>,
+{
+    arity: usize,
+    circuit_configs: CircuitConfigs,
+    allow_list: Option<&'a CircuitDigestAllowList<F, H>>,
+    leaf_circuit_cache: Option<&'a LeafCircuitCache<C, F, D>>,
+    thread_pool: Option<&'a ThreadPool>,
+}
+
+impl<'a, C, F, H, const D: usize> ZkTreeBuilder<'a, C, F, H, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    /// Creates a new `ZkTreeBuilder` for a binary `ZkTree` with the default circuit configs, see
+    /// [`CircuitConfigs::default`].
+    pub fn new() -> Self {
+        Self {
+            arity: DEFAULT_ARITY,
+            circuit_configs: CircuitConfigs::default(),
+            allow_list: None,
+            leaf_circuit_cache: None,
+            thread_pool: None,
+        }
+    }
+
+    /// Sets the number of children of every node, see [`ZkTree::new_with_arity`].
+    pub fn arity(mut self, arity: usize) -> Self {
+        self.arity = arity;
+        self
+    }
+
+    /// Sets the `CircuitConfig` of each layer at once.
+    pub fn circuit_configs(mut self, circuit_configs: CircuitConfigs) -> Self {
+        self.circuit_configs = circuit_configs;
+        self
+    }
+
+    /// Sets the `CircuitConfig` of the leaf circuits.
+    pub fn leaf_config(mut self, config: CircuitConfig) -> Self {
+        self.circuit_configs.leaf = config;
+        self
+    }
+
+    /// Sets the `CircuitConfig` of the node circuits below the root.
+    pub fn node_config(mut self, config: CircuitConfig) -> Self {
+        self.circuit_configs.node = config;
+        self
+    }
+
+    /// Sets the `CircuitConfig` of the root node circuit.
+    pub fn root_config(mut self, config: CircuitConfig) -> Self {
+        self.circuit_configs.root = config;
+        self
+    }
+
+    /// Sets the `CircuitConfig`s of the shrink layers wrapping the root proof, in order. No shrink
+    /// layer is added if `configs` is empty.
+    pub fn shrink_configs(mut self, configs: Vec<CircuitConfig>) -> Self {
+        self.circuit_configs.shrink = configs;
+        self
+    }
+
+    /// Sets the shape every user proof is brought to before being aggregated, so that user
+    /// circuits of any degree up to `2^common_leaf_shape.max_user_degree_bits` can be mixed. The
+    /// user circuit hashes, and the allow-list if any, are then the digests of the
+    /// `PaddedUserCircuit`s, see [`UserProof::pad`].
+    pub fn common_leaf_shape(mut self, common_leaf_shape: CommonLeafShape) -> Self {
+        self.circuit_configs.common_leaf_shape = Some(common_leaf_shape);
+        self
+    }
+
+    /// Sets the `CircuitConfig` of the `CyclicNodeCircuit` proving every node of the `ZkTree` at
+    /// any height, in place of the node and root circuits. The verifier data of the cyclic node
+    /// circuit is then constrained in-circuit at every level down to the leaves. The config must
+    /// not be zero-knowledge, e.g. `CircuitConfig::standard_recursion_config()`.
+    pub fn cyclic_node_config(mut self, config: CircuitConfig) -> Self {
+        self.circuit_configs.cyclic_node = Some(config);
+        self
+    }
+
+    /// Sets the allow-list of user circuits the user proofs must belong to.
+    pub fn allow_list(mut self, allow_list: &'a CircuitDigestAllowList<F, H>) -> Self {
+        self.allow_list = Some(allow_list);
+        self
+    }
+
+    /// Sets the cache the compiled leaf circuits are retrieved from.
+    pub fn leaf_circuit_cache(mut self, leaf_circuit_cache: &'a LeafCircuitCache<C, F, D>) -> Self {
+        self.leaf_circuit_cache = Some(leaf_circuit_cache);
+        self
+    }
+
+    /// Sets the thread pool all the leaf and node proving runs on.
+    pub fn thread_pool(mut self, thread_pool: &'a ThreadPool) -> Self {
+        self.thread_pool = Some(thread_pool);
+        self
+    }
+
+    /// Builds a `ZkTree` aggregating the given user proofs with the configured options.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_proofs`: The user proofs to aggregate.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `ZkTree`, or a `ZkTreeError` if the user proofs cannot be
+    /// aggregated with the configured options.
+    pub fn build(
+        self,
+        user_proofs: Vec<UserProof<C, F, D>>,
+    ) -> Result<ZkTree<C, F, H, D>, ZkTreeError> {
+        let default_leaf_circuit_cache = LeafCircuitCache::new();
+        let leaf_circuit_cache = self
+            .leaf_circuit_cache
+            .unwrap_or(&default_leaf_circuit_cache);
+        let build = || {
+            ZkTree::build(
+                user_proofs,
+                self.arity,
+                leaf_circuit_cache,
+                self.allow_list,
+                &self.circuit_configs,
+            )
+        };
+        install_in_thread_pool(self.thread_pool, build)
+    }
+}
+
+impl<'a, C, F, H, const D: usize> Default for ZkTreeBuilder<'a, C, F, H, D>
+where
+    F: RichField + Extendable<D>,
+    C: GenericConfig<D, F = F, Hasher = H> + 'static,
+    H: AlgebraicHasher<F> + Send + Sync,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}